@@ -0,0 +1,51 @@
+//! Times the classic Lox benchmarks on both backends, and the scanner and
+//! the parser on large generated files. Run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate lox;
+
+use criterion::{BatchSize, Criterion};
+use lox::lox::benchmarks::{self, PROGRAMS};
+use lox::lox::parser::Parser;
+use lox::lox::scanner::Scanner;
+use lox::lox::{Backend, Lox};
+
+fn programs(c: &mut Criterion) {
+    for &(name, source) in PROGRAMS.iter() {
+        for &(backend, backend_name) in [(Backend::Tree, "tree"), (Backend::Vm, "vm")].iter() {
+            if !benchmarks::supports(backend, source) {
+                continue;
+            }
+            let new_lox = || {
+                let mut lox = Lox::new();
+                lox.set_backend(backend);
+                lox
+            };
+            c.bench_function(&format!("{}/{}", name, backend_name), |b| {
+                b.iter_batched(new_lox, |mut lox| lox.run_source(source).unwrap(), BatchSize::SmallInput)
+            });
+        }
+    }
+}
+
+fn front_end(c: &mut Criterion) {
+    let program = benchmarks::large_program(1000);
+    c.bench_function("scan_tokens/program", |b| {
+        b.iter(|| Scanner::new(&program).scan_tokens(|_, err| panic!("{}", err)).len())
+    });
+
+    let tokens = Scanner::new(&program).scan_tokens(|_, err| panic!("{}", err)).clone();
+    c.bench_function("parse_program/program", |b| {
+        b.iter(|| Parser::new(&tokens).parse_program::<(), _>(|_, err| panic!("{}", err)).unwrap().len())
+    });
+
+    let expression = benchmarks::large_expression(10_000);
+    let tokens = Scanner::new(&expression).scan_tokens(|_, err| panic!("{}", err)).clone();
+    c.bench_function("parse/expression", |b| {
+        b.iter(|| Parser::new(&tokens).parse::<(), _>(|_, err| panic!("{}", err)).is_some())
+    });
+}
+
+criterion_group!(benches, programs, front_end);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+//! Runs arbitrary text as a script, which may fail but must never panic.
+//! Scripts can't touch files or the standard streams, except by printing,
+//! and are stopped once they run too long or grow too big.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lox::Lox;
+use lox::lox::fuzzing;
+
+fuzz_target!(|source: &str| {
+    let mut lox = Lox::new();
+    lox.set_io(false);
+    lox.set_limits(fuzzing::limits());
+    let _ = lox.load(source);
+});
@@ -0,0 +1,19 @@
+//! Parses arbitrary text as a program, as an expression and with the
+//! bytecode compiler, none of which may panic.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lox::lox::compiler::Compiler;
+use lox::lox::expr::ExprRef;
+use lox::lox::parser::Parser;
+use lox::lox::scanner::Scanner;
+use lox::lox::stmt::StmtRef;
+
+fuzz_target!(|source: &str| {
+    let tokens = Scanner::new(source).scan_tokens(|_, _| {}).clone();
+
+    let _: Option<Vec<StmtRef<()>>> = Parser::new(&tokens).parse_program(|_, _| {});
+    let _: Option<ExprRef<()>> = Parser::new(&tokens).parse(|_, _| {});
+    Parser::new(&tokens).is_expression();
+    Compiler::new(&tokens).compile(|_, _| {});
+});
@@ -0,0 +1,13 @@
+//! Generates a program from the input and checks that printing it back as
+//! source changes neither its tree nor what it does.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lox::lox::fuzzing;
+
+fuzz_target!(|data: &[u8]| {
+    let program = fuzzing::generate(data);
+    if let Err(problem) = fuzzing::round_trip(&program) {
+        panic!("{}\nin the program\n{}", problem, program);
+    }
+});
@@ -0,0 +1,9 @@
+//! Scans arbitrary text, which must never panic.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lox::lox::scanner::Scanner;
+
+fuzz_target!(|source: &str| {
+    Scanner::new(source).scan_tokens(|_, _| {});
+});
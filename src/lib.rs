@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate lazy_static;
+extern crate stacker;
 
 pub mod lox;
 pub use lox::Lox;
@@ -8,6 +9,29 @@ pub use lox::Lox;
 #[cfg(test)]
 mod tests {
     use lox::ast_printer::AstPrinter;
+    use lox::dot_printer::DotPrinter;
+    use lox::constant_folder::*;
+    use lox::chunk::*;
+    use lox::vm::VM;
+    use lox::debug;
+    use lox::bytecode_file::{self, Prototype};
+    use lox::memory::*;
+    use lox::interner::intern;
+    use lox::scanner::Scanner;
+    use lox::compiler::Compiler;
+    use lox::testing;
+    use lox::benchmarks;
+    use lox::cycles;
+    use lox::fuzzing;
+    use lox::limits::{Limit, Limits};
+    use lox::parser::{Parser, MAX_NESTING};
+    use lox::value::*;
+    use lox::{Lox, LoadError, AstFormat, Backend};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
     use lox::expr::*;
     use lox::token::*;
 
@@ -25,4 +49,1128 @@ mod tests {
         let ast_printer = AstPrinter {};
         assert_eq!("(* (- 123) (group 45.67))", ast_printer.print(expression.as_ref()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dot_printer() {
+        let expression = Unary::new(
+            Token::new(TokenType::Bang, "!", TokenLiteral::None, 3),
+            Grouping::new(Literal::new(TokenLiteral::Bool(true)))
+        );
+
+        let dot_printer = DotPrinter::new(true);
+        let expected = concat!(
+            "digraph AST {\n",
+            "    node [shape=box, fontname=\"monospace\"];\n",
+            "    n0 [label=\"Unary\\n!\\nline 3\"];\n",
+            "    n1 [label=\"Grouping\"];\n",
+            "    n2 [label=\"Literal\\ntrue\"];\n",
+            "    n1 -> n2 [label=\"expression\"];\n",
+            "    n0 -> n1 [label=\"right\"];\n",
+            "}\n",
+        );
+        assert_eq!(expected, dot_printer.print(expression.as_ref()));
+    }
+
+    #[test]
+    fn constant_folder() {
+        // -(2 * 3) + 1
+        let expression = Binary::new(
+            Unary::new(
+                Token::new(TokenType::Minus, "-", TokenLiteral::None, 1),
+                Grouping::new(Binary::new(
+                    Literal::new(TokenLiteral::Number(2.0)),
+                    Token::new(TokenType::Star, "*", TokenLiteral::None, 1),
+                    Literal::new(TokenLiteral::Number(3.0))
+                ))
+            ),
+            Token::new(TokenType::Plus, "+", TokenLiteral::None, 1),
+            Literal::new(TokenLiteral::Number(1.0))
+        );
+
+        let folded = ConstantFolder::new().fold(expression.as_ref());
+        assert_eq!("-5", AstPrinter {}.print(folded.as_ref()));
+    }
+
+    #[test]
+    fn constant_folder_type_error() {
+        // 1 + -"str"
+        let expression: ExprRef<FoldRes<String>> = Binary::new(
+            Literal::new(TokenLiteral::Number(1.0)),
+            Token::new(TokenType::Plus, "+", TokenLiteral::None, 1),
+            Unary::new(
+                Token::new(TokenType::Minus, "-", TokenLiteral::None, 2),
+                Literal::new(TokenLiteral::Str(intern("str").into()))
+            )
+        );
+
+        // The failing operator stays in the tree, and the one using its
+        // result along with it.
+        let folder = ConstantFolder::new();
+        let folded = folder.fold(expression.as_ref());
+        assert_eq!("(+ 1 (- str))", AstPrinter {}.print(folded.as_ref()));
+        let diagnostics = folder.diagnostics();
+        assert_eq!(vec![(2, "Operand of '-' must be a number, got string \"str\".")],
+                   diagnostics.iter().map(|d| (d.0.line, d.1.as_str())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn constant_errors_at_run_time() {
+        // Operators that always fail only fail if they run.
+        let sources = [
+            "if (false) print -nil; print \"ok\";",
+            "fun cb(v) { return -nil; } print \"ok\";",
+            "var f = false; f and 1 + nil; print \"ok\";",
+        ];
+        for source in sources.iter() {
+            assert!(Lox::new().load(source).is_ok(), "{}", source);
+        }
+
+        let mut lox = Lox::new();
+        lox.load("fun cb(v) { return -nil; }").unwrap();
+        let cb = lox.get_global("cb").unwrap();
+        match lox.call(&cb, &[Value::Nil]) {
+            Result::Err(e) => assert_eq!("Operand of '-' must be a number, got nil.", e.message),
+            Result::Ok(value) => panic!("cb returned {}", value),
+        }
+    }
+
+    #[test]
+    fn chunk_lines() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil as u8, 1);
+        chunk.write(OpCode::Nil as u8, 1);
+        chunk.write(OpCode::Add as u8, 2);
+        chunk.write(OpCode::Return as u8, 4);
+        assert_eq!((1, 1, 2, 4), (chunk.get_line(0), chunk.get_line(1), chunk.get_line(2), chunk.get_line(3)));
+
+        chunk.truncate(1);
+        chunk.write(OpCode::Negate as u8, 3);
+        assert_eq!((1, 3), (chunk.get_line(0), chunk.get_line(1)));
+    }
+
+    #[test]
+    fn vm_runtime_error() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(TokenLiteral::Str(intern("a").into()));
+        chunk.write(OpCode::Constant as u8, 1);
+        chunk.write(constant as u8, 1);
+        chunk.write(OpCode::True as u8, 1);
+        chunk.write(OpCode::Add as u8, 2);
+        chunk.write(OpCode::Return as u8, 2);
+
+        let mut errors = Vec::new();
+        VM::new().interpret(&chunk, |line, err| errors.push((line, err.to_string())));
+        assert_eq!(vec![(2, String::from("Operands of '+' must be two numbers or two strings, got string \"a\" and bool true."))], errors);
+    }
+
+    #[test]
+    fn disassembler() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(TokenLiteral::Number(1.5));
+        chunk.write(OpCode::Constant as u8, 1);
+        chunk.write(constant as u8, 1);
+        chunk.write(OpCode::Negate as u8, 1);
+        chunk.write(OpCode::Return as u8, 2);
+
+        let expected = concat!(
+            "== test ==\n",
+            "0000    1 OP_CONSTANT         0 '1.5'\n",
+            "0002    | OP_NEGATE\n",
+            "0003    2 OP_RETURN\n",
+        );
+        assert_eq!(expected, debug::disassemble_chunk(&chunk, "test"));
+        assert_eq!("          [ 1.5 ][ nil ]", debug::stack(&[Value::Number(1.5), Value::Nil]));
+    }
+
+    #[test]
+    fn bytecode_file_round_trip() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(TokenLiteral::Str(intern("lox").into()));
+        chunk.write(OpCode::Constant as u8, 1);
+        chunk.write(constant as u8, 1);
+        chunk.write(OpCode::Return as u8, 2);
+
+        let bytes = bytecode_file::serialize(&[Prototype { name: String::from("<script>"), expression: true, chunk }]);
+        let prototypes = bytecode_file::deserialize(&bytes).unwrap();
+        assert_eq!(1, prototypes.len());
+        assert_eq!(
+            "== <script> ==\n0000    1 OP_CONSTANT         0 'lox'\n0002    2 OP_RETURN\n",
+            debug::disassemble_chunk(&prototypes[0].chunk, &prototypes[0].name)
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 9;
+        assert_eq!(
+            Some(String::from("Unsupported bytecode version 9, expected 2.")),
+            bytecode_file::deserialize(&wrong_version).err()
+        );
+
+        for len in 0..bytes.len() {
+            assert!(bytecode_file::deserialize(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn bytecode_file_rejects_unsafe_code() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil as u8, 1);
+        chunk.write(OpCode::Add as u8, 1);
+        chunk.write(OpCode::Return as u8, 1);
+
+        let bytes = bytecode_file::serialize(&[Prototype { name: String::from("<script>"), expression: true, chunk }]);
+        assert_eq!(
+            Some(String::from("Invalid code in '<script>': stack underflow at offset 1.")),
+            bytecode_file::deserialize(&bytes).err()
+        );
+
+        // Jumps have to stay in the code and meet the same stack depth.
+        let verify = |code: &[u8]| {
+            let mut chunk = Chunk::new();
+            for &byte in code {
+                chunk.write(byte, 1);
+            }
+            bytecode_file::deserialize(&bytecode_file::serialize(&[Prototype { name: String::from("<script>"), expression: false, chunk }])).err()
+        };
+        let (jump, jump_if_false, nil, pop, ret) = (OpCode::Jump as u8, OpCode::JumpIfFalse as u8, OpCode::Nil as u8, OpCode::Pop as u8, OpCode::Return as u8);
+        assert_eq!(None, verify(&[nil, jump_if_false, 0, 4, pop, jump, 0, 1, pop, nil, ret]));
+        assert_eq!(Some(String::from("Invalid code in '<script>': jump out of range at offset 0.")), verify(&[jump, 0, 9, nil, ret]));
+        assert_eq!(Some(String::from("Invalid code in '<script>': stack depth differs between paths to offset 5.")),
+                   verify(&[nil, jump_if_false, 0, 1, nil, ret]));
+        assert_eq!(Some(String::from("Invalid code in '<script>': local slot 0 out of range at offset 0.")),
+                   verify(&[OpCode::GetLocal as u8, 0, ret]));
+    }
+
+    #[test]
+    fn heap_collects_unreachable_objects() {
+        let mut heap = Heap::new(GcConfig::default());
+        let kept = heap.allocate(Obj::Str(intern("kept").into()));
+        heap.allocate(Obj::Str(intern("garbage").into()));
+        assert_eq!(2, heap.object_count());
+
+        let before = heap.bytes_allocated();
+        let freed = heap.collect(&[VmValue::Number(1.0), VmValue::Obj(kept)]);
+        assert_eq!(1, heap.object_count());
+        assert_eq!(before - freed, heap.bytes_allocated());
+        match *heap.get(kept) {
+            Obj::Str(ref s) => assert_eq!("kept", &**s),
+            _ => panic!("expected a string"),
+        }
+
+        // Freed slots are reused.
+        heap.allocate(Obj::Str(intern("new").into()));
+        assert_eq!(2, heap.object_count());
+    }
+
+    #[test]
+    fn vm_gc_stress() {
+        let mut chunk = Chunk::new();
+        for s in &["a", "b", "c"] {
+            let constant = chunk.add_constant(TokenLiteral::Str(intern(s).into()));
+            chunk.write(OpCode::Constant as u8, 1);
+            chunk.write(constant as u8, 1);
+        }
+        chunk.write(OpCode::Add as u8, 1);
+        chunk.write(OpCode::Add as u8, 1);
+        chunk.write(OpCode::Return as u8, 1);
+
+        let mut vm = VM::new();
+        vm.set_gc(GcConfig { stress: true, ..GcConfig::default() });
+        let mut errors = Vec::new();
+        vm.interpret(&chunk, |line, err| errors.push((line, err.to_string())));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn vm_heap_limit_counts_lists() {
+        let mut lox = Lox::new();
+        lox.set_backend(Backend::Vm);
+        lox.set_limits(Limits { max_heap_bytes: Some(100_000), ..Limits::default() });
+        match lox.run_source("var l = []; while (true) l.push([1, 2, 3]);") {
+            Result::Err(LoadError::Runtime(e)) => assert_eq!("Exceeded the limit of 100000 bytes of heap.", e.message),
+            _ => panic!("the heap limit didn't stop the VM"),
+        }
+    }
+
+    #[test]
+    fn interner() {
+        let a = intern("lox");
+        let b = intern(&String::from("lox"));
+        assert!(a == b);
+        assert!(a != intern("clox"));
+        assert!(intern("clox") < a);
+
+        let (a, b): (Rc<String>, Rc<String>) = (a.into(), b.into());
+        assert!(Rc::ptr_eq(&a, &b));
+    }
+
+    fn half(arguments: &[Value]) -> Result<Value, RuntimeError> {
+        match arguments[0] {
+            Value::Number(n) => Result::Ok(Value::Number(n / 2.0)),
+            ref value => Result::Err(RuntimeError::new(format!("Cannot halve a {}.", value.type_name()))),
+        }
+    }
+
+    #[test]
+    fn native_function_errors() {
+        let cases = [
+            ("half(4) + half(\n\"a\")", (2, "Cannot halve a string.")),
+            ("half(1,\n2)", (2, "Expected 1 arguments but got 2.")),
+            ("half(1)(2)", (1, "Can only call functions and classes.")),
+            ("halve(1)", (1, "Undefined variable 'halve'.")),
+        ];
+
+        for &(source, (line, message)) in cases.iter() {
+            let expected = vec![(line, String::from(message))];
+
+            let mut lox = Lox::new();
+            lox.define_native("half", 1, half);
+            let errors: Vec<_> = match lox.load(source) {
+                Result::Err(LoadError::Runtime(e)) => vec![(e.line.unwrap(), e.message)],
+                _ => vec![],
+            };
+            assert_eq!(expected, errors, "tree-walker: {}", source);
+
+            let mut scanner = Scanner::new(source);
+            let tokens = scanner.scan_tokens(|_, err| panic!("{}", err));
+            let chunk = Compiler::new(tokens).compile(|_, err| panic!("{}", err)).unwrap();
+            let mut vm = VM::new();
+            vm.define_global(intern("half"), Value::Native(Rc::new(NativeFunction { name: intern("half"), arity: 1, function: half })));
+            let mut errors = Vec::new();
+            vm.interpret(&chunk, |line, err| errors.push((line, err.to_string())));
+            assert_eq!(expected, errors, "vm: {}", source);
+        }
+    }
+
+    #[test]
+    fn embedding_calls_lox_functions() {
+        let mut lox = Lox::new();
+        lox.load("
+            fun describe(name, scores) {
+                var total = 0;
+                for (var i = 0; i < 3; i = i + 1) total = total + i;
+                if (total == 3) return scores;
+                return name;
+            }
+            fun fail() { var x; return 1 + x; }
+        ").unwrap();
+
+        let describe = lox.get_global("describe").unwrap();
+        let scores: HashMap<String, f64> = [(String::from("a"), 1.0), (String::from("b"), -0.0)].iter().cloned().collect();
+        let result = lox.call(&describe, &["lox".into(), scores.clone().into()]).unwrap();
+        assert_eq!("{\"a\": 1, \"b\": -0}", result.to_string());
+
+        let error = lox.call(&lox.get_global("fail").unwrap(), &[]).unwrap_err();
+        assert_eq!(RuntimeError::at("Operands of '+' must be two numbers or two strings, got number 1 and nil.", 8), error);
+        assert_eq!("Expected 2 arguments but got 0.", lox.call(&describe, &[]).unwrap_err().message);
+        assert!(lox.get_global("missing").is_none());
+
+        let list: Value = vec![Some(1.5), None].into();
+        assert_eq!(vec![Some(1.5), None], Vec::<Option<f64>>::try_from(&list).unwrap());
+        assert_eq!(scores, HashMap::<String, f64>::try_from(&Value::from(scores.clone())).unwrap());
+        assert_eq!("Expected a number but got string.", f64::try_from(&Value::from("1")).unwrap_err().message);
+        assert_eq!(Some(String::from("x")), Option::<String>::try_from(&Value::from("x")).unwrap());
+        assert!(bool::try_from(&Value::from(true)).unwrap());
+    }
+
+    struct Counter {
+        count: f64,
+        step: f64,
+    }
+
+    impl NativeObject for Counter {
+        fn class_name(&self) -> &str {
+            "Counter"
+        }
+
+        fn get(&self, name: &str) -> Option<Value> {
+            match name {
+                "count" => Some(self.count.into()),
+                "step" => Some(self.step.into()),
+                _ => None,
+            }
+        }
+
+        fn set(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
+            match name {
+                "step" => self.step = f64::try_from(&value)?,
+                _ => return Result::Err(RuntimeError::new(format!("Counter has no settable property '{}'.", name))),
+            }
+            Result::Ok(())
+        }
+
+        fn method_arity(&self, name: &str) -> Option<usize> {
+            match name {
+                "increment" => Some(0),
+                _ => None,
+            }
+        }
+
+        fn call_method(&mut self, _name: &str, _arguments: &[Value]) -> Result<Value, RuntimeError> {
+            self.count += self.step;
+            Result::Ok(self.count.into())
+        }
+    }
+
+    #[test]
+    fn embedding_native_objects() {
+        let mut lox = Lox::new();
+        lox.load("
+            fun bump(counter) {
+                counter.step = 2;
+                counter.increment();
+                return counter.increment();
+            }
+            fun broken(counter) { counter.count = 1; }
+            fun missing(counter) { return counter.reset; }
+        ").unwrap();
+
+        let counter = Value::object(Counter { count: 0.0, step: 1.0 });
+        let result = lox.call(&lox.get_global("bump").unwrap(), std::slice::from_ref(&counter)).unwrap();
+        assert_eq!("4", result.to_string());
+        assert_eq!("<Counter instance>", counter.to_string());
+
+        let error = lox.call(&lox.get_global("broken").unwrap(), std::slice::from_ref(&counter)).unwrap_err();
+        assert_eq!(RuntimeError::at("Counter has no settable property 'count'.", 7), error);
+        let error = lox.call(&lox.get_global("missing").unwrap(), &[counter]).unwrap_err();
+        assert_eq!(RuntimeError::at("Undefined property 'reset'.", 8), error);
+    }
+
+    #[test]
+    fn vm_properties_and_methods() {
+        let counter = Value::object(Counter { count: 0.0, step: 1.0 });
+        let cases = [
+            ("[1, 2].len()", Result::Ok("2")),
+            ("[4, 2].map(half)", Result::Ok("[2, 1]")),
+            ("\"lox\".upper() + {\"a\": 1}.keys()[0]", Result::Ok("LOXa")),
+            ("[1, 2][-1] = [3][0] = 4", Result::Ok("4")),
+            ("[counter.step = 3, counter.increment(), counter.count]", Result::Ok("[3, 3, 3]")),
+            ("[1].nope()", Result::Err((1, "Undefined property 'nope'."))),
+            ("counter.count = 1", Result::Err((1, "Counter has no settable property 'count'."))),
+            ("half.x", Result::Err((1, "Only instances have properties."))),
+        ];
+
+        for &(source, expected) in cases.iter() {
+            let tokens = Scanner::new(source).scan_tokens(|_, err| panic!("{}", err)).clone();
+            let chunk = Compiler::new(&tokens).compile(|_, err| panic!("{}", err)).unwrap();
+            let mut vm = VM::new();
+            vm.define_global(intern("half"), Value::Native(Rc::new(NativeFunction { name: intern("half"), arity: 1, function: half })));
+            vm.define_global(intern("counter"), counter.clone());
+            let mut errors = Vec::new();
+            let value = vm.interpret(&chunk, |line, err| errors.push((line, err.to_string())));
+            match expected {
+                Result::Ok(expected) => assert_eq!(expected, value.unwrap().to_string(), "{}", source),
+                Result::Err((line, message)) => assert_eq!(vec![(line, String::from(message))], errors, "{}", source),
+            }
+        }
+
+        let tokens = Scanner::new("1 + [1][0] = 2").scan_tokens(|_, err| panic!("{}", err)).clone();
+        let mut errors = Vec::new();
+        assert!(Compiler::new(&tokens).compile(|token, err| errors.push((token.lexeme.to_string(), err.to_string()))).is_none());
+        assert_eq!(vec![(String::from("="), String::from("Invalid assignment target."))], errors);
+    }
+
+    #[test]
+    fn string_methods() {
+        let mut lox = Lox::new();
+        lox.load("
+            var word = \"  héllo \".trim();
+            var parts = \"a,b,,c\".split(\",\");
+            var joined = \"-\".join(word.chars());
+            var found = word.substring(1, 3) + \" at \" + word.indexOf(\"l\").toString();
+            var parsed = \"-2.5\".toNumber();
+            var rejected = \"1e5\".toNumber();
+            var fixed = (2 / 3).toFixed(3);
+            fun slice(s, start, end) { return s.substring(start, end); }
+        ").unwrap();
+
+        let get = |name| lox.get_global(name).unwrap().to_string();
+        assert_eq!("héllo", get("word"));
+        assert_eq!(r#"["a", "b", "", "c"]"#, get("parts"));
+        assert_eq!("h-é-l-l-o", get("joined"));
+        assert_eq!("él at 2", get("found"));
+        assert_eq!("-2.5", get("parsed"));
+        assert_eq!("nil", get("rejected"));
+        assert_eq!("0.667", get("fixed"));
+
+        let slice = lox.get_global("slice").unwrap();
+        let error = lox.call(&slice, &["héllo".into(), 1.0.into(), 9.0.into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("Index 9 is out of range 0..5.", 9), error);
+        let error = lox.call(&slice, &[1.0.into(), 0.0.into(), 0.0.into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("Undefined property 'substring'.", 9), error);
+    }
+
+    #[test]
+    fn math_operators_and_natives() {
+        let mut lox = Lox::new();
+        let ast = lox.print_ast("-2 ** 2 ** x % 3", &AstFormat::SExpr, false).unwrap();
+        assert_eq!("(% (- (** 2 (** 2 x))) 3)", ast);
+
+        let mut scanner = Scanner::new("-2 ** 3 ** 2 % 7");
+        let tokens = scanner.scan_tokens(|_, err| panic!("{}", err));
+        let chunk = Compiler::new(tokens).compile(|_, err| panic!("{}", err)).unwrap();
+        match chunk.constants[..] {
+            [TokenLiteral::Number(n)] => assert_eq!(-1.0, n),
+            _ => panic!("not folded"),
+        }
+
+        lox.load("
+            var rounded = floor(2.7) + ceil(2.1) + round(-2.5) + abs(-1) + sqrt(16);
+            var extremes = min(1, 2) + max(1, 2) + pow(2, 3);
+            var angle = atan2(1, 1) * 4 == PI;
+            seedRandom(7);
+            var first = random();
+            seedRandom(7);
+            var same = first == random() and 0 <= first and first < 1;
+            fun bad() { return sqrt(nil); }
+        ").unwrap();
+
+        let get = |name| lox.get_global(name).unwrap().to_string();
+        assert_eq!("7", get("rounded"));
+        assert_eq!("11", get("extremes"));
+        assert_eq!("true", get("angle"));
+        assert_eq!("true", get("same"));
+
+        let error = lox.call(&lox.get_global("bad").unwrap(), &[]).unwrap_err();
+        assert_eq!(RuntimeError::at("Expected a number but got nil.", 9), error);
+
+        // Every interpreter draws from a generator of its own.
+        let mut other = Lox::new();
+        lox.load("seedRandom(7); random(); var second = random();").unwrap();
+        other.load("seedRandom(7); random();").unwrap();
+        lox.load("seedRandom(8);").unwrap();
+        other.load("var second = random();").unwrap();
+        assert_eq!(lox.get_global("second").unwrap().to_string(), other.get_global("second").unwrap().to_string());
+    }
+
+    #[test]
+    fn lists() {
+        let mut lox = Lox::new();
+        let ast = lox.print_ast("xs[-1] = [1, xs[0]]", &AstFormat::SExpr, false).unwrap();
+        assert_eq!("(= (index xs (- 1)) (list 1 (index xs 0)))", ast);
+
+        lox.load("
+            fun double(x) { return x * 2; }
+            fun odd(x) { return x % 2 == 1; }
+            fun add(a, b) { return a + b; }
+            fun descending(a, b) { return b - a; }
+            var xs = [3, 1, 2];
+            xs.push(4);
+            xs[-1] = xs[-1] + 1;
+            xs.insert(0, xs.pop());
+            var removed = xs.remove(-1);
+            var mapped = xs.map(double);
+            var odds = xs.filter(odd);
+            var sum = xs.reduce(add, 0);
+            var slice = xs.slice(-2, 10);
+            xs.sort(descending);
+            fun get(list, i) { return list
+                [i]; }
+        ").unwrap();
+
+        let get = |name| lox.get_global(name).unwrap().to_string();
+        assert_eq!("[5, 3, 1]", get("xs"));
+        assert_eq!("2", get("removed"));
+        assert_eq!("[10, 6, 2]", get("mapped"));
+        assert_eq!("[5, 3, 1]", get("odds"));
+        assert_eq!("9", get("sum"));
+        assert_eq!("[3, 1]", get("slice"));
+
+        let get = lox.get_global("get").unwrap();
+        let error = lox.call(&get, &[Value::list(vec![]), 0.0.into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("List index 0 is out of range for a list of length 0.", 17), error);
+        let error = lox.call(&get, &[Value::list(vec![1.0.into()]), 0.5.into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("List index 0.5 is not an integer.", 17), error);
+
+        let mut scanner = Scanner::new("[1, [2, 3]][-1]\n[-3]");
+        let tokens = scanner.scan_tokens(|_, err| panic!("{}", err));
+        let chunk = Compiler::new(tokens).compile(|_, err| panic!("{}", err)).unwrap();
+        let mut errors = Vec::new();
+        VM::new().interpret(&chunk, |line, err| errors.push((line, err.to_string())));
+        assert_eq!(vec![(2, String::from("List index -3 is out of range for a list of length 2."))], errors);
+    }
+
+    #[test]
+    fn maps() {
+        let mut lox = Lox::new();
+        let ast = lox.print_ast("{ var m = {\"a\": 1}; for (var k in m) print m[k]; }", &AstFormat::SExpr, false).unwrap();
+        assert_eq!("(block (var m (map (: a 1))) (for-in k m (print (index m k))))", ast);
+
+        lox.load("
+            var m = {\"b\": 2, \"a\": 1, 0: \"zero\"};
+            m[-0] = \"negative zero\";
+            m[true] = nil;
+            var deleted = m.delete(\"b\") and !m.delete(\"b\");
+            var found = m.has(\"a\") and !m.has(\"b\");
+            var keys = [];
+            for (var key in m) keys.push(key);
+            fun lookup(map, key) { return map[key]; }
+            fun store(map, key) { map[key] = 1; }
+        ").unwrap();
+
+        let get = |name| lox.get_global(name).unwrap().to_string();
+        assert_eq!(r#"{true: nil, 0: "negative zero", "a": 1}"#, get("m"));
+        assert_eq!("true", get("deleted"));
+        assert_eq!("true", get("found"));
+        assert_eq!(r#"[true, 0, "a"]"#, get("keys"));
+
+        let m = lox.get_global("m").unwrap();
+        let lookup = lox.get_global("lookup").unwrap();
+        let error = lox.call(&lookup, &[m.clone(), "b".into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("Undefined key \"b\".", 9), error);
+        let store = lox.get_global("store").unwrap();
+        let error = lox.call(&store, &[m.clone(), Value::list(vec![])]).unwrap_err();
+        assert_eq!(RuntimeError::at("A list can't be used as a map key.", 10), error);
+        let error = lox.call(&store, &[m, f64::NAN.into()]).unwrap_err();
+        assert_eq!(RuntimeError::at("NaN can't be used as a map key.", 10), error);
+    }
+
+    #[test]
+    fn file_io() {
+        let dir = std::env::temp_dir().join(format!("lox-io-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.txt");
+        let path = path.to_str().unwrap();
+
+        let mut lox = Lox::new();
+        lox.load("
+            fun report(dir, path) {
+                writeFile(path, \"one\");
+                appendFile(path, \",two\");
+                return [readFile(path).split(\",\"), exists(path), listDir(dir)];
+            }
+            fun read(path) { return readFile(path); }
+        ").unwrap();
+
+        let report = lox.get_global("report").unwrap();
+        let result = lox.call(&report, &[dir.to_str().unwrap().into(), path.into()]).unwrap();
+        assert_eq!(r#"[["one", "two"], true, ["report.txt"]]"#, result.to_string());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let read = lox.get_global("read").unwrap();
+        let error = lox.call(&read, &[path.into()]).unwrap_err();
+        assert!(error.message.starts_with(&format!("Can't read '{}': ", path)), "{}", error.message);
+        assert_eq!(Some(7), error.line);
+
+        lox.set_io(false);
+        lox.load("fun read(path) { return readFile(path); }").unwrap();
+        let read = lox.get_global("read").unwrap();
+        assert_eq!(RuntimeError::at("I/O is disabled.", 1), lox.call(&read, &[path.into()]).unwrap_err());
+    }
+
+    #[test]
+    fn modules() {
+        let dir = std::env::temp_dir().join(format!("lox-modules-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(dir.join(name), source).unwrap();
+        write("lib/stack.lox", "
+            export var items = [];
+            export fun push(x) { items.push(x); return size(); }
+            fun size() { return items.len(); }
+        ");
+        write("lib/first.lox", "import \"second.lox\" as second;");
+        write("lib/second.lox", "from \"first.lox\" import x;");
+
+        let mut lox = Lox::new();
+        lox.add_module_path(dir.join("lib").to_str().unwrap());
+        lox.load("
+            import \"stack.lox\" as stack;
+            from \"stack.lox\" import push, items;
+            push(1);
+            var size = stack.push(2);
+        ").unwrap();
+        assert_eq!("2", lox.get_global("size").unwrap().to_string());
+        assert_eq!("[1, 2]", lox.get_global("items").unwrap().to_string());
+
+        let errors = [
+            ("from \"stack.lox\" import size;", "Module 'stack.lox' doesn't export 'size'."),
+            ("import \"stack.lox\" as s; print s.size;", "Undefined property 'size'."),
+            ("import \"missing.lox\" as m;", "Can't find module 'missing.lox'."),
+            ("import \"first.lox\" as m;", "Import cycle: first.lox -> second.lox -> first.lox."),
+        ];
+        for &(source, message) in errors.iter() {
+            match lox.load(source) {
+                Result::Err(LoadError::Runtime(e)) => assert_eq!(message, e.message),
+                _ => panic!("no runtime error for {}", source),
+            }
+        }
+
+        // Without I/O, imports don't look at files, even ones run before.
+        lox.set_io(false);
+        match lox.load("import \"stack.lox\" as again;") {
+            Result::Err(LoadError::Runtime(e)) => assert_eq!("I/O is disabled.", e.message),
+            _ => panic!("imported without I/O"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut lox = Lox::new();
+        let ast = lox.print_ast("try { throw 1; } catch (e) { print e; } finally { print 2; }", &AstFormat::SExpr, false).unwrap();
+        assert_eq!("(try (block (throw 1)) (catch e (print e)) (finally (print 2)))", ast);
+
+        lox.load("
+            var log = [];
+            fun divide(a, b) {
+                if (b == 0) throw \"division by zero\";
+                return a / b;
+            }
+            fun attempt(f) {
+                try {
+                    return f();
+                } catch (e) {
+                    log.push(e);
+                    return nil;
+                } finally {
+                    log.push(\"finally\");
+                }
+            }
+            fun divideByZero() { return divide(1, 0); }
+            attempt(divideByZero);
+        ").unwrap();
+        assert_eq!(r#"["division by zero", "finally"]"#, lox.get_global("log").unwrap().to_string());
+
+        lox.load("
+            var caught;
+            try { nil.field; } catch (e) { caught = e; }
+            var message = caught.message;
+            var line = caught.line;
+        ").unwrap();
+        assert_eq!("<Error instance>", lox.get_global("caught").unwrap().to_string());
+        assert_eq!("Only instances have properties.", lox.get_global("message").unwrap().to_string());
+        assert_eq!("3", lox.get_global("line").unwrap().to_string());
+
+        match lox.load("
+            fun inner(x) { throw {\"code\": x}; }
+            fun outer() { [1].map(inner); }
+            outer();
+        ") {
+            Result::Err(LoadError::Runtime(e)) => {
+                assert_eq!("{\"code\": 1}", e.message);
+                assert_eq!("{\"code\": 1}\n[line 2] in inner()\n[line 3] in map() (native)\n[line 3] in outer()\n[line 4] in script", e.to_string());
+            },
+            _ => panic!("the error was not thrown"),
+        }
+    }
+
+    #[test]
+    fn stack_traces() {
+        let dir = std::env::temp_dir().join(format!("lox-trace-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shapes.lox"), "
+            export fun area(r) {
+                return PI * sqrt(r);
+            }
+            area(-1) + area(nil);
+        ").unwrap();
+
+        let mut lox = Lox::new();
+        lox.add_module_path(dir.to_str().unwrap());
+        let error = match lox.load("
+            fun draw() {
+                import \"shapes.lox\" as shapes;
+            }
+            draw();
+        ") {
+            Result::Err(LoadError::Runtime(e)) => e,
+            _ => panic!("the module did not fail"),
+        };
+
+        let frame = |function: Option<&str>, native, file: Option<&str>, line| Frame {
+            function: function.map(String::from),
+            native,
+            file: file.map(String::from),
+            line,
+        };
+        assert_eq!(vec![
+            frame(Some("sqrt"), true, None, 3),
+            frame(Some("area"), false, Some("shapes.lox"), 3),
+            frame(None, false, Some("shapes.lox"), 5),
+            frame(Some("draw"), false, None, 3),
+            frame(None, false, None, 5),
+        ], error.trace);
+        assert_eq!("Expected a number but got nil.\n\
+                    [line 3] in sqrt() (native)\n\
+                    [line 3] in area() (shapes.lox)\n\
+                    [line 5] in script (shapes.lox)\n\
+                    [line 3] in draw()\n\
+                    [line 5] in script", error.to_string());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn operator_errors() {
+        let long = "a string too long to show";
+        let cases: Vec<(&str, Value, Value, &str)> = vec![
+            ("a + b", 1.0.into(), true.into(), "Operands of '+' must be two numbers or two strings, got number 1 and bool true."),
+            ("a + b", true.into(), false.into(), "Operands of '+' must be two numbers or two strings, got bool true and bool false."),
+            ("a - b", "a".into(), "b".into(), "Operands of '-' must be numbers, got string \"a\" and string \"b\"."),
+            ("a * b", Value::list(vec![1.0.into()]), 2.0.into(), "Operands of '*' must be numbers, got list [1] and number 2."),
+            ("a ** b", 2.0.into(), long.into(), "Operands of '**' must be numbers, got number 2 and string."),
+            ("a % b", Value::Nil, 2.0.into(), "Operands of '%' must be numbers, got nil and number 2."),
+            ("a < b", 1.0.into(), "2".into(), "Operands of '<' must be two numbers or two strings, got number 1 and string \"2\"."),
+            ("a >= b", true.into(), false.into(), "Operands of '>=' must be two numbers or two strings, got bool true and bool false."),
+            ("a > b", Value::list(vec![Value::Nil; 100_000]), 1.0.into(), "Operands of '>' must be two numbers or two strings, got list and number 1."),
+            ("-a", "a".into(), Value::Nil, "Operand of '-' must be a number, got string \"a\"."),
+            // Operators on constants that fail aren't folded but fail when they run.
+            ("1 + nil", Value::Nil, Value::Nil, "Operands of '+' must be two numbers or two strings, got number 1 and nil."),
+        ];
+
+        for (source, a, b, message) in cases {
+            let mut lox = Lox::new();
+            lox.define_global("a", a.clone());
+            lox.define_global("b", b.clone());
+            match lox.load(source) {
+                Result::Err(LoadError::Runtime(e)) => assert_eq!(message, e.message, "tree-walker: {}", source),
+                _ => panic!("no runtime error for {}", source),
+            }
+
+            let tokens = Scanner::new(source).scan_tokens(|_, err| panic!("{}", err)).clone();
+            let chunk = Compiler::new(&tokens).compile(|_, err| panic!("{}", err)).unwrap();
+            let mut vm = VM::new();
+            vm.define_global(intern("a"), a);
+            vm.define_global(intern("b"), b);
+            let mut errors = Vec::new();
+            vm.interpret(&chunk, |_, err| errors.push(err.to_string()));
+            assert_eq!(vec![String::from(message)], errors, "vm: {}", source);
+        }
+    }
+
+    #[test]
+    fn equality() {
+        let cases = [
+            ("nil == nil", "true"),
+            ("nil != false", "true"),
+            ("1 == \"1\"", "false"),
+            ("\"a\" + \"b\" == \"ab\"", "true"),
+            ("[1] == [1]", "false"),
+            ("[xs == xs, xs != xs, {} == {}]", "[true, false, false]"),
+            ("clock == clock", "true"),
+        ];
+
+        for &(source, expected) in cases.iter() {
+            let mut lox = Lox::new();
+            lox.define_global("xs", Value::list(vec![]));
+            lox.load(&format!("var result = {};", source)).unwrap();
+            assert_eq!(expected, lox.get_global("result").unwrap().to_string(), "tree-walker: {}", source);
+
+            let tokens = Scanner::new(source).scan_tokens(|_, err| panic!("{}", err)).clone();
+            let chunk = Compiler::new(&tokens).compile(|_, err| panic!("{}", err)).unwrap();
+            let mut vm = VM::new();
+            vm.define_global(intern("xs"), lox.get_global("xs").unwrap());
+            vm.define_global(intern("clock"), lox.get_global("clock").unwrap());
+            let value = vm.interpret(&chunk, |_, err| panic!("{}", err)).unwrap();
+            assert_eq!(expected, value.to_string(), "vm: {}", source);
+        }
+    }
+
+    #[test]
+    fn testing_framework() {
+        let mut lox = Lox::new();
+        lox.load("
+            assert(true, \"unused\");
+            assertEqual([1, {\"a\": nil}], [1, {\"a\": nil}]);
+            fun fail() { var x; return -x; }
+            var caught = assertThrows(fail).message;
+        ").unwrap();
+        assert_eq!("Operand of '-' must be a number, got nil.", lox.get_global("caught").unwrap().to_string());
+
+        let failures = [
+            ("assert(1 > 2, \"math\");", "Assertion failed: math"),
+            ("assertEqual([1], [2]);", "Expected [2] but got [1]."),
+            ("assertEqual(1, \"1\");", "Expected \"1\" but got 1."),
+            ("fun ok() {} assertThrows(ok);", "Expected an error but none was thrown."),
+        ];
+        for &(source, message) in failures.iter() {
+            match lox.load(source) {
+                Result::Err(LoadError::Runtime(e)) => assert_eq!(message, e.message),
+                _ => panic!("no failure for {}", source),
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("lox-testing-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested").join("math_test.lox"), "
+            var calls = 0;
+            fun check(total) {
+                calls = calls + 1;
+                assertEqual(calls, total);
+            }
+            fun testFirst() { check(1); }
+            fun testSecond() { check(1); check(3); }
+            fun helper() {}
+        ").unwrap();
+        std::fs::write(dir.join("ignored.lox"), "fun testIgnored() {}").unwrap();
+
+        let files = testing::discover(&dir).unwrap();
+        assert_eq!(vec![dir.join("nested").join("math_test.lox")], files);
+        assert_eq!(std::io::ErrorKind::NotFound, testing::discover(&dir.join("missing")).unwrap_err().kind());
+        let results = testing::run_file(&files[0], "nested/math_test.lox", &Lox::new);
+        let outcomes: Vec<_> = results.iter()
+            .map(|result| (result.name.as_str(), result.failure.as_ref().map(|failure| (failure.message.as_str(), failure.line))))
+            .collect();
+        assert_eq!(vec![("testFirst", None), ("testSecond", Some(("Expected 3 but got 2.", Some(5))))], outcomes);
+
+        let junit = testing::junit(&results);
+        assert!(junit.contains("<testsuites tests=\"2\" failures=\"1\">"), "{}", junit);
+        assert!(junit.contains("<failure message=\"Expected 3 but got 2.\">nested/math_test.lox:5</failure>"), "{}", junit);
+        let json = testing::json(&results);
+        assert!(json.contains("\"name\": \"testSecond\", \"passed\": false"), "{}", json);
+        assert!(json.contains("\"message\": \"Expected 3 but got 2.\", \"line\": 5"), "{}", json);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fuzzing_round_trip() {
+        // Programs picked by pseudo-random bytes from a xorshift generator.
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        for length in 0..300 {
+            let data: Vec<u8> = (0..length).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            }).collect();
+            let program = fuzzing::generate(&data);
+            if let Err(problem) = fuzzing::round_trip(&program) {
+                panic!("{}\nin the program\n{}", problem, program);
+            }
+        }
+
+        let mut lox = Lox::new();
+        assert_eq!(
+            "fun f(a) {\n    return -(a + 1) * 2;\n}\n{\n    var i = 0;\n    while (i < 2) {\n        print f(i)[\"k\"];\n        i = i + 1;\n    }\n}",
+            lox.print_ast("fun f(a) { return -(a + 1) * 2; } for (var i = 0; i < 2; i = i + 1) print f(i)[\"k\"];", &AstFormat::Source, false).unwrap());
+
+        // Found while fuzzing: lists and maps containing themselves.
+        lox.load("var l = [1]; l.push(l); var m = {}; m[\"m\"] = m;").unwrap();
+        assert_eq!("[1, [...]]", lox.get_global("l").unwrap().to_string());
+        assert_eq!("{\"m\": {...}}", lox.get_global("m").unwrap().to_string());
+        lox.load("var k = [1]; k.push(k); assertEqual(l, k);").unwrap();
+
+        // Found while fuzzing: the compiler ignored anything after the expression.
+        let tokens = Scanner::new("1 + 2 3").scan_tokens(|_, _| {}).clone();
+        let mut errors = Vec::new();
+        assert!(Compiler::new(&tokens).compile(|tok, err| errors.push((tok.lexeme.to_string(), err.to_string()))).is_none());
+        assert_eq!(vec![(String::from("3"), String::from("Expect ';' after expression."))], errors);
+    }
+
+    #[test]
+    fn default_limits_fit_small_stacks() {
+        // Threads get 2MB of stack unless told otherwise, less than the
+        // default limits take, so the interpreter has to grow it.
+        let recursion = thread::spawn(|| {
+            match Lox::new().load("fun f(n) { { { return f(n + 1); } } } f(0);") {
+                Result::Err(LoadError::Runtime(e)) => (e.limit, e.message),
+                _ => panic!("the recursion didn't stop"),
+            }
+        }).join().expect("the interpreter overflowed its thread's stack");
+        assert_eq!((Some(Limit::CallDepth), String::from("Stack overflow.")), recursion);
+    }
+
+    #[test]
+    fn limits() {
+        let limited = |limits: Limits, source: &str| {
+            let mut lox = Lox::new();
+            lox.set_limits(limits);
+            match lox.load(source) {
+                Result::Err(LoadError::Runtime(e)) => (e.limit, e.message),
+                _ => panic!("no limit stopped {}", source),
+            }
+        };
+
+        let steps = Limits { max_steps: Some(1000), ..Limits::default() };
+        assert_eq!((Some(Limit::Steps), String::from("Exceeded the limit of 1000 steps.")), limited(steps, "while (true) {}"));
+
+        let depth = Limits { max_call_depth: 50, ..Limits::default() };
+        assert_eq!((Some(Limit::CallDepth), String::from("Stack overflow.")), limited(depth, "fun f(n) { return f(n + 1); } f(0);"));
+
+        let heap = Limits { max_heap_bytes: Some(100_000), ..Limits::default() };
+        assert_eq!(Some(Limit::Heap), limited(heap, "var l = []; while (true) l.push([1, 2, 3]);").0);
+
+        let string = Limits { max_string_length: Some(100), ..Limits::default() };
+        assert_eq!((Some(Limit::StringLength), String::from("Strings can't be longer than 100 bytes, got one of 128.")),
+                   limited(string, "var s = \"ab\"; while (true) s = s + s;"));
+
+        let timeout = Limits { timeout: Some(Duration::from_millis(50)), ..Limits::default() };
+        assert_eq!((Some(Limit::Timeout), String::from("Timed out after 50ms.")), limited(timeout, "while (true) {}"));
+
+        // Limits hold in handlers and finalizers too, so scripts can't catch them.
+        let source = "
+            var handled = false;
+            try { while (true) {} } catch (e) { handled = true; } finally { handled = true; }
+        ";
+        let mut lox = Lox::new();
+        lox.set_limits(steps);
+        assert!(lox.load(source).is_err());
+        assert_eq!("false", lox.get_global("handled").unwrap().to_string());
+
+        // Every run gets all of its steps again, and so does every call.
+        lox.load("fun count(n) { var i = 0; while (i < n) i = i + 1; return i; }").unwrap();
+        let count = lox.get_global("count").unwrap();
+        assert_eq!("100", lox.call(&count, &[100.0.into()]).unwrap().to_string());
+        assert_eq!("100", lox.call(&count, &[100.0.into()]).unwrap().to_string());
+        assert_eq!(Some(Limit::Steps), lox.call(&count, &[1000.0.into()]).unwrap_err().limit);
+
+        // Other threads can cancel a script.
+        let mut lox = Lox::new();
+        let cancel = lox.cancel_handle();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        match lox.load("while (true) {}") {
+            Result::Err(LoadError::Runtime(e)) => assert_eq!((Some(Limit::Cancelled), "Cancelled."), (e.limit, e.message.as_str())),
+            _ => panic!("the script was not cancelled"),
+        }
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let parse_errors = |source: &str| {
+            let tokens = Scanner::new(source).scan_tokens(|_, err| panic!("{}", err)).clone();
+            let mut errors = Vec::new();
+            Parser::new(&tokens).parse_program::<(), _>(|tok, err| errors.push((tok.lexeme.to_string(), err)));
+            let mut compile_errors = Vec::new();
+            Compiler::new(&tokens).compile(|tok, err| compile_errors.push((tok.lexeme.to_string(), err.to_string())));
+            (errors, compile_errors)
+        };
+
+        let parens = format!("{}1{};", "(".repeat(100_000), ")".repeat(100_000));
+        let (errors, compile_errors) = parse_errors(&parens);
+        assert_eq!(vec![(String::from("("), "Too much nesting.")], errors);
+        assert_eq!(vec![(String::from("("), String::from("Too much nesting."))], compile_errors);
+
+        let nots = format!("{}true;", "!".repeat(100_000));
+        assert_eq!(vec![(String::from("!"), "Too much nesting.")], parse_errors(&nots).0);
+        let blocks = format!("{}{}", "{".repeat(100_000), "}".repeat(100_000));
+        assert_eq!(vec![(String::from("{"), "Too much nesting.")], parse_errors(&blocks).0);
+        // Parsing resumes after the error, only to find the next statements nested too deeply too.
+        let ifs = format!("{}print 1;", "if (true) ".repeat(100_000));
+        assert_eq!((String::from("true"), "Too much nesting."), parse_errors(&ifs).0[0]);
+        let powers = format!("2{};", " ** 2".repeat(100_000));
+        assert_eq!(vec![(String::from("2"), "Too much nesting.")], parse_errors(&powers).0);
+
+        // Anything less deep parses, folds and runs.
+        let depth = MAX_NESTING - 10;
+        let mut lox = Lox::new();
+        lox.load(&format!("var a = {}1{};", "(".repeat(depth), ")".repeat(depth))).unwrap();
+        lox.load(&format!("var b = {}true;", "!".repeat(depth))).unwrap();
+        lox.load(&format!("var c = {}1{};", "[".repeat(depth), "]".repeat(depth))).unwrap();
+        assert_eq!("1", lox.get_global("a").unwrap().to_string());
+        assert_eq!("true", lox.get_global("b").unwrap().to_string());
+        assert!(matches!(lox.load(&parens), Result::Err(LoadError::Syntax)));
+
+        // The interpreter counts nesting across calls, where the parser can't.
+        let mut lox = Lox::new();
+        lox.set_limits(Limits { max_nesting: 200, ..Limits::default() });
+        lox.load("fun f(n) { if (n > 0) { return -(-(f(n - 1))); } return 0; }").unwrap();
+        let f = lox.get_global("f").unwrap();
+        assert_eq!("0", lox.call(&f, &[10.0.into()]).unwrap().to_string());
+        let error = lox.call(&f, &[1000.0.into()]).unwrap_err();
+        assert_eq!((Some(Limit::Nesting), "Stack overflow."), (error.limit, error.message.as_str()));
+    }
+
+    #[test]
+    fn benchmarks() {
+        for &(name, source) in benchmarks::PROGRAMS.iter() {
+            let timing = benchmarks::measure(name, source, Backend::Tree, 2, &Lox::new)
+                .unwrap_or_else(|e| panic!("{} failed: {}", name, e));
+            assert_eq!(2, timing.runs.len());
+            assert!(timing.min() <= timing.median());
+            assert_eq!(name == "expression" || name == "string_equality", benchmarks::supports(Backend::Vm, source));
+        }
+
+        let (_, expression) = benchmarks::PROGRAMS[benchmarks::PROGRAMS.len() - 1];
+        assert!(benchmarks::measure("expression", expression, Backend::Vm, 2, &Lox::new).is_ok());
+        assert!(benchmarks::supports(Backend::Vm, "var i = 0; while (i < 10) i = i + 1;"));
+
+        let program = benchmarks::large_program(50);
+        let tokens = Scanner::new(&program).scan_tokens(|_, err| panic!("{}", err)).clone();
+        assert_eq!(50, Parser::new(&tokens).parse_program::<(), _>(|_, err| panic!("{}", err)).unwrap().len());
+        let expression = benchmarks::large_expression(100);
+        let tokens = Scanner::new(&expression).scan_tokens(|_, err| panic!("{}", err)).clone();
+        assert!(Parser::new(&tokens).is_expression());
+    }
+
+    #[test]
+    fn profiling() {
+        let mut lox = Lox::new();
+        assert!(lox.profile().is_none());
+        lox.set_profiling(true);
+        lox.load("fun fib(n) {\n  if (n < 2) return n;\n  return fib(n - 1) + fib(n - 2);\n}\n\
+                  fun spin() {\n  var s = \"\";\n  for (var i = 0; i < 20000; i = i + 1) s = i.toString();\n}\n\
+                  fib(10);\nspin();").unwrap();
+        let profile = lox.profile().unwrap();
+
+        let function = |frame: &str| profile.functions.iter().find(|function| function.frame == frame)
+            .unwrap_or_else(|| panic!("{} wasn't profiled", frame));
+        assert_eq!(177, function("fib").calls);
+        assert_eq!(1, function("spin").calls);
+        assert_eq!(20000, function("toString (native)").calls);
+        // Recursive calls count once towards the inclusive time, and every
+        // call's own time towards the exclusive time.
+        assert!(function("fib").inclusive <= function("script").inclusive);
+        assert!(function("spin").exclusive < function("spin").inclusive);
+        assert!(function("script").exclusive < function("script").inclusive);
+
+        // Time in natives is sampled at the line calling them.
+        assert!(profile.lines.iter().any(|line| line.line == 7 && line.samples > 0));
+        assert!(profile.lines.iter().all(|line| line.file.is_none() && (2..=10).contains(&line.line)));
+        assert!(profile.folded().lines().all(|stack| stack.starts_with("script") && stack.rsplit(' ').next().unwrap().parse::<u64>().is_ok()));
+        assert!(profile.folded().contains("script;spin;toString (native) "));
+        assert!(profile.report().starts_with("     calls    inclusive    exclusive  function\n"));
+    }
+
+    thread_local! {
+        static PROBES_DROPPED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    struct Probe;
+
+    impl NativeObject for Probe {
+        fn class_name(&self) -> &str {
+            "Probe"
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            PROBES_DROPPED.with(|dropped| dropped.set(dropped.get() + 1));
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        let dropped = || PROBES_DROPPED.with(|dropped| dropped.get());
+        let mut lox = Lox::new();
+        lox.define_native("probe", 0, |_| Result::Ok(Value::object(Probe)));
+
+        // A closure stored in the scope it closes over, and a list in itself.
+        lox.load("fun make() { var held = probe(); fun get() { return held; } return get; }\n\
+                  var get = make();\n\
+                  var xs = [probe()];\n\
+                  xs.push(xs);").unwrap();
+        lox.load("get = nil; xs = nil;").unwrap();
+        assert_eq!(0, dropped());
+        assert!(cycles::collect() > 0);
+        assert_eq!(2, dropped());
+
+        // Reachable cycles stay.
+        lox.load("var kept = make(); var ys = [probe()]; ys.push(ys);").unwrap();
+        cycles::collect();
+        assert_eq!(2, dropped());
+        let kept = lox.get_global("kept").unwrap();
+        assert_eq!("<Probe instance>", lox.call(&kept, &[]).unwrap().to_string());
+
+        // Functions declared at the top level close over the globals holding
+        // them, and are freed with the interpreter.
+        drop(kept);
+        drop(lox);
+        assert_eq!(4, dropped());
+    }
+}
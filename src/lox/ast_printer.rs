@@ -1,14 +1,20 @@
 use lox::expr::*;
+use lox::stmt::*;
 use lox::token::TokenLiteral;
 
 pub struct AstPrinter;
 
 impl<'a> AstPrinter {
-    pub fn print(&self, expr: &Expr<'a, String>) -> String {
-        return expr.accept(self);
+    pub fn print(&self, expr: &dyn Expr<'a, String>) -> String {
+        expr.accept(self)
     }
 
-    pub fn parenthesize(&self, name: &'a str, expr: &[&Expr<'a, String>]) -> String {
+    /// Prints every statement of a program on its own line.
+    pub fn print_program(&self, program: &[StmtRef<'a, String>]) -> String {
+        program.iter().map(|stmt| stmt.accept(self)).collect::<Vec<_>>().join("\n")
+    }
+
+    pub fn parenthesize(&self, name: &str, expr: &[&dyn Expr<'a, String>]) -> String {
         let mut s = String::from("(") + name;
         
         for &e in expr {
@@ -18,27 +24,167 @@ impl<'a> AstPrinter {
 
         s + ")"
     }
+
+    fn parenthesize_parts(&self, name: &str, parts: &[String]) -> String {
+        let mut s = String::from("(") + name;
+
+        for part in parts {
+            s += " ";
+            s += part;
+        }
+
+        s + ")"
+    }
 }
 
 impl<'a> Visitor<'a, String> for AstPrinter {
+    fn visit_assign(&self, expr: &Assign<'a, String>) -> String {
+        self.parenthesize_parts("=", &[expr.name.lexeme.to_string(), expr.value.accept(self)])
+    }
+
     fn visit_binary(&self, expr: &Binary<'a, String>) -> String {
-        self.parenthesize(expr.operator.lexeme, &[expr.left.as_ref(), expr.right.as_ref()])
+        self.parenthesize(&expr.operator.lexeme, &[expr.left.as_ref(), expr.right.as_ref()])
+    }
+
+    fn visit_call(&self, expr: &Call<'a, String>) -> String {
+        let mut exprs = vec![expr.callee.as_ref()];
+        exprs.extend(expr.arguments.iter().map(|a| a.as_ref()));
+        self.parenthesize("call", &exprs)
+    }
+
+    fn visit_get(&self, expr: &Get<'a, String>) -> String {
+        self.parenthesize_parts(".", &[expr.object.accept(self), expr.name.lexeme.to_string()])
     }
 
     fn visit_grouping(&self, expr: &Grouping<'a, String>) -> String {
         self.parenthesize("group", &[expr.expression.as_ref()])
     }
 
+    fn visit_index(&self, expr: &Index<'a, String>) -> String {
+        self.parenthesize("index", &[expr.object.as_ref(), expr.index.as_ref()])
+    }
+
+    fn visit_list(&self, expr: &List<'a, String>) -> String {
+        let elements: Vec<&dyn Expr<'a, String>> = expr.elements.iter().map(|e| e.as_ref()).collect();
+        self.parenthesize("list", &elements)
+    }
+
     fn visit_literal(&self, expr: &Literal) -> String {
         match expr.value {
-            TokenLiteral::Str(ref s) => s.clone(),
+            TokenLiteral::Str(ref s) => s.to_string(),
             TokenLiteral::Number(n) => n.to_string(),
             TokenLiteral::Bool(n) => n.to_string(),
             TokenLiteral::None => String::from("nil"),
         }
     }
 
+    fn visit_logical(&self, expr: &Logical<'a, String>) -> String {
+        self.parenthesize(&expr.operator.lexeme, &[expr.left.as_ref(), expr.right.as_ref()])
+    }
+
+    fn visit_map(&self, expr: &Map<'a, String>) -> String {
+        let entries: Vec<String> = expr.keys.iter().zip(&expr.values)
+            .map(|(key, value)| self.parenthesize(":", &[key.as_ref(), value.as_ref()]))
+            .collect();
+        self.parenthesize_parts("map", &entries)
+    }
+
+    fn visit_set(&self, expr: &Set<'a, String>) -> String {
+        let target = self.parenthesize_parts(".", &[expr.object.accept(self), expr.name.lexeme.to_string()]);
+        self.parenthesize_parts("=", &[target, expr.value.accept(self)])
+    }
+
+    fn visit_set_index(&self, expr: &SetIndex<'a, String>) -> String {
+        let target = self.parenthesize("index", &[expr.object.as_ref(), expr.index.as_ref()]);
+        self.parenthesize_parts("=", &[target, expr.value.accept(self)])
+    }
+
     fn visit_unary(&self, expr: &Unary<'a, String>) -> String {
-        self.parenthesize(expr.operator.lexeme, &[expr.right.as_ref()])
+        self.parenthesize(&expr.operator.lexeme, &[expr.right.as_ref()])
+    }
+
+    fn visit_variable(&self, expr: &Variable) -> String {
+        expr.name.lexeme.to_string()
+    }
+}
+
+impl<'a> StmtVisitor<'a, String> for AstPrinter {
+    fn visit_block(&self, stmt: &Block<'a, String>) -> String {
+        let statements: Vec<String> = stmt.statements.iter().map(|s| s.accept(self)).collect();
+        self.parenthesize_parts("block", &statements)
+    }
+
+    fn visit_export(&self, stmt: &Export<'a, String>) -> String {
+        self.parenthesize_parts("export", &[stmt.declaration.accept(self)])
+    }
+
+    fn visit_expression(&self, stmt: &Expression<'a, String>) -> String {
+        self.parenthesize(";", &[stmt.expression.as_ref()])
+    }
+
+    fn visit_for_in(&self, stmt: &ForIn<'a, String>) -> String {
+        self.parenthesize_parts("for-in", &[stmt.name.lexeme.to_string(), stmt.iterable.accept(self), stmt.body.accept(self)])
+    }
+
+    fn visit_function(&self, stmt: &Function<'a, String>) -> String {
+        let params: Vec<&str> = stmt.params.iter().map(|p| &*p.lexeme).collect();
+        let mut parts = vec![stmt.name.lexeme.to_string(), format!("({})", params.join(" "))];
+        parts.extend(stmt.body.iter().map(|s| s.accept(self)));
+        self.parenthesize_parts("fun", &parts)
+    }
+
+    fn visit_if(&self, stmt: &If<'a, String>) -> String {
+        let mut parts = vec![stmt.condition.accept(self), stmt.then_branch.accept(self)];
+        if let Some(ref else_branch) = stmt.else_branch {
+            parts.push(else_branch.accept(self));
+        }
+        self.parenthesize_parts("if", &parts)
+    }
+
+    fn visit_import(&self, stmt: &Import<'a, String>) -> String {
+        let mut parts = vec![stmt.path.lexeme.to_string()];
+        match stmt.alias {
+            Some(ref alias) => parts.extend(vec![String::from("as"), alias.lexeme.to_string()]),
+            None => parts.extend(stmt.names.iter().map(|n| n.lexeme.to_string())),
+        }
+        self.parenthesize_parts("import", &parts)
     }
-}
\ No newline at end of file
+
+    fn visit_print(&self, stmt: &Print<'a, String>) -> String {
+        self.parenthesize("print", &[stmt.expression.as_ref()])
+    }
+
+    fn visit_return(&self, stmt: &Return<'a, String>) -> String {
+        let parts: Vec<String> = stmt.value.iter().map(|v| v.accept(self)).collect();
+        self.parenthesize_parts("return", &parts)
+    }
+
+    fn visit_throw(&self, stmt: &Throw<'a, String>) -> String {
+        self.parenthesize("throw", &[stmt.value.as_ref()])
+    }
+
+    fn visit_try(&self, stmt: &Try<'a, String>) -> String {
+        let body: Vec<String> = stmt.body.iter().map(|s| s.accept(self)).collect();
+        let mut parts = vec![self.parenthesize_parts("block", &body)];
+        if let Some((ref name, ref handler)) = stmt.handler {
+            let mut catch = vec![name.lexeme.to_string()];
+            catch.extend(handler.iter().map(|s| s.accept(self)));
+            parts.push(self.parenthesize_parts("catch", &catch));
+        }
+        if let Some(ref finalizer) = stmt.finalizer {
+            let finalizer: Vec<String> = finalizer.iter().map(|s| s.accept(self)).collect();
+            parts.push(self.parenthesize_parts("finally", &finalizer));
+        }
+        self.parenthesize_parts("try", &parts)
+    }
+
+    fn visit_var(&self, stmt: &Var<'a, String>) -> String {
+        let mut parts = vec![stmt.name.lexeme.to_string()];
+        parts.extend(stmt.initializer.iter().map(|i| i.accept(self)));
+        self.parenthesize_parts("var", &parts)
+    }
+
+    fn visit_while(&self, stmt: &While<'a, String>) -> String {
+        self.parenthesize_parts("while", &[stmt.condition.accept(self), stmt.body.accept(self)])
+    }
+}
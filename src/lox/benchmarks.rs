@@ -0,0 +1,98 @@
+//! The programs `lox bench` and `cargo bench` time, and how they time them.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use lox::{Backend, LoadError, Lox};
+use lox::compiler::Compiler;
+use lox::scanner::Scanner;
+
+/// The classic Lox benchmarks, with objects as maps until Lox has classes,
+/// and a long expression of lists and maps. Each checks its own result.
+pub const PROGRAMS: [(&str, &str); 7] = [
+    ("fib", include_str!("../../benches/lox/fib.lox")),
+    ("binary_trees", include_str!("../../benches/lox/binary_trees.lox")),
+    ("method_call", include_str!("../../benches/lox/method_call.lox")),
+    ("string_equality", include_str!("../../benches/lox/string_equality.lox")),
+    ("zoo", include_str!("../../benches/lox/zoo.lox")),
+    ("instantiation", include_str!("../../benches/lox/instantiation.lox")),
+    ("expression", include_str!("../../benches/lox/expression.lox")),
+];
+
+/// How long each run of a benchmark took.
+pub struct Timing {
+    pub name: String,
+    pub backend: Backend,
+    pub runs: Vec<Duration>,
+}
+
+impl Timing {
+    pub fn min(&self) -> Duration {
+        self.runs.iter().min().cloned().unwrap_or_default()
+    }
+
+    pub fn median(&self) -> Duration {
+        let mut runs = self.runs.clone();
+        runs.sort();
+        runs.get(runs.len() / 2).cloned().unwrap_or_default()
+    }
+}
+
+/// Whether `backend` can run `source`: the VM doesn't compile every
+/// statement yet, functions among them.
+pub fn supports(backend: Backend, source: &str) -> bool {
+    if backend == Backend::Tree {
+        return true;
+    }
+
+    let mut compiles = true;
+    let tokens = Scanner::new(source).scan_tokens(|_, _| compiles = false).clone();
+    let chunk = Compiler::new(&tokens).compile(|_, _| compiles = false);
+    compiles && chunk.is_some()
+}
+
+/// Runs `source` `runs` times on `backend`, each time on a fresh interpreter
+/// made by `new_lox`, and times everything but making the interpreter.
+pub fn measure<F: Fn() -> Lox>(name: &str, source: &str, backend: Backend, runs: usize, new_lox: &F) -> Result<Timing, LoadError> {
+    let mut timing = Timing { name: String::from(name), backend, runs: Vec::new() };
+    for _ in 0..runs {
+        let mut lox = new_lox();
+        lox.set_backend(backend);
+
+        let start = Instant::now();
+        lox.run_source(source)?;
+        timing.runs.push(start.elapsed());
+    }
+    Result::Ok(timing)
+}
+
+/// A program of `functions` functions using most of the syntax, for timing
+/// the scanner and the parser on a large file.
+pub fn large_program(functions: usize) -> String {
+    let mut program = String::new();
+    for i in 0..functions {
+        writeln!(program, "// Function number {}.", i).unwrap();
+        writeln!(program, "fun function{}(a, b) {{", i).unwrap();
+        writeln!(program, "    var list = [a, b, \"string {}\", {}.5, true, nil];", i, i).unwrap();
+        writeln!(program, "    var map = {{\"key\": a * (b + {}) - -a / 2, \"other\": list[0]}};", i).unwrap();
+        writeln!(program, "    for (var i = 0; i < {}; i = i + 1) {{", i % 10).unwrap();
+        writeln!(program, "        if (a > b and !(a == b) or b <= {}) a = a + i ** 2 % 7;", i).unwrap();
+        writeln!(program, "        else {{ b = map[\"key\"]; }}").unwrap();
+        writeln!(program, "    }}").unwrap();
+        writeln!(program, "    try {{ throw {{\"code\": {}}}; }} catch (e) {{ print e; }}", i).unwrap();
+        writeln!(program, "    return function{}(a, b);", i.saturating_sub(1)).unwrap();
+        writeln!(program, "}}").unwrap();
+    }
+    program
+}
+
+/// A single expression of about `terms` terms, for timing the parser on a
+/// large expression.
+pub fn large_expression(terms: usize) -> String {
+    let terms: Vec<String> = (0..terms).map(|i| match i % 4 {
+        0 => format!("{}", i),
+        1 => format!("(a{} * {})", i, i),
+        2 => format!("-f(x, [{}, \"s\"])[0]", i),
+        _ => format!("{{\"k\": {}}}[\"k\"] ** 2", i),
+    }).collect();
+    terms.join(" + ")
+}
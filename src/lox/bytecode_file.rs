@@ -0,0 +1,328 @@
+//! Binary format of compiled Lox programs (`.loxc` files).
+//!
+//! All integers are little-endian:
+//!
+//! ```text
+//! file      := "LOXC" version:u16 count:u32 prototype*
+//! prototype := name:string expression:u8
+//!              constants:u32 constant*
+//!              code:u32 byte*
+//!              runs:u32 (line:u32 count:u32)*
+//! constant  := 0 number:f64 | 1 string | 2 bool:u8 | 3
+//! string    := length:u32 utf8-byte*
+//! ```
+//!
+//! The first prototype is the top-level script. `expression` is 1 if the
+//! script is a single expression, whose value is printed once it has run.
+
+use std::str;
+use lox::chunk::*;
+use lox::token::TokenLiteral;
+use lox::interner::intern;
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 2;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STR: u8 = 1;
+const TAG_BOOL: u8 = 2;
+const TAG_NIL: u8 = 3;
+
+/// A named chunk of compiled code.
+pub struct Prototype {
+    pub name: String,
+    /// Whether the code is a script that is a single expression.
+    pub expression: bool,
+    pub chunk: Chunk,
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn serialize(prototypes: &[Prototype]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_u32(&mut out, prototypes.len());
+
+    for prototype in prototypes {
+        write_str(&mut out, &prototype.name);
+        out.push(prototype.expression as u8);
+
+        let chunk = &prototype.chunk;
+        write_u32(&mut out, chunk.constants.len());
+        for constant in &chunk.constants {
+            match *constant {
+                TokenLiteral::Number(n) => {
+                    out.push(TAG_NUMBER);
+                    out.extend_from_slice(&n.to_bits().to_le_bytes());
+                },
+                TokenLiteral::Str(ref s) => {
+                    out.push(TAG_STR);
+                    write_str(&mut out, s);
+                },
+                TokenLiteral::Bool(b) => {
+                    out.push(TAG_BOOL);
+                    out.push(b as u8);
+                },
+                TokenLiteral::None => out.push(TAG_NIL),
+            }
+        }
+
+        write_u32(&mut out, chunk.code.len());
+        out.extend_from_slice(&chunk.code);
+
+        write_u32(&mut out, chunk.line_runs().len());
+        for &(line, count) in chunk.line_runs() {
+            write_u32(&mut out, line);
+            write_u32(&mut out, count);
+        }
+    }
+
+    out
+}
+
+/// Loads the prototypes stored in `bytes`, checking that the code in every
+/// chunk is safe for the VM to execute.
+pub fn deserialize(bytes: &[u8]) -> Result<Vec<Prototype>, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Result::Err(String::from("Not a compiled Lox file."));
+    }
+
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Result::Err(format!("Unsupported bytecode version {}, expected {}.", version, VERSION));
+    }
+
+    let count = reader.u32()?;
+    if count == 0 {
+        return Result::Err(String::from("File contains no code."));
+    }
+
+    let mut prototypes = Vec::new();
+    for _ in 0..count {
+        let prototype = reader.prototype()?;
+        verify(&prototype.chunk).map_err(|e| format!("Invalid code in '{}': {}", prototype.name, e))?;
+        prototypes.push(prototype);
+    }
+
+    if reader.pos != bytes.len() {
+        return Result::Err(String::from("Unexpected data after the last prototype."));
+    }
+
+    Result::Ok(prototypes)
+}
+
+/// Follows every path through the code, checking opcodes, operands and
+/// that the stack is as deep whichever way an instruction is reached.
+fn verify(chunk: &Chunk) -> Result<(), String> {
+    // The stack depth at every instruction reached so far.
+    let mut depths: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((offset, depth)) = pending.pop() {
+        if offset >= chunk.code.len() {
+            return Result::Err(String::from("code does not end with a return."));
+        }
+        match depths[offset] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Result::Err(format!("stack depth differs between paths to offset {}.", offset)),
+            None => depths[offset] = Some(depth),
+        }
+
+        let byte = chunk.code[offset];
+        let op = match OpCode::from_byte(byte) {
+            Some(op) => op,
+            None => return Result::Err(format!("unknown opcode {} at offset {}.", byte, offset)),
+        };
+
+        let (pops, pushes, size) = match op {
+            OpCode::Constant => {
+                constant(chunk, offset)?;
+                (0, 1, 2)
+            },
+            OpCode::GetGlobal | OpCode::GetProperty | OpCode::SetProperty |
+            OpCode::DefineGlobal | OpCode::SetGlobal => {
+                match *constant(chunk, offset)? {
+                    TokenLiteral::Str(_) => {},
+                    _ => return Result::Err(format!("name is not a string at offset {}.", offset)),
+                }
+                match op {
+                    OpCode::GetGlobal => (0, 1, 2),
+                    OpCode::GetProperty | OpCode::SetGlobal => (1, 1, 2),
+                    OpCode::DefineGlobal => (1, 0, 2),
+                    _ => (2, 1, 2),
+                }
+            },
+            OpCode::GetLocal | OpCode::SetLocal => {
+                let slot = operand(chunk, offset)? as usize;
+                // Locals live at the bottom of the stack, below what is being computed.
+                if slot >= depth || (op == OpCode::SetLocal && slot + 1 >= depth) {
+                    return Result::Err(format!("local slot {} out of range at offset {}.", slot, offset));
+                }
+                if op == OpCode::GetLocal { (0, 1, 2) } else { (1, 1, 2) }
+            },
+            OpCode::Jump | OpCode::Loop => (0, 0, 3),
+            OpCode::JumpIfFalse => (1, 1, 3),
+            OpCode::Call => {
+                let arg_count = operand(chunk, offset)? as usize;
+                (arg_count + 1, 1, 2)
+            },
+            OpCode::BuildList => (operand(chunk, offset)? as usize, 1, 2),
+            OpCode::BuildMap => (2 * operand(chunk, offset)? as usize, 1, 2),
+            OpCode::Nil | OpCode::True | OpCode::False => (0, 1, 1),
+            OpCode::Equal | OpCode::NotEqual |
+            OpCode::Greater | OpCode::GreaterEqual |
+            OpCode::Less | OpCode::LessEqual |
+            OpCode::Add | OpCode::Subtract |
+            OpCode::Multiply | OpCode::Divide |
+            OpCode::Modulo | OpCode::Power |
+            OpCode::Index => (2, 1, 1),
+            OpCode::SetIndex => (3, 1, 1),
+            OpCode::Not | OpCode::Negate => (1, 1, 1),
+            OpCode::Pop | OpCode::Print | OpCode::Return => (1, 0, 1),
+        };
+
+        if depth < pops {
+            return Result::Err(format!("stack underflow at offset {}.", offset));
+        }
+        let depth = depth - pops + pushes;
+        let next = offset + size;
+
+        match op {
+            OpCode::Return if next != chunk.code.len() => {
+                return Result::Err(format!("unreachable code after offset {}.", offset));
+            },
+            OpCode::Return => {},
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let distance = chunk.jump_distance(offset).ok_or_else(|| format!("missing operand at offset {}.", offset))?;
+                let target = match op {
+                    OpCode::Loop => next.checked_sub(distance),
+                    _ => Some(next + distance),
+                };
+                match target {
+                    Some(target) if target < chunk.code.len() => pending.push((target, depth)),
+                    _ => return Result::Err(format!("jump out of range at offset {}.", offset)),
+                }
+                if op == OpCode::JumpIfFalse {
+                    pending.push((next, depth));
+                }
+            },
+            _ => pending.push((next, depth)),
+        }
+    }
+
+    Result::Ok(())
+}
+
+fn operand(chunk: &Chunk, offset: usize) -> Result<u8, String> {
+    match chunk.code.get(offset + 1) {
+        Some(&operand) => Result::Ok(operand),
+        None => Result::Err(format!("missing operand at offset {}.", offset)),
+    }
+}
+
+fn constant(chunk: &Chunk, offset: usize) -> Result<&TokenLiteral, String> {
+    let constant = operand(chunk, offset)?;
+    match chunk.constants.get(constant as usize) {
+        Some(value) => Result::Ok(value),
+        None => Result::Err(format!("constant {} out of range at offset {}.", constant, offset)),
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Result::Err(String::from("Unexpected end of file."));
+        }
+
+        self.pos += n;
+        Result::Ok(&self.bytes[self.pos - n..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Result::Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Result::Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let b = self.take(4)?;
+        Result::Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let b = self.take(8)?;
+        let mut bits = [0; 8];
+        bits.copy_from_slice(b);
+        Result::Ok(f64::from_bits(u64::from_le_bytes(bits)))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        match str::from_utf8(self.take(len)?) {
+            Ok(s) => Result::Ok(String::from(s)),
+            Err(_) => Result::Err(String::from("String is not valid UTF-8.")),
+        }
+    }
+
+    fn prototype(&mut self) -> Result<Prototype, String> {
+        let name = self.string()?;
+        let expression = self.u8()? != 0;
+        let mut chunk = Chunk::new();
+
+        for _ in 0..self.u32()? {
+            let constant = match self.u8()? {
+                TAG_NUMBER => TokenLiteral::Number(self.f64()?),
+                TAG_STR => TokenLiteral::Str(intern(&self.string()?).into()),
+                TAG_BOOL => TokenLiteral::Bool(self.u8()? != 0),
+                TAG_NIL => TokenLiteral::None,
+                tag => return Result::Err(format!("Unknown constant tag {}.", tag)),
+            };
+            chunk.add_constant(constant);
+        }
+
+        let code_len = self.u32()?;
+        let code = self.take(code_len)?;
+
+        let mut offset = 0;
+        for _ in 0..self.u32()? {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            if count > code.len() - offset {
+                return Result::Err(String::from("Line table does not match the code."));
+            }
+
+            for &byte in &code[offset..offset + count] {
+                chunk.write(byte, line);
+            }
+            offset += count;
+        }
+
+        if offset != code.len() {
+            return Result::Err(String::from("Line table does not match the code."));
+        }
+
+        Result::Ok(Prototype { name, expression, chunk })
+    }
+}
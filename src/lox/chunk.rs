@@ -0,0 +1,137 @@
+use lox::token::TokenLiteral;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Return,
+    GetGlobal,
+    Call,
+    Modulo,
+    Power,
+    BuildList,
+    Index,
+    BuildMap,
+    SetIndex,
+    GetProperty,
+    SetProperty,
+    Pop,
+    Print,
+    DefineGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    /// Jumps take a 16-bit distance from the end of the instruction, high
+    /// byte first. `Loop` jumps backwards by it.
+    Jump,
+    JumpIfFalse,
+    Loop,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 36] = [
+        OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False,
+        OpCode::Equal, OpCode::NotEqual, OpCode::Greater, OpCode::GreaterEqual,
+        OpCode::Less, OpCode::LessEqual, OpCode::Add, OpCode::Subtract,
+        OpCode::Multiply, OpCode::Divide, OpCode::Not, OpCode::Negate,
+        OpCode::Return, OpCode::GetGlobal, OpCode::Call, OpCode::Modulo,
+        OpCode::Power, OpCode::BuildList, OpCode::Index, OpCode::BuildMap,
+        OpCode::SetIndex, OpCode::GetProperty, OpCode::SetProperty, OpCode::Pop,
+        OpCode::Print, OpCode::DefineGlobal, OpCode::SetGlobal, OpCode::GetLocal,
+        OpCode::SetLocal, OpCode::Jump, OpCode::JumpIfFalse, OpCode::Loop,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).cloned()
+    }
+}
+
+/// A sequence of bytecode together with its constant pool and a run-length
+/// encoded table mapping every byte back to its source line.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<TokenLiteral>,
+    lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk {
+            code: Vec::new(),
+            constants: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+
+        match self.lines.last_mut() {
+            Some(&mut (l, ref mut count)) if l == line => *count += 1,
+            _ => self.lines.push((line, 1)),
+        }
+    }
+
+    pub fn add_constant(&mut self, value: TokenLiteral) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Drops all code starting at `offset`.
+    pub fn truncate(&mut self, offset: usize) {
+        let mut remaining = offset;
+        let mut runs = 0;
+        while remaining > 0 {
+            let count = self.lines[runs].1;
+            if count >= remaining {
+                self.lines[runs].1 = remaining;
+                remaining = 0;
+            } else {
+                remaining -= count;
+            }
+            runs += 1;
+        }
+
+        self.code.truncate(offset);
+        self.lines.truncate(runs);
+    }
+
+    /// The line table as `(line, number of bytes)` runs.
+    pub fn line_runs(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+
+    /// The distance of the jump instruction at `offset`, if it is all there.
+    pub fn jump_distance(&self, offset: usize) -> Option<usize> {
+        let high = *self.code.get(offset + 1)? as usize;
+        let low = *self.code.get(offset + 2)? as usize;
+        Some(high << 8 | low)
+    }
+
+    pub fn get_line(&self, offset: usize) -> usize {
+        let mut start = 0;
+        for &(line, count) in &self.lines {
+            start += count;
+            if offset < start {
+                return line;
+            }
+        }
+
+        self.lines.last().map_or(0, |&(line, _)| line)
+    }
+}
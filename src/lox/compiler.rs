@@ -0,0 +1,831 @@
+use std::rc::*;
+use lox::token::*;
+use lox::interner::intern;
+use lox::chunk::*;
+use lox::interpreter::Interpreter;
+use lox::value::Value;
+use lox::parser::{Parser, MAX_NESTING};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Exponent,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Exponent,
+            Precedence::Exponent => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type CompileRes = Result<(), (Rc<Token>, String)>;
+type PrefixFn<'a> = fn(&mut Compiler<'a>) -> CompileRes;
+/// Infix rules also get the offset where the code of their left operand starts.
+type InfixFn<'a> = fn(&mut Compiler<'a>, usize) -> CompileRes;
+
+struct ParseRule<'a> {
+    prefix: Option<PrefixFn<'a>>,
+    infix: Option<InfixFn<'a>>,
+    precedence: Precedence,
+}
+
+/// A variable declared in a block, kept in the stack slot of its index.
+struct Local {
+    name: Rc<str>,
+    depth: usize,
+}
+
+/// Single-pass compiler from tokens straight to bytecode.
+///
+/// It accepts the same grammar as `Parser` and, like `ConstantFolder`,
+/// evaluates operators on constant operands at compile time. Operators that
+/// fail are compiled as usual and fail at run time, as on the tree-walker.
+///
+/// Functions, `return`, `throw`, `try`, modules and `for`-`in` loops only
+/// run on the tree-walker; scripts using them fail to compile, saying so.
+pub struct Compiler<'a> {
+    tokens: &'a Vec<Rc<Token>>,
+    current: usize,
+    chunk: Chunk,
+    /// How many expressions and statements enclose the current token.
+    nesting: usize,
+    /// Whether the expression being compiled may be the target of an `=`.
+    can_assign: bool,
+    locals: Vec<Local>,
+    /// How many blocks enclose the current token.
+    scope_depth: usize,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(tokens: &'a Vec<Rc<Token>>) -> Compiler<'a> {
+        Compiler {
+            tokens,
+            current: 0,
+            chunk: Chunk::new(),
+            nesting: 0,
+            can_assign: false,
+            locals: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+
+    /// Compiles a script that is a single expression into code returning
+    /// its value, and any other script into code returning `nil`. Like
+    /// `Parser`, it reports every syntax error, resuming at the next
+    /// statement after each.
+    pub fn compile<F>(mut self, mut err: F) -> Option<Chunk>
+    where F: FnMut(Rc<Token>, &str) {
+        if Parser::new(self.tokens).is_expression() {
+            return match self.expression().and_then(|_| self.end()) {
+                Ok(()) => {
+                    let line = self.previous().line;
+                    self.emit_op(OpCode::Return, line);
+                    Option::Some(self.chunk)
+                },
+                Err(e) => {
+                    err(e.0, &e.1);
+                    Option::None
+                },
+            };
+        }
+
+        let mut had_error = false;
+        while !self.is_at_end() {
+            if let Err(e) = self.declaration() {
+                err(e.0, &e.1);
+                had_error = true;
+                self.synchronize();
+            }
+        }
+
+        if had_error {
+            return Option::None;
+        }
+        let line = self.peek().line;
+        self.emit_op(OpCode::Nil, line);
+        self.emit_op(OpCode::Return, line);
+        Option::Some(self.chunk)
+    }
+
+    fn declaration(&mut self) -> CompileRes {
+        match self.peek().token_type {
+            TokenType::Fun | TokenType::Import | TokenType::From | TokenType::Export => Compiler::unsupported(self.peek()),
+            TokenType::Var => {
+                self.advance();
+                self.var_declaration()
+            },
+            _ => self.statement(),
+        }
+    }
+
+    fn var_declaration(&mut self) -> CompileRes {
+        self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let name = self.previous();
+
+        if self.check(TokenType::Equal) {
+            self.advance();
+            self.expression()?;
+        } else {
+            self.emit_op(OpCode::Nil, name.line);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.")?;
+
+        // Declared only now, so the initializer sees any variable it shadows.
+        if self.scope_depth > 0 {
+            if self.locals.len() > u8::MAX as usize {
+                return Result::Err((name, String::from("Too many local variables.")));
+            }
+            self.locals.push(Local { name: Rc::clone(&name.lexeme), depth: self.scope_depth });
+            return Result::Ok(());
+        }
+
+        let constant = self.identifier_constant(&name)?;
+        self.emit_op(OpCode::DefineGlobal, name.line);
+        self.chunk.write(constant, name.line);
+        Result::Ok(())
+    }
+
+    fn statement(&mut self) -> CompileRes {
+        self.nested(|compiler| compiler.simple_statement())
+    }
+
+    /// A statement, which may contain others but isn't counted as a level of nesting.
+    fn simple_statement(&mut self) -> CompileRes {
+        let keyword = self.peek();
+        match keyword.token_type {
+            // Without functions, every `return` is at the top level.
+            TokenType::Return => Result::Err((keyword, String::from("Can't return from top-level code."))),
+            TokenType::Throw | TokenType::Try => Compiler::unsupported(keyword),
+            TokenType::For => {
+                self.advance();
+                self.for_statement()
+            },
+            TokenType::If => {
+                self.advance();
+                self.if_statement()
+            },
+            TokenType::Print => {
+                self.advance();
+                self.expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+                self.emit_op(OpCode::Print, keyword.line);
+                Result::Ok(())
+            },
+            TokenType::While => {
+                self.advance();
+                self.while_statement()
+            },
+            TokenType::LeftBrace => {
+                self.advance();
+                self.scope_depth += 1;
+                self.block()?;
+                self.end_scope();
+                Result::Ok(())
+            },
+            _ => {
+                self.expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+                self.emit_op(OpCode::Pop, keyword.line);
+                Result::Ok(())
+            },
+        }
+    }
+
+    /// Compiles a `for` loop the way `Parser` desugars it: a block holding
+    /// the initializer and a `while` loop with the increment after the body.
+    fn for_statement(&mut self) -> CompileRes {
+        let keyword = self.previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.check(TokenType::Var) && self.check_at(2, TokenType::In) {
+            return Compiler::unsupported(Rc::clone(&self.tokens[self.current + 2]));
+        }
+        self.scope_depth += 1;
+
+        if self.check(TokenType::Semicolon) {
+            self.advance();
+        } else if self.check(TokenType::Var) {
+            self.advance();
+            self.var_declaration()?;
+        } else {
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+            self.emit_op(OpCode::Pop, keyword.line);
+        }
+
+        let mut loop_start = self.chunk.code.len();
+        let mut exit_jump = None;
+        if !self.check(TokenType::Semicolon) {
+            self.expression()?;
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse, keyword.line));
+            self.emit_op(OpCode::Pop, keyword.line);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        // The increment comes first in the code, so the body jumps back to it.
+        if !self.check(TokenType::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump, keyword.line);
+            let increment_start = self.chunk.code.len();
+            self.expression()?;
+            self.emit_op(OpCode::Pop, keyword.line);
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+            self.emit_loop(loop_start, keyword.line)?;
+            loop_start = increment_start;
+            self.patch_jump(body_jump)?;
+        } else {
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        }
+
+        self.statement()?;
+        self.emit_loop(loop_start, keyword.line)?;
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump)?;
+            self.emit_op(OpCode::Pop, keyword.line);
+        }
+
+        self.end_scope();
+        Result::Ok(())
+    }
+
+    fn if_statement(&mut self) -> CompileRes {
+        let keyword = self.previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse, keyword.line);
+        self.emit_op(OpCode::Pop, keyword.line);
+        self.statement()?;
+        let else_jump = self.emit_jump(OpCode::Jump, keyword.line);
+
+        self.patch_jump(then_jump)?;
+        self.emit_op(OpCode::Pop, keyword.line);
+        if self.check(TokenType::Else) {
+            self.advance();
+            self.statement()?;
+        }
+        self.patch_jump(else_jump)
+    }
+
+    fn while_statement(&mut self) -> CompileRes {
+        let keyword = self.previous();
+        let loop_start = self.chunk.code.len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse, keyword.line);
+        self.emit_op(OpCode::Pop, keyword.line);
+        self.statement()?;
+        self.emit_loop(loop_start, keyword.line)?;
+
+        self.patch_jump(exit_jump)?;
+        self.emit_op(OpCode::Pop, keyword.line);
+        Result::Ok(())
+    }
+
+    fn block(&mut self) -> CompileRes {
+        self.nested(|compiler| {
+            while !compiler.check(TokenType::RightBrace) && !compiler.is_at_end() {
+                compiler.declaration()?;
+            }
+
+            compiler.consume(TokenType::RightBrace, "Expect '}' after block.")
+        })
+    }
+
+    /// Leaves a block, popping the locals declared in it.
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        let line = self.previous().line;
+        while self.locals.last().is_some_and(|local| local.depth > self.scope_depth) {
+            self.locals.pop();
+            self.emit_op(OpCode::Pop, line);
+        }
+    }
+
+    fn unsupported(token: Rc<Token>) -> CompileRes {
+        let message = format!("The VM doesn't support '{}'.", token.lexeme);
+        Result::Err((token, message))
+    }
+
+    fn rule(token_type: &TokenType) -> ParseRule<'a> {
+        let (prefix, infix, precedence): (Option<PrefixFn<'a>>, Option<InfixFn<'a>>, Precedence) = match *token_type {
+            TokenType::LeftParen => (Some(Compiler::grouping), Some(Compiler::call), Precedence::Call),
+            TokenType::LeftBracket => (Some(Compiler::list), Some(Compiler::index), Precedence::Call),
+            TokenType::LeftBrace => (Some(Compiler::map), None, Precedence::None),
+            TokenType::Dot => (None, Some(Compiler::dot), Precedence::Call),
+            TokenType::Minus => (Some(Compiler::unary), Some(Compiler::binary), Precedence::Term),
+            TokenType::Plus => (None, Some(Compiler::binary), Precedence::Term),
+            TokenType::Percent | TokenType::Slash | TokenType::Star => (None, Some(Compiler::binary), Precedence::Factor),
+            TokenType::StarStar => (None, Some(Compiler::binary), Precedence::Exponent),
+            TokenType::Bang => (Some(Compiler::unary), None, Precedence::None),
+            TokenType::BangEqual | TokenType::EqualEqual => (None, Some(Compiler::binary), Precedence::Equality),
+            TokenType::Greater | TokenType::GreaterEqual |
+            TokenType::Less | TokenType::LessEqual => (None, Some(Compiler::binary), Precedence::Comparison),
+            TokenType::And => (None, Some(Compiler::and), Precedence::And),
+            TokenType::Or => (None, Some(Compiler::or), Precedence::Or),
+            TokenType::Identifier => (Some(Compiler::variable), None, Precedence::None),
+            TokenType::Str | TokenType::Number => (Some(Compiler::literal), None, Precedence::None),
+            TokenType::False | TokenType::True | TokenType::Nil => (Some(Compiler::literal), None, Precedence::None),
+            _ => (None, None, Precedence::None),
+        };
+
+        ParseRule {
+            prefix,
+            infix,
+            precedence,
+        }
+    }
+
+    fn expression(&mut self) -> CompileRes {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    /// Compiles an expression of at least `precedence`, counted as a level of
+    /// nesting like the expressions of `Parser`.
+    fn parse_precedence(&mut self, precedence: Precedence) -> CompileRes {
+        self.nested(|compiler| compiler.parse_operand(precedence))
+    }
+
+    /// Compiles something nested in what is being compiled, failing once
+    /// that is more than `MAX_NESTING` levels deep.
+    fn nested<C>(&mut self, compile: C) -> CompileRes
+    where C: FnOnce(&mut Compiler<'a>) -> CompileRes {
+        if self.nesting >= MAX_NESTING {
+            return Result::Err((self.peek(), String::from("Too much nesting.")));
+        }
+
+        self.nesting += 1;
+        let result = compile(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn parse_operand(&mut self, precedence: Precedence) -> CompileRes {
+        let start = self.chunk.code.len();
+        let can_assign = precedence <= Precedence::Assignment;
+
+        let prefix = match Compiler::rule(&self.peek().token_type).prefix {
+            Some(prefix) => prefix,
+            None => return Result::Err((self.peek(), String::from("Expect expression."))),
+        };
+        self.advance();
+        self.can_assign = can_assign;
+        prefix(self)?;
+
+        loop {
+            let rule = Compiler::rule(&self.peek().token_type);
+            match rule.infix {
+                Some(infix) if precedence <= rule.precedence => {
+                    self.advance();
+                    self.can_assign = can_assign;
+                    infix(self, start)?;
+                },
+                _ => break,
+            }
+        }
+
+        // Valid targets took the `=` themselves.
+        if can_assign && self.check(TokenType::Equal) {
+            return Result::Err((self.peek(), String::from("Invalid assignment target.")));
+        }
+        Result::Ok(())
+    }
+
+    fn grouping(&mut self) -> CompileRes {
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after expression.")
+    }
+
+    fn literal(&mut self) -> CompileRes {
+        let token = self.previous();
+        let value = match token.token_type {
+            TokenType::False => TokenLiteral::Bool(false),
+            TokenType::True => TokenLiteral::Bool(true),
+            TokenType::Nil => TokenLiteral::None,
+            _ => token.literal.clone(),
+        };
+
+        self.emit_value(value, &token)
+    }
+
+    fn unary(&mut self) -> CompileRes {
+        let operator = self.previous();
+        let start = self.chunk.code.len();
+        self.parse_precedence(Precedence::Unary)?;
+
+        // An operator that fails is left to fail at run time, if it runs.
+        let right = self.constant_between(start, self.chunk.code.len());
+        if let Some(Result::Ok(value)) = right.map(|right| Interpreter::unary_op(&operator.token_type, Value::from(right))) {
+            self.discard(start);
+            return self.emit_folded(value, &operator);
+        }
+
+        match operator.token_type {
+            TokenType::Bang => self.emit_op(OpCode::Not, operator.line),
+            _ => self.emit_op(OpCode::Negate, operator.line),
+        }
+        Result::Ok(())
+    }
+
+    fn binary(&mut self, start: usize) -> CompileRes {
+        let operator = self.previous();
+        let middle = self.chunk.code.len();
+        // The right operand of `**` may itself be a unary or `**` expression.
+        let precedence = match operator.token_type {
+            TokenType::StarStar => Precedence::Unary,
+            ref token_type => Compiler::rule(token_type).precedence.next(),
+        };
+        self.parse_precedence(precedence)?;
+
+        let left = self.constant_between(start, middle);
+        let right = self.constant_between(middle, self.chunk.code.len());
+        if let (Some(left), Some(right)) = (left, right) {
+            if let Result::Ok(value) = Interpreter::binary_op(&operator.token_type, Value::from(left), Value::from(right)) {
+                self.discard(start);
+                return self.emit_folded(value, &operator);
+            }
+        }
+
+        let op = match operator.token_type {
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Percent => OpCode::Modulo,
+            TokenType::StarStar => OpCode::Power,
+            _ => OpCode::Divide,
+        };
+        self.emit_op(op, operator.line);
+        Result::Ok(())
+    }
+
+    /// Leaves the left operand as the value if it is falsey, or else replaces
+    /// it with the right one.
+    fn and(&mut self, _start: usize) -> CompileRes {
+        let operator = self.previous();
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse, operator.line);
+        self.emit_op(OpCode::Pop, operator.line);
+        self.parse_precedence(Precedence::And.next())?;
+        self.patch_jump(end_jump)
+    }
+
+    fn or(&mut self, _start: usize) -> CompileRes {
+        let operator = self.previous();
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse, operator.line);
+        let end_jump = self.emit_jump(OpCode::Jump, operator.line);
+        self.patch_jump(else_jump)?;
+        self.emit_op(OpCode::Pop, operator.line);
+        self.parse_precedence(Precedence::Or.next())?;
+        self.patch_jump(end_jump)
+    }
+
+    fn call(&mut self, _start: usize) -> CompileRes {
+        let mut arg_count = 0;
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arg_count >= 255 {
+                    return Result::Err((self.peek(), String::from("Can't have more than 255 arguments.")));
+                }
+                self.expression()?;
+                arg_count += 1;
+
+                if !self.check(TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        let paren = self.previous();
+        self.emit_op(OpCode::Call, paren.line);
+        self.chunk.write(arg_count as u8, paren.line);
+        Result::Ok(())
+    }
+
+    fn list(&mut self) -> CompileRes {
+        let mut count = 0;
+
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                if count >= 255 {
+                    return Result::Err((self.peek(), String::from("Can't have more than 255 elements in a list literal.")));
+                }
+                self.expression()?;
+                count += 1;
+
+                if !self.check(TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+        let bracket = self.previous();
+        self.emit_op(OpCode::BuildList, bracket.line);
+        self.chunk.write(count as u8, bracket.line);
+        Result::Ok(())
+    }
+
+    fn map(&mut self) -> CompileRes {
+        let mut count = 0;
+
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                if count >= 255 {
+                    return Result::Err((self.peek(), String::from("Can't have more than 255 entries in a map literal.")));
+                }
+                self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                self.expression()?;
+                count += 1;
+
+                if !self.check(TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        let brace = self.previous();
+        self.emit_op(OpCode::BuildMap, brace.line);
+        self.chunk.write(count as u8, brace.line);
+        Result::Ok(())
+    }
+
+    fn index(&mut self, _start: usize) -> CompileRes {
+        let can_assign = self.can_assign;
+        self.expression()?;
+        self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+        let bracket = self.previous();
+
+        if can_assign && self.check(TokenType::Equal) {
+            self.advance();
+            self.expression()?;
+            self.emit_op(OpCode::SetIndex, bracket.line);
+        } else {
+            self.emit_op(OpCode::Index, bracket.line);
+        }
+        Result::Ok(())
+    }
+
+    fn dot(&mut self, _start: usize) -> CompileRes {
+        let can_assign = self.can_assign;
+        self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+        let name = self.previous();
+        let constant = self.identifier_constant(&name)?;
+
+        if can_assign && self.check(TokenType::Equal) {
+            self.advance();
+            self.expression()?;
+            self.emit_op(OpCode::SetProperty, name.line);
+        } else {
+            self.emit_op(OpCode::GetProperty, name.line);
+        }
+        self.chunk.write(constant, name.line);
+        Result::Ok(())
+    }
+
+    fn variable(&mut self) -> CompileRes {
+        let can_assign = self.can_assign;
+        let name = self.previous();
+        let (get, set, operand) = match self.resolve_local(&name) {
+            Some(slot) => (OpCode::GetLocal, OpCode::SetLocal, slot),
+            None => (OpCode::GetGlobal, OpCode::SetGlobal, self.identifier_constant(&name)?),
+        };
+
+        if can_assign && self.check(TokenType::Equal) {
+            self.advance();
+            self.expression()?;
+            self.emit_op(set, name.line);
+        } else {
+            self.emit_op(get, name.line);
+        }
+        self.chunk.write(operand, name.line);
+        Result::Ok(())
+    }
+
+    /// The slot of the innermost local called `name`, if there is one.
+    fn resolve_local(&self, name: &Token) -> Option<u8> {
+        self.locals.iter().rposition(|local| local.name == name.lexeme).map(|slot| slot as u8)
+    }
+
+    fn identifier_constant(&mut self, name: &Rc<Token>) -> Result<u8, (Rc<Token>, String)> {
+        self.make_constant(TokenLiteral::Str(intern(&name.lexeme).into()), name)
+    }
+
+    /// Returns the value loaded by the code in `start..end` if that code is a
+    /// single instruction pushing a constant.
+    fn constant_between(&self, start: usize, end: usize) -> Option<TokenLiteral> {
+        let code = &self.chunk.code[start..end];
+        match (OpCode::from_byte(*code.first()?)?, code.len()) {
+            (OpCode::Constant, 2) => Some(self.chunk.constants[code[1] as usize].clone()),
+            (OpCode::Nil, 1) => Some(TokenLiteral::None),
+            (OpCode::True, 1) => Some(TokenLiteral::Bool(true)),
+            (OpCode::False, 1) => Some(TokenLiteral::Bool(false)),
+            _ => None,
+        }
+    }
+
+    /// Removes the code emitted since `start` along with the constants it added.
+    fn discard(&mut self, start: usize) {
+        let mut offset = start;
+        while offset < self.chunk.code.len() {
+            match OpCode::from_byte(self.chunk.code[offset]) {
+                Some(OpCode::Constant) | Some(OpCode::GetGlobal) |
+                Some(OpCode::GetProperty) | Some(OpCode::SetProperty) |
+                Some(OpCode::DefineGlobal) | Some(OpCode::SetGlobal) => {
+                    self.chunk.constants.pop();
+                    offset += 2;
+                },
+                Some(OpCode::Call) | Some(OpCode::BuildList) | Some(OpCode::BuildMap) |
+                Some(OpCode::GetLocal) | Some(OpCode::SetLocal) => offset += 2,
+                Some(OpCode::Jump) | Some(OpCode::JumpIfFalse) | Some(OpCode::Loop) => offset += 3,
+                _ => offset += 1,
+            }
+        }
+
+        self.chunk.truncate(start);
+    }
+
+    /// Emits the result of an operator folded at compile time.
+    fn emit_folded(&mut self, value: Value, token: &Rc<Token>) -> CompileRes {
+        // Operators only ever produce values that have a literal form.
+        self.emit_value(value.to_literal().unwrap(), token)
+    }
+
+    fn emit_value(&mut self, value: TokenLiteral, token: &Rc<Token>) -> CompileRes {
+        match value {
+            TokenLiteral::None => self.emit_op(OpCode::Nil, token.line),
+            TokenLiteral::Bool(true) => self.emit_op(OpCode::True, token.line),
+            TokenLiteral::Bool(false) => self.emit_op(OpCode::False, token.line),
+            value => {
+                let constant = self.make_constant(value, token)?;
+                self.emit_op(OpCode::Constant, token.line);
+                self.chunk.write(constant, token.line);
+            },
+        }
+
+        Result::Ok(())
+    }
+
+    fn make_constant(&mut self, value: TokenLiteral, token: &Rc<Token>) -> Result<u8, (Rc<Token>, String)> {
+        let constant = self.chunk.add_constant(value);
+        if constant > u8::MAX as usize {
+            return Result::Err((Rc::clone(token), String::from("Too many constants in one chunk.")));
+        }
+
+        Result::Ok(constant as u8)
+    }
+
+    fn emit_op(&mut self, op: OpCode, line: usize) {
+        self.chunk.write(op as u8, line);
+    }
+
+    /// Emits a forward jump, returning where its distance goes once known.
+    fn emit_jump(&mut self, op: OpCode, line: usize) -> usize {
+        self.emit_op(op, line);
+        self.chunk.write(0xff, line);
+        self.chunk.write(0xff, line);
+        self.chunk.code.len() - 2
+    }
+
+    /// Makes the jump emitted at `offset` land on the next instruction.
+    fn patch_jump(&mut self, offset: usize) -> CompileRes {
+        let distance = self.chunk.code.len() - offset - 2;
+        if distance > u16::MAX as usize {
+            return Result::Err((self.previous(), String::from("Too much code to jump over.")));
+        }
+
+        self.chunk.code[offset] = (distance >> 8) as u8;
+        self.chunk.code[offset + 1] = distance as u8;
+        Result::Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, line: usize) -> CompileRes {
+        self.emit_op(OpCode::Loop, line);
+        let distance = self.chunk.code.len() + 2 - loop_start;
+        if distance > u16::MAX as usize {
+            return Result::Err((self.previous(), String::from("Loop body too large.")));
+        }
+
+        self.chunk.write((distance >> 8) as u8, line);
+        self.chunk.write(distance as u8, line);
+        Result::Ok(())
+    }
+
+    /// Checks that the expression is all there is.
+    fn end(&mut self) -> CompileRes {
+        if self.is_at_end() {
+            return Result::Ok(());
+        }
+
+        Result::Err((self.peek(), String::from("Expect end of expression.")))
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &'static str) -> CompileRes {
+        if self.check(token_type) {
+            self.advance();
+            return Result::Ok(());
+        }
+
+        Result::Err((self.peek(), String::from(message)))
+    }
+
+    /// Skips tokens until the start of the next statement.
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().token_type {
+                TokenType::Class |
+                TokenType::Export |
+                TokenType::Fun |
+                TokenType::From |
+                TokenType::Import |
+                TokenType::Var |
+                TokenType::For |
+                TokenType::If |
+                TokenType::While |
+                TokenType::Print |
+                TokenType::Return |
+                TokenType::Throw |
+                TokenType::Try => return,
+                _ => {},
+            }
+
+            self.advance();
+        }
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+
+        self.peek().token_type == token_type
+    }
+
+    /// Like `check`, but looks `ahead` tokens past the current one.
+    fn check_at(&self, ahead: usize, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + ahead) {
+            Some(token) => token.token_type == token_type,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> Rc<Token> {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    fn peek(&self) -> Rc<Token> {
+        Rc::clone(&self.tokens[self.current])
+    }
+
+    fn previous(&self) -> Rc<Token> {
+        Rc::clone(&self.tokens[self.current - 1])
+    }
+}
@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::*;
+use lox::token::*;
+use lox::expr::*;
+use lox::stmt::*;
+use lox::interpreter::Interpreter;
+use lox::value::Value;
+
+/// A subtree after folding: either a value known at compile time, an
+/// expression that has to be evaluated at run time or a statement.
+pub enum Folded<'a, U: 'a> {
+    Constant(TokenLiteral),
+    Expr(ExprRef<'a, U>),
+    Stmt(StmtRef<'a, U>),
+}
+
+impl<'a, U: 'a> Folded<'a, U> {
+    pub fn into_expr(self) -> ExprRef<'a, U> {
+        match self {
+            Folded::Constant(value) => Literal::new(value),
+            Folded::Expr(expr) => expr,
+            Folded::Stmt(_) => unreachable!("statements only come from statements"),
+        }
+    }
+
+    pub fn into_stmt(self) -> StmtRef<'a, U> {
+        match self {
+            Folded::Stmt(stmt) => stmt,
+            _ => unreachable!("expressions only come from expressions"),
+        }
+    }
+}
+
+pub type FoldRes<'a, U> = Folded<'a, U>;
+
+/// Optimization pass that rebuilds an expression tree as a tree of type `U`.
+///
+/// Operators whose operands are all constants are evaluated with the same
+/// code the interpreter uses, so the folded tree produces exactly the same
+/// values. An operator that would always fail is left in the tree, so it
+/// still fails at run time, and only if it is reached; its error is kept as
+/// a diagnostic at the operator token. Groupings only carry precedence, which
+/// the tree shape already encodes, so they are dropped. Identities such as
+/// `x * 1` are left alone: without static types they may change which values
+/// or errors are produced.
+pub struct ConstantFolder<U> {
+    diagnostics: RefCell<Vec<(Rc<Token>, String)>>,
+    output: PhantomData<U>,
+}
+
+impl<U> Default for ConstantFolder<U> {
+    fn default() -> ConstantFolder<U> {
+        ConstantFolder::new()
+    }
+}
+
+impl<U> ConstantFolder<U> {
+    pub fn new() -> ConstantFolder<U> {
+        ConstantFolder {
+            diagnostics: RefCell::new(Vec::new()),
+            output: PhantomData,
+        }
+    }
+
+    /// The operators found so far that fail whenever they are evaluated,
+    /// with their errors.
+    pub fn diagnostics(&self) -> Vec<(Rc<Token>, String)> {
+        self.diagnostics.borrow().clone()
+    }
+}
+
+impl<'a, U: 'a> ConstantFolder<U> {
+    pub fn fold(&self, expr: &dyn Expr<'a, FoldRes<'a, U>>) -> ExprRef<'a, U> {
+        expr.accept(self).into_expr()
+    }
+
+    pub fn fold_program(&self, program: &[StmtRef<'a, FoldRes<'a, U>>]) -> Vec<StmtRef<'a, U>> {
+        self.fold_statements(program)
+    }
+
+    fn fold_statements(&self, statements: &[StmtRef<'a, FoldRes<'a, U>>]) -> Vec<StmtRef<'a, U>> {
+        statements.iter().map(|statement| statement.accept(self).into_stmt()).collect()
+    }
+
+    fn fold_expr(&self, expr: &ExprRef<'a, FoldRes<'a, U>>) -> ExprRef<'a, U> {
+        expr.accept(self).into_expr()
+    }
+
+    /// Turns the result of an operator applied to constants back into a
+    /// constant, or into the expression `unfolded` builds if it failed.
+    fn constant<F>(&self, value: Result<Value, String>, operator: &Rc<Token>, unfolded: F) -> FoldRes<'a, U>
+        where F: FnOnce() -> ExprRef<'a, U>
+    {
+        match value {
+            // Operators only ever produce values that have a literal form.
+            Result::Ok(value) => Folded::Constant(value.to_literal().unwrap()),
+            Result::Err(e) => {
+                self.diagnostics.borrow_mut().push((Rc::clone(operator), e));
+                Folded::Expr(unfolded())
+            },
+        }
+    }
+}
+
+impl<'a, U: 'a> Visitor<'a, FoldRes<'a, U>> for ConstantFolder<U> {
+    fn visit_assign(&self, expr: &Assign<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Expr(Assign::new(Rc::clone(&expr.name), self.fold_expr(&expr.value)))
+    }
+
+    fn visit_binary(&self, expr: &Binary<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let left = expr.left.accept(self);
+        let right = expr.right.accept(self);
+
+        match (left, right) {
+            (Folded::Constant(l), Folded::Constant(r)) => {
+                let value = Interpreter::binary_op(&expr.operator.token_type, Value::from(l.clone()), Value::from(r.clone()));
+                self.constant(value, &expr.operator, || Binary::new(Literal::new(l), Rc::clone(&expr.operator), Literal::new(r)))
+            },
+            (left, right) => Folded::Expr(Binary::new(left.into_expr(), Rc::clone(&expr.operator), right.into_expr())),
+        }
+    }
+
+    fn visit_call(&self, expr: &Call<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let callee = expr.callee.accept(self).into_expr();
+
+        let mut arguments = Vec::new();
+        for argument in &expr.arguments {
+            arguments.push(argument.accept(self).into_expr());
+        }
+
+        Folded::Expr(Call::new(callee, Rc::clone(&expr.paren), arguments))
+    }
+
+    fn visit_get(&self, expr: &Get<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Expr(Get::new(self.fold_expr(&expr.object), Rc::clone(&expr.name)))
+    }
+
+    fn visit_grouping(&self, expr: &Grouping<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        expr.expression.accept(self)
+    }
+
+    fn visit_index(&self, expr: &Index<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let object = self.fold_expr(&expr.object);
+        let index = self.fold_expr(&expr.index);
+        Folded::Expr(Index::new(object, Rc::clone(&expr.bracket), index))
+    }
+
+    fn visit_list(&self, expr: &List<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let mut elements = Vec::new();
+        for element in &expr.elements {
+            elements.push(self.fold_expr(element));
+        }
+
+        Folded::Expr(List::new(Rc::clone(&expr.bracket), elements))
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> FoldRes<'a, U> {
+        Folded::Constant(expr.value.clone())
+    }
+
+    fn visit_logical(&self, expr: &Logical<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let left = self.fold_expr(&expr.left);
+        let right = self.fold_expr(&expr.right);
+        Folded::Expr(Logical::new(left, Rc::clone(&expr.operator), right))
+    }
+
+    fn visit_map(&self, expr: &Map<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for (key, value) in expr.keys.iter().zip(&expr.values) {
+            keys.push(self.fold_expr(key));
+            values.push(self.fold_expr(value));
+        }
+
+        Folded::Expr(Map::new(Rc::clone(&expr.brace), keys, values))
+    }
+
+    fn visit_set(&self, expr: &Set<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let object = self.fold_expr(&expr.object);
+        let value = self.fold_expr(&expr.value);
+        Folded::Expr(Set::new(object, Rc::clone(&expr.name), value))
+    }
+
+    fn visit_set_index(&self, expr: &SetIndex<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let object = self.fold_expr(&expr.object);
+        let index = self.fold_expr(&expr.index);
+        let value = self.fold_expr(&expr.value);
+        Folded::Expr(SetIndex::new(object, Rc::clone(&expr.bracket), index, value))
+    }
+
+    fn visit_unary(&self, expr: &Unary<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        match expr.right.accept(self) {
+            Folded::Constant(r) => {
+                let value = Interpreter::unary_op(&expr.operator.token_type, Value::from(r.clone()));
+                self.constant(value, &expr.operator, || Unary::new(Rc::clone(&expr.operator), Literal::new(r)))
+            },
+            right => Folded::Expr(Unary::new(Rc::clone(&expr.operator), right.into_expr())),
+        }
+    }
+
+    fn visit_variable(&self, expr: &Variable) -> FoldRes<'a, U> {
+        Folded::Expr(Variable::new(Rc::clone(&expr.name)))
+    }
+}
+
+impl<'a, U: 'a> StmtVisitor<'a, FoldRes<'a, U>> for ConstantFolder<U> {
+    fn visit_block(&self, stmt: &Block<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Stmt(Block::new(self.fold_statements(&stmt.statements)))
+    }
+
+    fn visit_export(&self, stmt: &Export<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let declaration = stmt.declaration.accept(self).into_stmt();
+        Folded::Stmt(Export::new(Rc::clone(&stmt.name), declaration))
+    }
+
+    fn visit_expression(&self, stmt: &Expression<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Stmt(Expression::new(self.fold_expr(&stmt.expression)))
+    }
+
+    fn visit_for_in(&self, stmt: &ForIn<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let iterable = self.fold_expr(&stmt.iterable);
+        let body = stmt.body.accept(self).into_stmt();
+        Folded::Stmt(ForIn::new(Rc::clone(&stmt.name), Rc::clone(&stmt.keyword), iterable, body))
+    }
+
+    fn visit_function(&self, stmt: &Function<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let body = self.fold_statements(&stmt.body);
+        Folded::Stmt(Function::new(Rc::clone(&stmt.name), stmt.params.to_vec(), body))
+    }
+
+    fn visit_if(&self, stmt: &If<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let condition = self.fold_expr(&stmt.condition);
+        let then_branch = stmt.then_branch.accept(self).into_stmt();
+        let else_branch = stmt.else_branch.as_ref().map(|else_branch| else_branch.accept(self).into_stmt());
+        Folded::Stmt(If::new(condition, then_branch, else_branch))
+    }
+
+    fn visit_import(&self, stmt: &Import<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let alias = stmt.alias.as_ref().map(Rc::clone);
+        Folded::Stmt(Import::new(Rc::clone(&stmt.keyword), Rc::clone(&stmt.path), alias, stmt.names.clone()))
+    }
+
+    fn visit_print(&self, stmt: &Print<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Stmt(Print::new(self.fold_expr(&stmt.expression)))
+    }
+
+    fn visit_return(&self, stmt: &Return<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let value = stmt.value.as_ref().map(|value| self.fold_expr(value));
+        Folded::Stmt(Return::new(Rc::clone(&stmt.keyword), value))
+    }
+
+    fn visit_throw(&self, stmt: &Throw<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        Folded::Stmt(Throw::new(Rc::clone(&stmt.keyword), self.fold_expr(&stmt.value)))
+    }
+
+    fn visit_try(&self, stmt: &Try<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let body = self.fold_statements(&stmt.body);
+        let handler = stmt.handler.as_ref().map(|(name, handler)| (Rc::clone(name), self.fold_statements(handler)));
+        let finalizer = stmt.finalizer.as_ref().map(|finalizer| self.fold_statements(finalizer));
+        Folded::Stmt(Try::new(Rc::clone(&stmt.keyword), body, handler, finalizer))
+    }
+
+    fn visit_var(&self, stmt: &Var<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let initializer = stmt.initializer.as_ref().map(|initializer| self.fold_expr(initializer));
+        Folded::Stmt(Var::new(Rc::clone(&stmt.name), initializer))
+    }
+
+    fn visit_while(&self, stmt: &While<'a, FoldRes<'a, U>>) -> FoldRes<'a, U> {
+        let condition = self.fold_expr(&stmt.condition);
+        let body = stmt.body.accept(self).into_stmt();
+        Folded::Stmt(While::new(condition, body))
+    }
+}
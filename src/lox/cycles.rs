@@ -0,0 +1,217 @@
+//! Frees the reference cycles the tree-walker builds.
+//!
+//! Scopes, lists and maps are reference counted, which frees them as soon
+//! as nothing refers to them, except when they refer to themselves: a
+//! function declared in a scope is stored in that scope and closes over
+//! it, and `xs.push(xs)` puts a list in itself.
+//!
+//! Every list and map is tracked here, weakly, and every scope a function
+//! closes over, since other scopes can't be part of a cycle. Every so often
+//! `collect` counts the references they hold to each other and to the
+//! functions and bound methods between them. An object referred to more
+//! often than that is held by something else, like the interpreter or a
+//! Rust caller, and everything reachable from such objects is alive. The
+//! rest can only be reached from cycles of garbage, which are broken by
+//! emptying their scopes, lists and maps.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+use lox::environment::{EnvRef, Environment};
+use lox::interpreter::LoxFunction;
+use lox::value::{BoundMethod, Key, Value};
+
+/// Objects tracked between collections, at the least.
+const MIN_INTERVAL: usize = 10_000;
+
+type ListRef = Rc<RefCell<Vec<Value>>>;
+type MapRef = Rc<RefCell<HashMap<Key, Value>>>;
+
+thread_local! {
+    static TRACKED: RefCell<Tracked> = RefCell::new(Tracked {
+        until_collection: MIN_INTERVAL,
+        ..Tracked::default()
+    });
+}
+
+#[derive(Default)]
+struct Tracked {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    lists: Vec<Weak<RefCell<Vec<Value>>>>,
+    maps: Vec<Weak<RefCell<HashMap<Key, Value>>>>,
+    /// Objects to track before collecting again.
+    until_collection: usize,
+    collecting: bool,
+}
+
+pub fn track_environment(environment: &EnvRef) {
+    track(|tracked| tracked.environments.push(Rc::downgrade(environment)));
+}
+
+pub fn track_list(list: &ListRef) {
+    track(|tracked| tracked.lists.push(Rc::downgrade(list)));
+}
+
+pub fn track_map(map: &MapRef) {
+    track(|tracked| tracked.maps.push(Rc::downgrade(map)));
+}
+
+/// Tracks an object, collecting once as many objects were tracked as were
+/// alive after the last collection, so collecting takes time in
+/// proportion to the objects made.
+fn track<F: FnOnce(&mut Tracked)>(f: F) {
+    let due = TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        f(&mut tracked);
+        tracked.until_collection = tracked.until_collection.saturating_sub(1);
+        tracked.until_collection == 0 && !tracked.collecting
+    });
+    if due {
+        collect();
+    }
+}
+
+/// Something that may be part of a cycle.
+enum Node {
+    Environment(EnvRef),
+    List(ListRef),
+    Map(MapRef),
+    Function(Rc<LoxFunction>),
+    Method(Rc<BoundMethod>),
+}
+
+impl Node {
+    fn from_value(value: &Value) -> Option<Node> {
+        match *value {
+            Value::List(ref list) => Some(Node::List(Rc::clone(list))),
+            Value::Map(ref map) => Some(Node::Map(Rc::clone(map))),
+            Value::Function(ref function) => Some(Node::Function(Rc::clone(function))),
+            Value::Method(ref method) => Some(Node::Method(Rc::clone(method))),
+            _ => None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match *self {
+            Node::Environment(ref environment) => Rc::as_ptr(environment) as *const u8 as usize,
+            Node::List(ref list) => Rc::as_ptr(list) as *const u8 as usize,
+            Node::Map(ref map) => Rc::as_ptr(map) as *const u8 as usize,
+            Node::Function(ref function) => Rc::as_ptr(function) as *const u8 as usize,
+            Node::Method(ref method) => Rc::as_ptr(method) as *const u8 as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Node::Environment(ref environment) => Rc::strong_count(environment),
+            Node::List(ref list) => Rc::strong_count(list),
+            Node::Map(ref map) => Rc::strong_count(map),
+            Node::Function(ref function) => Rc::strong_count(function),
+            Node::Method(ref method) => Rc::strong_count(method),
+        }
+    }
+
+    /// The nodes this one refers to, or `None` if it is being changed and
+    /// can't be looked into.
+    fn children(&self) -> Option<Vec<Node>> {
+        Some(match *self {
+            Node::Environment(ref environment) => {
+                let environment = environment.try_borrow().ok()?;
+                let mut children: Vec<Node> = environment.values().filter_map(Node::from_value).collect();
+                children.extend(environment.enclosing().map(|enclosing| Node::Environment(Rc::clone(enclosing))));
+                children
+            },
+            Node::List(ref list) => list.try_borrow().ok()?.iter().filter_map(Node::from_value).collect(),
+            Node::Map(ref map) => map.try_borrow().ok()?.values().filter_map(Node::from_value).collect(),
+            Node::Function(ref function) => vec![Node::Environment(Rc::clone(function.closure()))],
+            Node::Method(ref method) => Node::from_value(&method.receiver).into_iter().collect(),
+        })
+    }
+}
+
+/// Frees the cycles nothing outside of them refers to any more, returning
+/// how many scopes, lists and maps were emptied to break them.
+pub fn collect() -> usize {
+    let (environments, lists, maps) = TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        tracked.collecting = true;
+        (mem::take(&mut tracked.environments), mem::take(&mut tracked.lists), mem::take(&mut tracked.maps))
+    });
+
+    let mut nodes = Vec::new();
+    let mut index = HashMap::new();
+    let mut add = |node: Node, nodes: &mut Vec<Node>| -> usize {
+        *index.entry(node.address()).or_insert_with(|| {
+            nodes.push(node);
+            nodes.len() - 1
+        })
+    };
+    for environment in environments.iter().filter_map(Weak::upgrade) {
+        add(Node::Environment(environment), &mut nodes);
+    }
+    for list in lists.iter().filter_map(Weak::upgrade) {
+        add(Node::List(list), &mut nodes);
+    }
+    for map in maps.iter().filter_map(Weak::upgrade) {
+        add(Node::Map(map), &mut nodes);
+    }
+
+    // Count the references between nodes, finding the functions and
+    // methods in between on the way.
+    let mut edges: Vec<Vec<usize>> = Vec::new();
+    let mut opaque = Vec::new();
+    let mut i = 0;
+    while i < nodes.len() {
+        let children = nodes[i].children();
+        opaque.push(children.is_none());
+        let children = children.unwrap_or_default().into_iter().map(|child| add(child, &mut nodes)).collect();
+        edges.push(children);
+        i += 1;
+    }
+    let mut internal = vec![0; nodes.len()];
+    for &child in edges.iter().flatten() {
+        internal[child] += 1;
+    }
+
+    // Everything reachable from a node referred to from outside, or that
+    // couldn't be looked into, is alive. `nodes` itself holds one reference.
+    let mut alive = vec![false; nodes.len()];
+    let mut gray: Vec<usize> = (0..nodes.len())
+        .filter(|&i| opaque[i] || nodes[i].strong_count() > internal[i] + 1)
+        .collect();
+    while let Some(i) = gray.pop() {
+        if !alive[i] {
+            alive[i] = true;
+            gray.extend(&edges[i]);
+        }
+    }
+
+    // Empty the garbage, dropping what it held only once nothing is
+    // borrowed, since dropping it frees the rest of the cycle.
+    let mut environments_freed = Vec::new();
+    let mut values_freed = Vec::new();
+    for (node, _) in nodes.iter().zip(&alive).filter(|&(_, &alive)| !alive) {
+        match *node {
+            Node::Environment(ref environment) => environments_freed.push(mem::take(&mut *environment.borrow_mut())),
+            Node::List(ref list) => values_freed.extend(mem::take(&mut *list.borrow_mut())),
+            Node::Map(ref map) => values_freed.extend(mem::take(&mut *map.borrow_mut()).into_values()),
+            Node::Function(_) | Node::Method(_) => {},
+        }
+    }
+    let freed = environments_freed.len() + values_freed.len();
+    drop(nodes);
+    drop(environments_freed);
+    drop(values_freed);
+
+    TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        tracked.environments.extend(environments.into_iter().filter(|weak| weak.strong_count() > 0));
+        tracked.lists.extend(lists.into_iter().filter(|weak| weak.strong_count() > 0));
+        tracked.maps.extend(maps.into_iter().filter(|weak| weak.strong_count() > 0));
+        let alive = tracked.environments.len() + tracked.lists.len() + tracked.maps.len();
+        tracked.until_collection = alive.max(MIN_INTERVAL);
+        tracked.collecting = false;
+    });
+    freed
+}
@@ -0,0 +1,127 @@
+use lox::chunk::*;
+use lox::token::TokenLiteral;
+use lox::value::Value;
+
+/// Renders the whole chunk, one instruction per line.
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut s = format!("== {} ==\n", name);
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (instruction, next) = disassemble_instruction(chunk, offset);
+        s += &instruction;
+        s += "\n";
+        offset = next;
+    }
+
+    s
+}
+
+/// Renders the instruction at `offset` and returns it with the offset of the next one.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let line = if offset > 0 && chunk.get_line(offset) == chunk.get_line(offset - 1) {
+        String::from("   |")
+    } else {
+        format!("{:4}", chunk.get_line(offset))
+    };
+    let prefix = format!("{:04} {} ", offset, line);
+
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => return (format!("{}Unknown opcode {}", prefix, chunk.code[offset]), offset + 1),
+    };
+
+    match op {
+        OpCode::Constant => constant_instruction(&prefix, "OP_CONSTANT", chunk, offset),
+        OpCode::Nil => simple_instruction(&prefix, "OP_NIL", offset),
+        OpCode::True => simple_instruction(&prefix, "OP_TRUE", offset),
+        OpCode::False => simple_instruction(&prefix, "OP_FALSE", offset),
+        OpCode::Equal => simple_instruction(&prefix, "OP_EQUAL", offset),
+        OpCode::NotEqual => simple_instruction(&prefix, "OP_NOT_EQUAL", offset),
+        OpCode::Greater => simple_instruction(&prefix, "OP_GREATER", offset),
+        OpCode::GreaterEqual => simple_instruction(&prefix, "OP_GREATER_EQUAL", offset),
+        OpCode::Less => simple_instruction(&prefix, "OP_LESS", offset),
+        OpCode::LessEqual => simple_instruction(&prefix, "OP_LESS_EQUAL", offset),
+        OpCode::Add => simple_instruction(&prefix, "OP_ADD", offset),
+        OpCode::Subtract => simple_instruction(&prefix, "OP_SUBTRACT", offset),
+        OpCode::Multiply => simple_instruction(&prefix, "OP_MULTIPLY", offset),
+        OpCode::Divide => simple_instruction(&prefix, "OP_DIVIDE", offset),
+        OpCode::Modulo => simple_instruction(&prefix, "OP_MODULO", offset),
+        OpCode::Power => simple_instruction(&prefix, "OP_POWER", offset),
+        OpCode::Not => simple_instruction(&prefix, "OP_NOT", offset),
+        OpCode::Negate => simple_instruction(&prefix, "OP_NEGATE", offset),
+        OpCode::Return => simple_instruction(&prefix, "OP_RETURN", offset),
+        OpCode::GetGlobal => constant_instruction(&prefix, "OP_GET_GLOBAL", chunk, offset),
+        OpCode::Call => byte_instruction(&prefix, "OP_CALL", chunk, offset),
+        OpCode::BuildList => byte_instruction(&prefix, "OP_BUILD_LIST", chunk, offset),
+        OpCode::Index => simple_instruction(&prefix, "OP_INDEX", offset),
+        OpCode::BuildMap => byte_instruction(&prefix, "OP_BUILD_MAP", chunk, offset),
+        OpCode::SetIndex => simple_instruction(&prefix, "OP_SET_INDEX", offset),
+        OpCode::GetProperty => constant_instruction(&prefix, "OP_GET_PROPERTY", chunk, offset),
+        OpCode::SetProperty => constant_instruction(&prefix, "OP_SET_PROPERTY", chunk, offset),
+        OpCode::Pop => simple_instruction(&prefix, "OP_POP", offset),
+        OpCode::Print => simple_instruction(&prefix, "OP_PRINT", offset),
+        OpCode::DefineGlobal => constant_instruction(&prefix, "OP_DEFINE_GLOBAL", chunk, offset),
+        OpCode::SetGlobal => constant_instruction(&prefix, "OP_SET_GLOBAL", chunk, offset),
+        OpCode::GetLocal => byte_instruction(&prefix, "OP_GET_LOCAL", chunk, offset),
+        OpCode::SetLocal => byte_instruction(&prefix, "OP_SET_LOCAL", chunk, offset),
+        OpCode::Jump => jump_instruction(&prefix, "OP_JUMP", true, chunk, offset),
+        OpCode::JumpIfFalse => jump_instruction(&prefix, "OP_JUMP_IF_FALSE", true, chunk, offset),
+        OpCode::Loop => jump_instruction(&prefix, "OP_LOOP", false, chunk, offset),
+    }
+}
+
+/// Renders the values on the stack, bottom first.
+pub fn stack(values: &[Value]) -> String {
+    let mut s = String::from("          ");
+    for value in values {
+        s += &format!("[ {} ]", value);
+    }
+    s
+}
+
+pub fn value_to_string(value: &TokenLiteral) -> String {
+    match *value {
+        TokenLiteral::Str(ref s) => s.to_string(),
+        TokenLiteral::Number(n) => n.to_string(),
+        TokenLiteral::Bool(b) => b.to_string(),
+        TokenLiteral::None => String::from("nil"),
+    }
+}
+
+fn simple_instruction(prefix: &str, name: &str, offset: usize) -> (String, usize) {
+    (format!("{}{}", prefix, name), offset + 1)
+}
+
+fn byte_instruction(prefix: &str, name: &str, chunk: &Chunk, offset: usize) -> (String, usize) {
+    match chunk.code.get(offset + 1) {
+        Some(&operand) => (format!("{}{:<16} {:4}", prefix, name, operand), offset + 2),
+        None => (format!("{}{:<16} <truncated>", prefix, name), offset + 1),
+    }
+}
+
+fn jump_instruction(prefix: &str, name: &str, forward: bool, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let distance = match chunk.jump_distance(offset) {
+        Some(distance) => distance,
+        None => return (format!("{}{:<16} <truncated>", prefix, name), offset + 1),
+    };
+
+    let target = if forward { Some(offset + 3 + distance) } else { (offset + 3).checked_sub(distance) };
+    match target {
+        Some(target) => (format!("{}{:<16} {:4} -> {}", prefix, name, offset, target), offset + 3),
+        None => (format!("{}{:<16} {:4} -> <invalid>", prefix, name, offset), offset + 3),
+    }
+}
+
+fn constant_instruction(prefix: &str, name: &str, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let constant = match chunk.code.get(offset + 1) {
+        Some(&constant) => constant as usize,
+        None => return (format!("{}{:<16} <truncated>", prefix, name), offset + 1),
+    };
+
+    let value = match chunk.constants.get(constant) {
+        Some(value) => value_to_string(value),
+        None => String::from("<invalid>"),
+    };
+    (format!("{}{:<16} {:4} '{}'", prefix, name, constant, value), offset + 2)
+}
@@ -0,0 +1,331 @@
+use std::cell::*;
+use lox::expr::*;
+use lox::stmt::*;
+use lox::token::Token;
+use lox::token::TokenLiteral;
+
+/// Renders an expression tree as a Graphviz DOT graph.
+///
+/// Every visit emits the node (and the edges to its children) into the
+/// output buffer and returns the node's identifier.
+pub struct DotPrinter {
+    lines: bool,
+    next_id: Cell<usize>,
+    out: RefCell<String>,
+}
+
+impl<'a> DotPrinter {
+    pub fn new(lines: bool) -> DotPrinter {
+        DotPrinter {
+            lines,
+            next_id: Cell::new(0),
+            out: RefCell::new(String::new()),
+        }
+    }
+
+    pub fn print(&self, expr: &dyn Expr<'a, String>) -> String {
+        self.next_id.set(0);
+        self.out.replace(String::new());
+
+        expr.accept(self);
+
+        self.finish()
+    }
+
+    /// Prints a program as one graph, its statements hanging off a `Program` node.
+    pub fn print_program(&self, program: &[StmtRef<'a, String>]) -> String {
+        self.next_id.set(0);
+        self.out.replace(String::new());
+
+        let id = self.node("Program", None, None);
+        self.children(&id, "stmt", program);
+
+        self.finish()
+    }
+
+    fn finish(&self) -> String {
+        let body = self.out.replace(String::new());
+        format!("digraph AST {{\n    node [shape=box, fontname=\"monospace\"];\n{}}}\n", body)
+    }
+
+    fn children(&self, id: &str, label: &str, statements: &[StmtRef<'a, String>]) {
+        for (i, statement) in statements.iter().enumerate() {
+            let child = statement.accept(self);
+            self.edge(id, &child, &format!("{} {}", label, i));
+        }
+    }
+
+    fn node(&self, kind: &str, detail: Option<&str>, token: Option<&Token>) -> String {
+        let id = format!("n{}", self.next_id.get());
+        self.next_id.set(self.next_id.get() + 1);
+
+        let mut label = String::from(kind);
+        if let Some(detail) = detail {
+            label += "\\n";
+            label += &DotPrinter::escape(detail);
+        }
+        if let (true, Some(token)) = (self.lines, token) {
+            label += &format!("\\nline {}", token.line);
+        }
+
+        self.out.borrow_mut().push_str(&format!("    {} [label=\"{}\"];\n", id, label));
+        id
+    }
+
+    fn edge(&self, from: &str, to: &str, label: &str) {
+        self.out.borrow_mut().push_str(&format!("    {} -> {} [label=\"{}\"];\n", from, to, label));
+    }
+
+    fn escape(s: &str) -> String {
+        let mut escaped = String::new();
+        for c in s.chars() {
+            match c {
+                '"' => escaped += "\\\"",
+                '\\' => escaped += "\\\\",
+                '\n' => escaped += "\\n",
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
+impl<'a> Visitor<'a, String> for DotPrinter {
+    fn visit_assign(&self, expr: &Assign<'a, String>) -> String {
+        let id = self.node("Assign", Some(&*expr.name.lexeme), Some(&expr.name));
+        let value = expr.value.accept(self);
+        self.edge(&id, &value, "value");
+        id
+    }
+
+    fn visit_binary(&self, expr: &Binary<'a, String>) -> String {
+        let id = self.node("Binary", Some(&*expr.operator.lexeme), Some(&expr.operator));
+        let left = expr.left.accept(self);
+        self.edge(&id, &left, "left");
+        let right = expr.right.accept(self);
+        self.edge(&id, &right, "right");
+        id
+    }
+
+    fn visit_call(&self, expr: &Call<'a, String>) -> String {
+        let id = self.node("Call", None, Some(&expr.paren));
+        let callee = expr.callee.accept(self);
+        self.edge(&id, &callee, "callee");
+        for (i, argument) in expr.arguments.iter().enumerate() {
+            let argument = argument.accept(self);
+            self.edge(&id, &argument, &format!("arg {}", i));
+        }
+        id
+    }
+
+    fn visit_get(&self, expr: &Get<'a, String>) -> String {
+        let id = self.node("Get", Some(&*expr.name.lexeme), Some(&expr.name));
+        let object = expr.object.accept(self);
+        self.edge(&id, &object, "object");
+        id
+    }
+
+    fn visit_grouping(&self, expr: &Grouping<'a, String>) -> String {
+        let id = self.node("Grouping", None, None);
+        let inner = expr.expression.accept(self);
+        self.edge(&id, &inner, "expression");
+        id
+    }
+
+    fn visit_index(&self, expr: &Index<'a, String>) -> String {
+        let id = self.node("Index", None, Some(&expr.bracket));
+        let object = expr.object.accept(self);
+        self.edge(&id, &object, "object");
+        let index = expr.index.accept(self);
+        self.edge(&id, &index, "index");
+        id
+    }
+
+    fn visit_list(&self, expr: &List<'a, String>) -> String {
+        let id = self.node("List", None, Some(&expr.bracket));
+        for (i, element) in expr.elements.iter().enumerate() {
+            let element = element.accept(self);
+            self.edge(&id, &element, &format!("element {}", i));
+        }
+        id
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> String {
+        let value = match expr.value {
+            TokenLiteral::Str(ref s) => format!("\"{}\"", s),
+            TokenLiteral::Number(n) => n.to_string(),
+            TokenLiteral::Bool(b) => b.to_string(),
+            TokenLiteral::None => String::from("nil"),
+        };
+        self.node("Literal", Some(&value), None)
+    }
+
+    fn visit_logical(&self, expr: &Logical<'a, String>) -> String {
+        let id = self.node("Logical", Some(&*expr.operator.lexeme), Some(&expr.operator));
+        let left = expr.left.accept(self);
+        self.edge(&id, &left, "left");
+        let right = expr.right.accept(self);
+        self.edge(&id, &right, "right");
+        id
+    }
+
+    fn visit_map(&self, expr: &Map<'a, String>) -> String {
+        let id = self.node("Map", None, Some(&expr.brace));
+        for (i, (key, value)) in expr.keys.iter().zip(&expr.values).enumerate() {
+            let key = key.accept(self);
+            self.edge(&id, &key, &format!("key {}", i));
+            let value = value.accept(self);
+            self.edge(&id, &value, &format!("value {}", i));
+        }
+        id
+    }
+
+    fn visit_set(&self, expr: &Set<'a, String>) -> String {
+        let id = self.node("Set", Some(&*expr.name.lexeme), Some(&expr.name));
+        let object = expr.object.accept(self);
+        self.edge(&id, &object, "object");
+        let value = expr.value.accept(self);
+        self.edge(&id, &value, "value");
+        id
+    }
+
+    fn visit_set_index(&self, expr: &SetIndex<'a, String>) -> String {
+        let id = self.node("SetIndex", None, Some(&expr.bracket));
+        let object = expr.object.accept(self);
+        self.edge(&id, &object, "object");
+        let index = expr.index.accept(self);
+        self.edge(&id, &index, "index");
+        let value = expr.value.accept(self);
+        self.edge(&id, &value, "value");
+        id
+    }
+
+    fn visit_unary(&self, expr: &Unary<'a, String>) -> String {
+        let id = self.node("Unary", Some(&*expr.operator.lexeme), Some(&expr.operator));
+        let right = expr.right.accept(self);
+        self.edge(&id, &right, "right");
+        id
+    }
+
+    fn visit_variable(&self, expr: &Variable) -> String {
+        self.node("Variable", Some(&*expr.name.lexeme), Some(&expr.name))
+    }
+}
+
+impl<'a> StmtVisitor<'a, String> for DotPrinter {
+    fn visit_block(&self, stmt: &Block<'a, String>) -> String {
+        let id = self.node("Block", None, None);
+        self.children(&id, "stmt", &stmt.statements);
+        id
+    }
+
+    fn visit_export(&self, stmt: &Export<'a, String>) -> String {
+        let id = self.node("Export", Some(&*stmt.name.lexeme), Some(&stmt.name));
+        let declaration = stmt.declaration.accept(self);
+        self.edge(&id, &declaration, "declaration");
+        id
+    }
+
+    fn visit_expression(&self, stmt: &Expression<'a, String>) -> String {
+        let id = self.node("Expression", None, None);
+        let expression = stmt.expression.accept(self);
+        self.edge(&id, &expression, "expression");
+        id
+    }
+
+    fn visit_for_in(&self, stmt: &ForIn<'a, String>) -> String {
+        let id = self.node("ForIn", Some(&*stmt.name.lexeme), Some(&stmt.name));
+        let iterable = stmt.iterable.accept(self);
+        self.edge(&id, &iterable, "iterable");
+        let body = stmt.body.accept(self);
+        self.edge(&id, &body, "body");
+        id
+    }
+
+    fn visit_function(&self, stmt: &Function<'a, String>) -> String {
+        let params: Vec<&str> = stmt.params.iter().map(|p| &*p.lexeme).collect();
+        let signature = format!("{}({})", stmt.name.lexeme, params.join(", "));
+        let id = self.node("Function", Some(&signature), Some(&stmt.name));
+        self.children(&id, "body", &stmt.body);
+        id
+    }
+
+    fn visit_if(&self, stmt: &If<'a, String>) -> String {
+        let id = self.node("If", None, None);
+        let condition = stmt.condition.accept(self);
+        self.edge(&id, &condition, "condition");
+        let then_branch = stmt.then_branch.accept(self);
+        self.edge(&id, &then_branch, "then");
+        if let Some(ref else_branch) = stmt.else_branch {
+            let else_branch = else_branch.accept(self);
+            self.edge(&id, &else_branch, "else");
+        }
+        id
+    }
+
+    fn visit_import(&self, stmt: &Import<'a, String>) -> String {
+        let detail = match stmt.alias {
+            Some(ref alias) => format!("{} as {}", stmt.path.lexeme, alias.lexeme),
+            None => {
+                let names: Vec<&str> = stmt.names.iter().map(|n| &*n.lexeme).collect();
+                format!("{}: {}", stmt.path.lexeme, names.join(", "))
+            },
+        };
+        self.node("Import", Some(&detail), Some(&stmt.keyword))
+    }
+
+    fn visit_print(&self, stmt: &Print<'a, String>) -> String {
+        let id = self.node("Print", None, None);
+        let expression = stmt.expression.accept(self);
+        self.edge(&id, &expression, "expression");
+        id
+    }
+
+    fn visit_return(&self, stmt: &Return<'a, String>) -> String {
+        let id = self.node("Return", None, Some(&stmt.keyword));
+        if let Some(ref value) = stmt.value {
+            let value = value.accept(self);
+            self.edge(&id, &value, "value");
+        }
+        id
+    }
+
+    fn visit_throw(&self, stmt: &Throw<'a, String>) -> String {
+        let id = self.node("Throw", None, Some(&stmt.keyword));
+        let value = stmt.value.accept(self);
+        self.edge(&id, &value, "value");
+        id
+    }
+
+    fn visit_try(&self, stmt: &Try<'a, String>) -> String {
+        let id = self.node("Try", None, Some(&stmt.keyword));
+        self.children(&id, "body", &stmt.body);
+        if let Some((ref name, ref handler)) = stmt.handler {
+            let catch = self.node("Catch", Some(&*name.lexeme), Some(name));
+            self.edge(&id, &catch, "catch");
+            self.children(&catch, "body", handler);
+        }
+        if let Some(ref finalizer) = stmt.finalizer {
+            self.children(&id, "finally", finalizer);
+        }
+        id
+    }
+
+    fn visit_var(&self, stmt: &Var<'a, String>) -> String {
+        let id = self.node("Var", Some(&*stmt.name.lexeme), Some(&stmt.name));
+        if let Some(ref initializer) = stmt.initializer {
+            let initializer = initializer.accept(self);
+            self.edge(&id, &initializer, "initializer");
+        }
+        id
+    }
+
+    fn visit_while(&self, stmt: &While<'a, String>) -> String {
+        let id = self.node("While", None, None);
+        let condition = stmt.condition.accept(self);
+        self.edge(&id, &condition, "condition");
+        let body = stmt.body.accept(self);
+        self.edge(&id, &body, "body");
+        id
+    }
+}
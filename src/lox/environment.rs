@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use lox::interner::Symbol;
+use lox::value::Value;
+
+pub type EnvRef = Rc<RefCell<Environment>>;
+
+/// The variables of one scope, chained to the scope enclosing it.
+#[derive(Default)]
+pub struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<EnvRef>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Option<EnvRef>) -> EnvRef {
+        Rc::new(RefCell::new(Environment {
+            values: HashMap::new(),
+            enclosing,
+        }))
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    /// The names defined in this scope itself, in no particular order.
+    pub fn names(&self) -> Vec<Symbol> {
+        self.values.keys().cloned().collect()
+    }
+
+    /// The values defined in this scope itself, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.values()
+    }
+
+    pub fn enclosing(&self) -> Option<&EnvRef> {
+        self.enclosing.as_ref()
+    }
+
+    pub fn get(&self, name: &Symbol) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Assigns to the innermost existing variable; returns whether there was one.
+    pub fn assign(&mut self, name: &Symbol, value: Value) -> bool {
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = value;
+            return true;
+        }
+
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! The values `catch` clauses bind, and the errors `throw` statements raise.
+//!
+//! A `throw` statement may throw any value, and `catch` hands that value
+//! back unchanged. Errors raised by the interpreter itself, like an
+//! undefined variable, are caught as `Error` objects with a `message` and
+//! the `line` they happened on.
+
+use lox::value::*;
+
+/// A runtime error of the interpreter, caught by a script.
+pub struct ErrorObject {
+    message: String,
+    line: Option<usize>,
+}
+
+impl NativeObject for ErrorObject {
+    fn class_name(&self) -> &str {
+        "Error"
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "message" => Some(Value::from(self.message.as_str())),
+            "line" => Some(Value::from(self.line.map(|line| line as f64))),
+            _ => None,
+        }
+    }
+}
+
+/// The error a `throw` statement on `line` raises. Error objects that are
+/// thrown again keep their message.
+pub fn thrown(value: Value, line: usize) -> RuntimeError {
+    let message = match value {
+        Value::Object(ref object) if object.borrow().class_name() == "Error" => {
+            object.borrow().get("message").map(|message| message.to_string())
+        },
+        _ => None,
+    };
+
+    let mut error = RuntimeError::at(message.unwrap_or_else(|| value.to_string()), line);
+    error.value = Some(value);
+    error
+}
+
+/// The value a `catch` clause binds for `error`.
+pub fn caught(error: RuntimeError) -> Value {
+    match error.value {
+        Some(value) => value,
+        None => Value::object(ErrorObject { message: error.message, line: error.line }),
+    }
+}
@@ -1,29 +1,88 @@
+#![allow(clippy::new_ret_no_self)]
+
 use std::rc::*;
 use lox::token::Token;
 use lox::token::TokenLiteral;
+use lox::interner::*;
 
-pub type ExprRef<'a, T> = Rc<Expr<'a, T> + 'a>;
+pub type ExprRef<'a, T> = Rc<dyn Expr<'a, T> + 'a>;
 
 pub trait Visitor<'a, T: 'a> {
+    fn visit_assign(&self, expr: &Assign<'a, T>) -> T;
     fn visit_binary(&self, expr: &Binary<'a, T>) -> T;
+    fn visit_call(&self, expr: &Call<'a, T>) -> T;
+    fn visit_get(&self, expr: &Get<'a, T>) -> T;
     fn visit_grouping(&self, expr: &Grouping<'a, T>) -> T;
+    fn visit_index(&self, expr: &Index<'a, T>) -> T;
+    fn visit_list(&self, expr: &List<'a, T>) -> T;
     fn visit_literal(&self, expr: &Literal) -> T;
+    fn visit_logical(&self, expr: &Logical<'a, T>) -> T;
+    fn visit_map(&self, expr: &Map<'a, T>) -> T;
+    fn visit_set(&self, expr: &Set<'a, T>) -> T;
+    fn visit_set_index(&self, expr: &SetIndex<'a, T>) -> T;
     fn visit_unary(&self, expr: &Unary<'a, T>) -> T;
+    fn visit_variable(&self, expr: &Variable) -> T;
 }
 
 pub trait Expr<'a, T: 'a> {
-    fn accept(&self, visitor: &Visitor<'a, T>) -> T;
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T;
+
+    /// What assigning to this expression would change, if it can be assigned to.
+    fn target(&self) -> Option<Target<'a, T>> {
+        None
+    }
+
+    /// The line of the token this expression is made around, if it has one.
+    fn line(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The left-hand side of an assignment.
+pub enum Target<'a, T: 'a> {
+    Variable(Rc<Token>),
+    Property(ExprRef<'a, T>, Rc<Token>),
+    /// The object, the closing bracket and the index.
+    Index(ExprRef<'a, T>, Rc<Token>, ExprRef<'a, T>),
+}
+
+
+pub struct Assign<'a, T: 'a> {
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    pub value: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Assign<'a, T> {
+    pub fn new(name: Rc<Token>, value: ExprRef<'a, T>) -> ExprRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Assign {
+            name,
+            symbol,
+            value,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Assign<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_assign(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.name.line)
+    }
 }
 
 
 pub struct Binary<'a, T: 'a> {
     pub left: ExprRef<'a, T>,
-    pub operator: Rc<Token<'a>>,
+    pub operator: Rc<Token>,
     pub right: ExprRef<'a, T>,
 }
 
 impl<'a, T: 'a> Binary<'a, T> {
-    pub fn new(left: ExprRef<'a, T>, operator: Rc<Token<'a>>, right: ExprRef<'a, T>) -> ExprRef<'a, T> {
+    pub fn new(left: ExprRef<'a, T>, operator: Rc<Token>, right: ExprRef<'a, T>) -> ExprRef<'a, T> {
         Rc::new(Binary {
             left,
             operator,
@@ -33,9 +92,72 @@ impl<'a, T: 'a> Binary<'a, T> {
 }
 
 impl<'a, T> Expr<'a, T> for Binary<'a, T> {
-    fn accept(&self, visitor: &Visitor<'a, T>) -> T {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
         (*visitor).visit_binary(self)
     }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.operator.line)
+    }
+}
+
+
+pub struct Call<'a, T: 'a> {
+    pub callee: ExprRef<'a, T>,
+    pub paren: Rc<Token>,
+    pub arguments: Vec<ExprRef<'a, T>>,
+}
+
+impl<'a, T: 'a> Call<'a, T> {
+    pub fn new(callee: ExprRef<'a, T>, paren: Rc<Token>, arguments: Vec<ExprRef<'a, T>>) -> ExprRef<'a, T> {
+        Rc::new(Call {
+            callee,
+            paren,
+            arguments,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Call<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_call(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.paren.line)
+    }
+}
+
+
+pub struct Get<'a, T: 'a> {
+    pub object: ExprRef<'a, T>,
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+}
+
+impl<'a, T: 'a> Get<'a, T> {
+    pub fn new(object: ExprRef<'a, T>, name: Rc<Token>) -> ExprRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Get {
+            object,
+            name,
+            symbol,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Get<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_get(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.name.line)
+    }
+
+    fn target(&self) -> Option<Target<'a, T>> {
+        Some(Target::Property(Rc::clone(&self.object), Rc::clone(&self.name)))
+    }
 }
 
 
@@ -50,12 +172,70 @@ impl<'a, T: 'a> Grouping<'a, T> {
 }
 
 impl<'a, T> Expr<'a, T> for Grouping<'a, T> {
-    fn accept(&self, visitor: &Visitor<'a, T>) -> T {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
         (*visitor).visit_grouping(self)
     }
 }
 
 
+/// `object[index]`; `bracket` is the closing bracket, used to report errors.
+pub struct Index<'a, T: 'a> {
+    pub object: ExprRef<'a, T>,
+    pub bracket: Rc<Token>,
+    pub index: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Index<'a, T> {
+    pub fn new(object: ExprRef<'a, T>, bracket: Rc<Token>, index: ExprRef<'a, T>) -> ExprRef<'a, T> {
+        Rc::new(Index {
+            object,
+            bracket,
+            index,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Index<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_index(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.bracket.line)
+    }
+
+    fn target(&self) -> Option<Target<'a, T>> {
+        Some(Target::Index(Rc::clone(&self.object), Rc::clone(&self.bracket), Rc::clone(&self.index)))
+    }
+}
+
+
+/// A list literal such as `[1, 2, 3]`.
+pub struct List<'a, T: 'a> {
+    pub bracket: Rc<Token>,
+    pub elements: Vec<ExprRef<'a, T>>,
+}
+
+impl<'a, T: 'a> List<'a, T> {
+    pub fn new(bracket: Rc<Token>, elements: Vec<ExprRef<'a, T>>) -> ExprRef<'a, T> {
+        Rc::new(List {
+            bracket,
+            elements,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for List<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_list(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.bracket.line)
+    }
+}
+
+
 pub struct Literal {
     pub value: TokenLiteral,
 }
@@ -67,19 +247,134 @@ impl<'a> Literal {
 }
 
 impl<'a, T: 'a> Expr<'a, T> for Literal {
-    fn accept(&self, visitor: &Visitor<'a, T>) -> T {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
         (*visitor).visit_literal(self)
     }
 }
 
 
+pub struct Logical<'a, T: 'a> {
+    pub left: ExprRef<'a, T>,
+    pub operator: Rc<Token>,
+    pub right: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Logical<'a, T> {
+    pub fn new(left: ExprRef<'a, T>, operator: Rc<Token>, right: ExprRef<'a, T>) -> ExprRef<'a, T> {
+        Rc::new(Logical {
+            left,
+            operator,
+            right,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Logical<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_logical(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.operator.line)
+    }
+}
+
+
+/// A map literal such as `{"a": 1}`; `keys[i]` maps to `values[i]`.
+pub struct Map<'a, T: 'a> {
+    pub brace: Rc<Token>,
+    pub keys: Vec<ExprRef<'a, T>>,
+    pub values: Vec<ExprRef<'a, T>>,
+}
+
+impl<'a, T: 'a> Map<'a, T> {
+    pub fn new(brace: Rc<Token>, keys: Vec<ExprRef<'a, T>>, values: Vec<ExprRef<'a, T>>) -> ExprRef<'a, T> {
+        Rc::new(Map {
+            brace,
+            keys,
+            values,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Map<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_map(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.brace.line)
+    }
+}
+
+
+pub struct Set<'a, T: 'a> {
+    pub object: ExprRef<'a, T>,
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    pub value: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Set<'a, T> {
+    pub fn new(object: ExprRef<'a, T>, name: Rc<Token>, value: ExprRef<'a, T>) -> ExprRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Set {
+            object,
+            name,
+            symbol,
+            value,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for Set<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_set(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.name.line)
+    }
+}
+
+
+/// `object[index] = value`.
+pub struct SetIndex<'a, T: 'a> {
+    pub object: ExprRef<'a, T>,
+    pub bracket: Rc<Token>,
+    pub index: ExprRef<'a, T>,
+    pub value: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> SetIndex<'a, T> {
+    pub fn new(object: ExprRef<'a, T>, bracket: Rc<Token>, index: ExprRef<'a, T>, value: ExprRef<'a, T>) -> ExprRef<'a, T> {
+        Rc::new(SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+        })
+    }
+}
+
+impl<'a, T> Expr<'a, T> for SetIndex<'a, T> {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_set_index(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.bracket.line)
+    }
+}
+
+
 pub struct Unary<'a, T: 'a> {
-    pub operator: Rc<Token<'a>>,
+    pub operator: Rc<Token>,
     pub right: ExprRef<'a, T>,
 }
 
 impl<'a, T: 'a> Unary<'a, T> {
-    pub fn new(operator: Rc<Token<'a>>, right: ExprRef<'a, T>) -> ExprRef<'a, T> {
+    pub fn new(operator: Rc<Token>, right: ExprRef<'a, T>) -> ExprRef<'a, T> {
         Rc::new(Unary {
             operator,
             right,
@@ -88,7 +383,42 @@ impl<'a, T: 'a> Unary<'a, T> {
 }
 
 impl<'a, T: 'a> Expr<'a, T> for Unary<'a, T> {
-    fn accept(&self, visitor: &Visitor<'a, T>) -> T {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
         (*visitor).visit_unary(self)
     }
-}
\ No newline at end of file
+
+    fn line(&self) -> Option<usize> {
+        Some(self.operator.line)
+    }
+}
+
+
+pub struct Variable {
+    pub name: Rc<Token>,
+    /// The interned name, so lookups compare pointers.
+    pub symbol: Symbol,
+}
+
+impl Variable {
+    pub fn new<'a, T: 'a>(name: Rc<Token>) -> ExprRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Variable {
+            name,
+            symbol,
+        })
+    }
+}
+
+impl<'a, T: 'a> Expr<'a, T> for Variable {
+    fn accept(&self, visitor: &dyn Visitor<'a, T>) -> T {
+        (*visitor).visit_variable(self)
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.name.line)
+    }
+
+    fn target(&self) -> Option<Target<'a, T>> {
+        Some(Target::Variable(Rc::clone(&self.name)))
+    }
+}
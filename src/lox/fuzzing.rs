@@ -0,0 +1,396 @@
+//! Random Lox programs, and the checks the fuzz targets in `fuzz/` run on them.
+//!
+//! `generate` turns arbitrary bytes into a program that always parses: the
+//! bytes only pick between productions of the grammar, and running out of
+//! them picks the first, simplest one. Generated programs always finish,
+//! as loops are counted and functions only call functions declared before
+//! them, so they can't recurse.
+//!
+//! `round_trip` prints a program with `SourcePrinter`, parses the output
+//! and checks that it gives the same tree, and that both versions run to
+//! the same result.
+
+use lox::ast_printer::AstPrinter;
+use lox::parser::Parser;
+use lox::scanner::Scanner;
+use lox::source_printer::SourcePrinter;
+use lox::stmt::StmtRef;
+use lox::limits::Limits;
+use lox::Lox;
+use lox::LoadError;
+
+/// How deeply expressions and statements nest.
+const MAX_DEPTH: usize = 4;
+
+const NUMBERS: [&str; 8] = ["0", "1", "2", "3", "0.5", "10", "1000000", "123456789012345678901234567890"];
+const STRINGS: [&str; 6] = ["\"\"", "\"a\"", "\"lox\"", "\"héllo\"", "\"1.5\"", "\"a,b\""];
+const BINARY: [&str; 12] = ["+", "-", "*", "/", "%", "**", "==", "!=", "<", "<=", ">", ">="];
+/// Natives and their arities. `clock` and `random` are left out, so that
+/// programs always give the same results.
+const NATIVES: [(&str, usize); 8] = [
+    ("floor", 1), ("sqrt", 1), ("abs", 1), ("max", 2), ("pow", 2),
+    ("assert", 2), ("assertEqual", 2), ("assertThrows", 1),
+];
+/// Methods of strings, numbers, lists and maps, and their arities.
+const METHODS: [(&str, usize); 16] = [
+    ("len", 0), ("upper", 0), ("chars", 0), ("toString", 0), ("toNumber", 0), ("indexOf", 1),
+    ("split", 1), ("join", 1), ("substring", 2), ("push", 1), ("pop", 0), ("insert", 2),
+    ("slice", 2), ("keys", 0), ("has", 1), ("delete", 1),
+];
+/// Methods taking a function, with how many arguments they call it with.
+const CALLBACKS: [(&str, usize); 3] = [("map", 1), ("filter", 1), ("sort", 2)];
+
+/// The names a part of a program can use.
+#[derive(Clone, Default)]
+struct Scope {
+    variables: Vec<String>,
+    /// Variables that are never assigned, like loop counters.
+    constants: Vec<String>,
+    /// Functions and their arities.
+    functions: Vec<(String, usize)>,
+}
+
+pub struct Generator<'d> {
+    data: &'d [u8],
+    position: usize,
+    depth: usize,
+    /// How many loops enclose the current statement.
+    loops: usize,
+    in_function: bool,
+    scope: Scope,
+    names: usize,
+}
+
+/// A program picked by `data`.
+pub fn generate(data: &[u8]) -> String {
+    Generator::new(data).program()
+}
+
+impl<'d> Generator<'d> {
+    pub fn new(data: &'d [u8]) -> Generator<'d> {
+        Generator {
+            data,
+            position: 0,
+            depth: 0,
+            loops: 0,
+            in_function: false,
+            scope: Scope::default(),
+            names: 0,
+        }
+    }
+
+    pub fn program(&mut self) -> String {
+        let mut statements = Vec::new();
+        while self.position < self.data.len() {
+            statements.push(self.declaration(true));
+        }
+        statements.join("\n")
+    }
+
+    /// A number below `n`, 0 once the data is used up.
+    fn choose(&mut self, n: usize) -> usize {
+        let byte = self.data.get(self.position).cloned().unwrap_or(0);
+        self.position += 1;
+        byte as usize % n
+    }
+
+    fn pick<'s>(&mut self, choices: &[&'s str]) -> &'s str {
+        choices[self.choose(choices.len())]
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn nested<F: FnOnce(&mut Self) -> String>(&mut self, f: F) -> String {
+        let scope = self.scope.clone();
+        self.depth += 1;
+        let s = f(self);
+        self.depth -= 1;
+        self.scope = scope;
+        s
+    }
+
+    fn declaration(&mut self, top_level: bool) -> String {
+        match self.choose(10) {
+            0 | 1 => {
+                let value = self.expression();
+                let name = self.name("v");
+                self.scope.variables.push(name.clone());
+                let export = if top_level && self.choose(4) == 0 { "export " } else { "" };
+                format!("{}var {} = {};", export, name, value)
+            },
+            2 if self.depth < MAX_DEPTH => self.function(),
+            _ => self.statement(),
+        }
+    }
+
+    fn function(&mut self) -> String {
+        let name = self.name("f");
+        let arity = self.choose(3);
+        let params: Vec<String> = (0..arity).map(|_| self.name("p")).collect();
+
+        let loops = self.loops;
+        let in_function = self.in_function;
+        let body = self.nested(|g| {
+            g.loops = 0;
+            g.in_function = true;
+            g.scope.variables.extend(params.iter().cloned());
+            g.block()
+        });
+        self.loops = loops;
+        self.in_function = in_function;
+
+        // Only added now, so that the function can't call itself.
+        self.scope.functions.push((name.clone(), arity));
+        format!("fun {}({}) {}", name, params.join(", "), body)
+    }
+
+    fn block(&mut self) -> String {
+        let count = self.choose(4);
+        let statements: Vec<String> = (0..count).map(|_| self.declaration(false)).collect();
+        format!("{{\n{}\n}}", statements.join("\n"))
+    }
+
+    fn statement(&mut self) -> String {
+        if self.depth >= MAX_DEPTH {
+            return self.expression_statement();
+        }
+
+        match self.choose(12) {
+            0 => format!("print {};", self.expression()),
+            1 => {
+                let condition = self.expression();
+                let then_branch = self.nested(Self::statement);
+                if self.choose(2) == 0 {
+                    format!("if ({}) {}", condition, then_branch)
+                } else {
+                    format!("if ({}) {} else {}", condition, then_branch, self.nested(Self::statement))
+                }
+            },
+            2 if self.loops < 2 => {
+                let counter = self.name("i");
+                let count = self.choose(4);
+                let body = self.nested(|g| {
+                    g.loops += 1;
+                    g.scope.constants.push(counter.clone());
+                    let body = g.statement();
+                    g.loops -= 1;
+                    body
+                });
+                format!("for (var {0} = 0; {0} < {1}; {0} = {0} + 1) {2}", counter, count, body)
+            },
+            3 if self.loops < 2 => {
+                let item = self.name("x");
+                let iterable = self.expression();
+                let body = self.nested(|g| {
+                    g.loops += 1;
+                    g.scope.variables.push(item.clone());
+                    let body = g.statement();
+                    g.loops -= 1;
+                    body
+                });
+                format!("for (var {} in {}) {}", item, iterable, body)
+            },
+            4 => self.nested(Self::block),
+            5 if self.in_function => match self.choose(3) {
+                0 => String::from("return;"),
+                _ => format!("return {};", self.expression()),
+            },
+            6 => format!("throw {};", self.expression()),
+            7 => {
+                let body = self.nested(Self::block);
+                let error = self.name("e");
+                let handler = self.nested(|g| {
+                    g.scope.variables.push(error.clone());
+                    g.block()
+                });
+                match self.choose(3) {
+                    0 => format!("try {} catch ({}) {}", body, error, handler),
+                    1 => format!("try {} finally {}", body, self.nested(Self::block)),
+                    _ => format!("try {} catch ({}) {} finally {}", body, error, handler, self.nested(Self::block)),
+                }
+            },
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn expression_statement(&mut self) -> String {
+        let expression = self.expression();
+        // A statement starting with a brace is a block, not a map.
+        if expression.starts_with('{') {
+            format!("({});", expression)
+        } else {
+            format!("{};", expression)
+        }
+    }
+
+    /// An expression in parentheses unless it is a primary expression, so
+    /// that it can be used as an operand of anything.
+    fn expression(&mut self) -> String {
+        if self.depth >= MAX_DEPTH {
+            return self.primary();
+        }
+
+        self.nested(|g| match g.choose(14) {
+            0 => {
+                let left = g.expression();
+                let operator = g.pick(&BINARY);
+                format!("({} {} {})", left, operator, g.expression())
+            },
+            1 => {
+                let left = g.expression();
+                let operator = g.pick(&["and", "or"]);
+                format!("({} {} {})", left, operator, g.expression())
+            },
+            2 => format!("({}{})", g.pick(&["-", "!"]), g.expression()),
+            3 => {
+                let count = g.choose(4);
+                let elements: Vec<String> = (0..count).map(|_| g.expression()).collect();
+                format!("[{}]", elements.join(", "))
+            },
+            4 => {
+                let count = g.choose(3);
+                let entries: Vec<String> = (0..count).map(|_| {
+                    let key = g.primary();
+                    format!("{}: {}", key, g.expression())
+                }).collect();
+                format!("{{{}}}", entries.join(", "))
+            },
+            5 => {
+                let object = g.expression();
+                format!("{}[{}]", object, g.expression())
+            },
+            6 => {
+                let (method, arity) = METHODS[g.choose(METHODS.len())];
+                let object = g.expression();
+                format!("{}.{}({})", object, method, g.arguments(arity))
+            },
+            7 if !g.scope.functions.is_empty() => {
+                let (method, arity) = CALLBACKS[g.choose(CALLBACKS.len())];
+                let functions: Vec<String> = g.scope.functions.iter().filter(|f| f.1 == arity).map(|f| f.0.clone()).collect();
+                if functions.is_empty() {
+                    return g.primary();
+                }
+                let function = functions[g.choose(functions.len())].clone();
+                format!("{}.{}({})", g.expression(), method, function)
+            },
+            8 => {
+                let (native, arity) = NATIVES[g.choose(NATIVES.len())];
+                if native == "assertThrows" {
+                    match g.scope.functions.iter().find(|f| f.1 == 0) {
+                        Some((function, _)) => format!("assertThrows({})", function),
+                        None => g.primary(),
+                    }
+                } else {
+                    format!("{}({})", native, g.arguments(arity))
+                }
+            },
+            9 if !g.scope.functions.is_empty() => {
+                let i = g.choose(g.scope.functions.len());
+                let (function, arity) = g.scope.functions[i].clone();
+                // Sometimes with the wrong number of arguments.
+                let count = if g.choose(8) == 0 { arity + 1 } else { arity };
+                format!("{}({})", function, g.arguments(count))
+            },
+            10 if !g.scope.variables.is_empty() => {
+                let i = g.choose(g.scope.variables.len());
+                let variable = g.scope.variables[i].clone();
+                match g.choose(3) {
+                    0 => {
+                        let index = g.expression();
+                        format!("({}[{}] = {})", variable, index, g.expression())
+                    },
+                    _ => format!("({} = {})", variable, g.expression()),
+                }
+            },
+            _ => g.primary(),
+        })
+    }
+
+    fn arguments(&mut self, count: usize) -> String {
+        let arguments: Vec<String> = (0..count).map(|_| self.expression()).collect();
+        arguments.join(", ")
+    }
+
+    fn primary(&mut self) -> String {
+        let names = self.scope.variables.len() + self.scope.constants.len();
+        match self.choose(6) {
+            0 => self.pick(&NUMBERS).to_string(),
+            1 => self.pick(&STRINGS).to_string(),
+            2 => self.pick(&["true", "false", "nil"]).to_string(),
+            _ if names > 0 => {
+                let i = self.choose(names);
+                match self.scope.variables.get(i) {
+                    Some(variable) => variable.clone(),
+                    None => self.scope.constants[i - self.scope.variables.len()].clone(),
+                }
+            },
+            _ => self.pick(&NUMBERS).to_string(),
+        }
+    }
+}
+
+/// Checks that printing the program in `source` with `SourcePrinter` gives
+/// source for the same tree, which runs to the same result.
+pub fn round_trip(source: &str) -> Result<(), String> {
+    let (tree, printed) = parse_and_print(source)?;
+    let (reparsed, _) = parse_and_print(&printed).map_err(|e| format!("{}, printed as:\n{}", e, printed))?;
+    if tree != reparsed {
+        return Result::Err(format!("Printing changed the tree from\n{}\nto\n{}", tree, reparsed));
+    }
+
+    let (before, after) = (run(source), run(&printed));
+    if before != after {
+        return Result::Err(format!("The program ran to\n{}\nbut once printed to\n{}", before, after));
+    }
+    Result::Ok(())
+}
+
+/// What fuzzed scripts may use. They are stopped by the number of steps
+/// rather than by time, so a script always stops at the same point.
+pub fn limits() -> Limits {
+    Limits {
+        max_steps: Some(100_000),
+        max_call_depth: 100,
+        max_nesting: 2000,
+        max_heap_bytes: Some(1 << 20),
+        max_string_length: Some(1 << 16),
+        ..Limits::default()
+    }
+}
+
+/// The program as an s-expression and as printed by `SourcePrinter`.
+fn parse_and_print(source: &str) -> Result<(String, String), String> {
+    let mut errors = Vec::new();
+    let tokens = Scanner::new(source).scan_tokens(|line, message| errors.push(format!("[line {}] {}", line, message))).clone();
+    let program: Option<Vec<StmtRef<String>>> = Parser::new(&tokens)
+        .parse_program(|token, message| errors.push(format!("[line {}] at '{}': {}", token.line, token.lexeme, message)));
+
+    match program {
+        Some(ref program) if errors.is_empty() => {
+            Result::Ok((AstPrinter {}.print_program(program), SourcePrinter {}.print_program(program)))
+        },
+        _ => Result::Err(format!("The program doesn't parse: {}", errors.join(", "))),
+    }
+}
+
+/// How running `source` ended and the globals it left. Lines are left out,
+/// since printing moves code to other lines.
+fn run(source: &str) -> String {
+    let mut lox = Lox::new();
+    lox.set_io(false);
+    lox.set_limits(limits());
+
+    let mut outcome = match lox.load(source) {
+        Result::Ok(()) => String::from("ok"),
+        Result::Err(LoadError::Syntax) => String::from("syntax error"),
+        Result::Err(LoadError::Runtime(e)) => format!("error: {}", e.message),
+    };
+    for name in lox.global_names() {
+        let value = lox.get_global(&name).map(|value| value.to_string()).unwrap_or_default();
+        outcome += &format!("\n{} = {}", name, value);
+    }
+    outcome
+}
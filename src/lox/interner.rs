@@ -0,0 +1,122 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// A string stored once in the global string table.
+///
+/// Symbols with the same contents share one allocation, so cloning only
+/// bumps a reference count and equality and hashing only look at the
+/// pointer. Ordering still compares the contents.
+#[derive(Clone)]
+pub struct Symbol(Rc<String>);
+
+/// Entry of the string table, hashed and compared by contents so it can be
+/// looked up with a `&str`.
+#[derive(PartialEq, Eq, Hash)]
+struct Entry(Rc<String>);
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+struct Interner {
+    strings: HashSet<Entry>,
+    prune_at: usize,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+thread_local! {
+    // `Rc` is not `Send`, so every thread gets its own table.
+    static STRINGS: RefCell<Interner> = RefCell::new(Interner {
+        strings: HashSet::new(),
+        prune_at: MIN_PRUNE_AT,
+    });
+}
+
+/// Returns the symbol for `s`, adding it to the string table if needed.
+pub fn intern(s: &str) -> Symbol {
+    STRINGS.with(|table| {
+        let mut table = table.borrow_mut();
+
+        if let Some(existing) = table.strings.get(s) {
+            return Symbol(Rc::clone(&existing.0));
+        }
+
+        // Drop strings nobody but the table refers to any more before the
+        // table outgrows the live ones.
+        if table.strings.len() >= table.prune_at {
+            table.strings.retain(|entry| Rc::strong_count(&entry.0) > 1);
+            table.prune_at = MIN_PRUNE_AT.max(table.strings.len() * 2);
+        }
+
+        let string = Rc::new(String::from(s));
+        table.strings.insert(Entry(Rc::clone(&string)));
+        Symbol(string)
+    })
+}
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Symbol> for Rc<String> {
+    fn from(symbol: Symbol) -> Rc<String> {
+        symbol.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as usize).hash(state);
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
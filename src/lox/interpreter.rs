@@ -1,100 +1,912 @@
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::rc::*;
 use lox::token::*;
 use lox::expr::*;
+use lox::stmt::*;
+use lox::environment::*;
+use lox::cycles;
+use lox::interner::*;
+use lox::value::*;
+use lox::strings;
+use lox::lists;
+use lox::maps;
+use lox::errors;
+use lox::modules::{self, Module, Modules};
+use lox::limits::Budget;
+use lox::profiler::{Profile, Profiler};
 
-pub struct Interpreter;
+/// The Rust stack left when the tree-walker grows it, more than one level
+/// of nesting takes in debug builds, and how much it adds each time.
+const STACK_RED_ZONE: usize = 128 * 1024;
+const STACK_GROWTH: usize = 2 * 1024 * 1024;
 
-type InterpRes<'a> = Result<TokenLiteral, (&'static str, Rc<Token<'a>>)>;
+/// Why evaluation stopped before reaching the end of a statement.
+pub enum Unwind {
+    Error(RuntimeError),
+    /// A `return` statement on its way to the enclosing call.
+    Return(Value),
+}
 
-impl<'a> Interpreter {
-    fn evaluate(&self, expr: ExprRef<'a, InterpRes<'a>>) -> InterpRes<'a> {
-        expr.accept(self)
+impl Unwind {
+    fn error<S: Into<String>>(message: S, token: &Token) -> Unwind {
+        Unwind::Error(RuntimeError::at(message, token.line))
     }
+}
 
-    fn is_truthy(&self, literal: TokenLiteral) -> bool {
-        match literal {
-            TokenLiteral::None => false,
-            TokenLiteral::Bool(b) => b,
-            _ => true,
+pub type InterpRes = Result<Value, Unwind>;
+
+/// Functions keep their declaration around for as long as they may be
+/// called. Trees own their tokens, so they can outlive the script they
+/// were parsed from.
+pub type Program = Vec<StmtRef<'static, InterpRes>>;
+
+/// A function declared in Lox, together with the scope it was declared in.
+pub struct LoxFunction {
+    pub name: Symbol,
+    /// The file the function is declared in, if it came from a file.
+    pub file: Option<Rc<String>>,
+    params: Vec<Symbol>,
+    body: Rc<Program>,
+    closure: EnvRef,
+}
+
+impl LoxFunction {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn closure(&self) -> &EnvRef {
+        &self.closure
+    }
+
+    fn call(&self, interpreter: &Interpreter, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let environment = Environment::new(Some(Rc::clone(&self.closure)));
+        for (param, argument) in self.params.iter().zip(arguments) {
+            environment.borrow_mut().define(param.clone(), argument.clone());
+        }
+
+        match interpreter.execute_block(&self.body, environment) {
+            Result::Ok(_) => Result::Ok(Value::Nil),
+            Result::Err(Unwind::Return(value)) => Result::Ok(value),
+            Result::Err(Unwind::Error(e)) => Result::Err(e),
         }
     }
+}
 
-    pub fn interpret<F>(&self, expr: ExprRef<'a, InterpRes<'a>>, mut err: F)
-    where F: FnMut(Rc<Token<'a>>, &'static str) {
-        match self.evaluate(expr) {
-            Result::Ok(token_literal) => {
-                println!("{:?}", token_literal);
-            },
-            Result::Err(e) => {
-                err(e.1, e.0);
+/// A call that hasn't returned yet, or a module being run.
+struct Activation {
+    /// The function called, `None` for the top level of a module.
+    function: Option<Symbol>,
+    native: bool,
+    file: Option<Rc<String>>,
+    /// The line of the call or import, `None` when called from Rust.
+    called_from: Option<usize>,
+    /// The scope of the caller, which still holds values while the call runs.
+    environment: EnvRef,
+}
+
+pub struct Interpreter {
+    /// Natives and other globals defined by the embedder, visible in every module.
+    builtins: EnvRef,
+    /// The globals of the main script.
+    globals: EnvRef,
+    environment: RefCell<EnvRef>,
+    modules: RefCell<Modules>,
+    /// The calls and imports being run, outermost first.
+    activations: RefCell<Vec<Activation>>,
+    /// The line of the innermost call expression being evaluated.
+    line: Cell<Option<usize>>,
+    /// How many expressions and statements are being evaluated, across all
+    /// running calls; each of them takes some of the Rust stack.
+    nesting: Cell<usize>,
+    budget: Budget,
+    profiler: Option<RefCell<Profiler>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Drop for Interpreter {
+    /// Lets go of the globals and modules, then frees the cycles they were
+    /// part of, like every function declared at the top level of a file.
+    fn drop(&mut self) {
+        let builtins = mem::replace(&mut self.builtins, Environment::new(None));
+        let globals = mem::replace(&mut self.globals, Environment::new(None));
+        let environment = mem::replace(self.environment.get_mut(), Environment::new(None));
+        let modules = mem::take(self.modules.get_mut());
+        let activations = mem::take(self.activations.get_mut());
+        drop((builtins, globals, environment, modules, activations));
+        cycles::collect();
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let builtins = Environment::new(None);
+        let globals = Environment::new(Some(Rc::clone(&builtins)));
+        Interpreter {
+            builtins,
+            environment: RefCell::new(Rc::clone(&globals)),
+            globals,
+            modules: RefCell::new(Modules::default()),
+            activations: RefCell::new(Vec::new()),
+            line: Cell::new(None),
+            nesting: Cell::new(0),
+            budget: Budget::default(),
+            profiler: None,
+        }
+    }
+
+    /// Sets the limits later scripts and calls run with.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// Profiles later scripts and calls with `profiler`, or stops profiling.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(RefCell::new);
+    }
+
+    /// What the profiler measured so far, if profiling.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(|profiler| profiler.borrow().profile())
+    }
+
+    /// Gives the next script or call all of its steps and time.
+    pub fn start(&self) {
+        self.budget.start();
+    }
+
+    /// Defines a global visible to the main script and every module.
+    pub fn define(&self, name: Symbol, value: Value) {
+        self.builtins.borrow_mut().define(name, value);
+    }
+
+    pub fn modules(&self) -> RefMut<'_, Modules> {
+        self.modules.borrow_mut()
+    }
+
+    pub fn get_global(&self, name: &Symbol) -> Option<Value> {
+        self.globals.borrow().get(name)
+    }
+
+    /// The names of the globals the main script defined.
+    pub fn global_names(&self) -> Vec<Symbol> {
+        self.globals.borrow().names()
+    }
+
+    /// Runs a program in the scope of the natives, so what it declares is
+    /// visible to the main script and every module.
+    pub fn define_builtins(&self, program: &[StmtRef<'static, InterpRes>]) -> Result<(), RuntimeError> {
+        match self.execute_block(program, Rc::clone(&self.builtins)) {
+            Result::Err(Unwind::Error(e)) => Result::Err(e),
+            _ => Result::Ok(()),
+        }
+    }
+
+    /// Runs the statements of a program. Globals they define stay around
+    /// for later programs and calls.
+    pub fn interpret(&self, program: &[StmtRef<'static, InterpRes>]) -> Result<(), RuntimeError> {
+        let main = self.modules().main_file();
+        self.profiled(None, false, main, || {
+            for statement in program {
+                match self.execute(statement) {
+                    Result::Ok(_) => {},
+                    Result::Err(Unwind::Error(e)) => return Result::Err(self.traced(e)),
+                    // The parser rejects `return` outside of functions.
+                    Result::Err(Unwind::Return(_)) => return Result::Ok(()),
+                }
             }
+
+            Result::Ok(())
+        })
+    }
+
+    /// Evaluates a script consisting of a single expression.
+    pub fn interpret_expression(&self, expr: ExprRef<'static, InterpRes>) -> Result<Value, RuntimeError> {
+        let main = self.modules().main_file();
+        match self.profiled(None, false, main, || self.evaluate(expr)) {
+            Result::Ok(value) => Result::Ok(value),
+            Result::Err(Unwind::Error(e)) => Result::Err(self.traced(e)),
+            Result::Err(Unwind::Return(value)) => Result::Ok(value),
         }
     }
-}
 
-impl<'a> Visitor<'a, InterpRes<'a>> for Interpreter {
-    fn visit_binary(&self, expr: &Binary<'a, InterpRes<'a>>) -> InterpRes<'a> {
-        let right = self.evaluate(Rc::clone(&expr.right))?;
-        let left = self.evaluate(Rc::clone(&expr.left))?;
+    /// Calls a function value. Errors about the call itself have no line.
+    pub fn call(&self, callee: &Value, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let arity = match *callee {
+            Value::Native(ref native) => native.arity,
+            Value::Function(ref function) => function.arity(),
+            Value::Method(ref method) => method.arity,
+            _ => return Result::Err(RuntimeError::new("Can only call functions and classes.")),
+        };
+
+        if arguments.len() != arity {
+            return Result::Err(RuntimeError::new(format!("Expected {} arguments but got {}.", arity, arguments.len())));
+        }
+
+        let (name, native, file) = match *callee {
+            Value::Native(ref native) => (native.name.clone(), true, None),
+            Value::Function(ref function) => (function.name.clone(), false, function.file.clone()),
+            Value::Method(ref method) => (method.name.clone(), true, None),
+            _ => unreachable!(),
+        };
+        self.budget.check_depth(self.activations.borrow().len() + 1)?;
+        self.activations.borrow_mut().push(Activation {
+            function: Some(name.clone()),
+            native,
+            file: file.clone(),
+            called_from: self.line.get(),
+            environment: Rc::clone(&self.environment.borrow()),
+        });
+
+        let result = self.profiled(Some(&name), native, file, || match *callee {
+            Value::Native(ref native) => (native.function)(arguments),
+            Value::Function(ref function) => function.call(self, arguments),
+            Value::Method(ref method) => Interpreter::call_method(method, arguments, |f, a| self.call(f, a)),
+            _ => unreachable!(),
+        });
+
+        let result = result.map_err(|e| self.traced(e));
+        self.activations.borrow_mut().pop();
+        result
+    }
+
+    /// Records the calls and imports `error` is leaving, innermost first,
+    /// unless an inner call already did.
+    fn traced(&self, mut error: RuntimeError) -> RuntimeError {
+        if !error.trace.is_empty() {
+            return error;
+        }
+
+        // Natives have no line of their own; their frame shows the call.
+        let mut line = error.line;
+        for activation in self.activations.borrow().iter().rev() {
+            error.trace.push(Frame {
+                function: activation.function.as_ref().map(|function| function.to_string()),
+                native: activation.native,
+                file: activation.file.as_ref().map(|file| file.to_string()),
+                line: line.or(activation.called_from).unwrap_or(0),
+            });
+            line = activation.called_from;
+        }
+        if let Some(line) = line {
+            let file = self.modules().main_file().map(|file| file.to_string());
+            error.trace.push(Frame { function: None, native: false, file, line });
+        }
+        error
+    }
+
+    /// Evaluates an expression as one step of the budget.
+    fn evaluate(&self, expr: ExprRef<'static, InterpRes>) -> InterpRes {
+        self.budget.step().map_err(Unwind::Error)?;
+        if let Some(ref profiler) = self.profiler {
+            let mut profiler = profiler.borrow_mut();
+            if let Some(line) = expr.line() {
+                profiler.set_line(line);
+            }
+            profiler.sample();
+        }
+        if self.budget.should_measure() {
+            let (bytes, objects) = self.measure_heap();
+            self.budget.check_heap(bytes, objects).map_err(Unwind::Error)?;
+        }
+
+        let value = self.nested(|| expr.accept(self))?;
+        self.budget.check_string(&value).map_err(Unwind::Error)?;
+        Result::Ok(value)
+    }
+
+    /// Runs `f` as a call of `function`, timing it when profiling.
+    fn profiled<R, F: FnOnce() -> R>(&self, function: Option<&Symbol>, native: bool, file: Option<Rc<String>>, f: F) -> R {
+        let profiler = match self.profiler {
+            Some(ref profiler) => profiler,
+            None => return f(),
+        };
+
+        let frame = Profiler::frame(function.map(|function| function.as_str()), native, file.as_ref().map(|file| file.as_str()));
+        profiler.borrow_mut().enter(frame, file);
+        let result = f();
+        profiler.borrow_mut().exit();
+        result
+    }
+
+    fn execute(&self, stmt: &StmtRef<'static, InterpRes>) -> InterpRes {
+        self.nested(|| stmt.accept(self))
+    }
+
+    /// Runs `f` one level deeper, failing once too many expressions and
+    /// statements are being evaluated. The Rust stack grows when it runs
+    /// low, so the limits decide how deep scripts go whatever thread they
+    /// run on.
+    fn nested<F: FnOnce() -> InterpRes>(&self, f: F) -> InterpRes {
+        let nesting = self.nesting.get() + 1;
+        self.budget.check_nesting(nesting).map_err(Unwind::Error)?;
+
+        self.nesting.set(nesting);
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, f);
+        self.nesting.set(nesting - 1);
+        result
+    }
+
+    /// Roughly how many bytes the values scripts can still reach take, and
+    /// how many strings, lists, maps, functions and scopes hold them.
+    /// Values only held by Rust code, like the operands of an expression
+    /// being evaluated, aren't counted.
+    fn measure_heap(&self) -> (usize, usize) {
+        let mut environments = vec![Rc::clone(&self.builtins), Rc::clone(&self.globals), Rc::clone(&self.environment.borrow())];
+        for activation in self.activations.borrow().iter() {
+            environments.push(Rc::clone(&activation.environment));
+        }
+        Interpreter::measure_values(environments, self.modules().exported_values())
+    }
+
+    /// Roughly how many bytes the scopes in `environments` and the `values`
+    /// take along with everything they refer to, and how many strings,
+    /// lists, maps, functions and scopes that is.
+    pub fn measure_values(mut environments: Vec<EnvRef>, mut values: Vec<Value>) -> (usize, usize) {
+        let mut seen = HashSet::new();
+        let mut bytes = 0;
+
+        loop {
+            if let Some(environment) = environments.pop() {
+                if !seen.insert(Rc::as_ptr(&environment) as *const u8 as usize) {
+                    continue;
+                }
+                let environment = environment.borrow();
+                bytes += mem::size_of::<Environment>();
+                for value in environment.values() {
+                    bytes += mem::size_of::<(Symbol, Value)>();
+                    values.push(value.clone());
+                }
+                environments.extend(environment.enclosing().cloned());
+                continue;
+            }
+
+            let value = match values.pop() {
+                Some(value) => value,
+                None => break,
+            };
+            let address = match value {
+                Value::Str(ref s) => Rc::as_ptr(s) as *const u8 as usize,
+                Value::List(ref list) => Rc::as_ptr(list) as *const u8 as usize,
+                Value::Map(ref map) => Rc::as_ptr(map) as *const u8 as usize,
+                Value::Function(ref function) => Rc::as_ptr(function) as *const u8 as usize,
+                Value::Method(ref method) => Rc::as_ptr(method) as *const u8 as usize,
+                _ => continue,
+            };
+            if !seen.insert(address) {
+                continue;
+            }
+            match value {
+                Value::Str(ref s) => bytes += mem::size_of::<String>() + s.capacity(),
+                Value::List(ref list) => {
+                    let list = list.borrow();
+                    bytes += mem::size_of::<Vec<Value>>() + list.capacity() * mem::size_of::<Value>();
+                    values.extend(list.iter().cloned());
+                },
+                Value::Map(ref map) => {
+                    let map = map.borrow();
+                    bytes += mem::size_of::<HashMap<Key, Value>>() + map.capacity() * mem::size_of::<(Key, Value)>();
+                    values.extend(map.values().cloned());
+                    values.extend(map.keys().map(Key::to_value));
+                },
+                Value::Function(ref function) => {
+                    bytes += mem::size_of::<LoxFunction>();
+                    environments.push(Rc::clone(&function.closure));
+                },
+                Value::Method(ref method) => {
+                    bytes += mem::size_of::<BoundMethod>();
+                    values.push(method.receiver.clone());
+                },
+                _ => unreachable!(),
+            }
+        }
+        (bytes, seen.len())
+    }
+
+    fn execute_block(&self, statements: &[StmtRef<'static, InterpRes>], environment: EnvRef) -> InterpRes {
+        let previous = self.environment.replace(environment);
+
+        let mut result = Result::Ok(Value::Nil);
+        for statement in statements {
+            if let Result::Err(e) = self.execute(statement) {
+                result = Result::Err(e);
+                break;
+            }
+        }
+
+        self.environment.replace(previous);
+        result
+    }
+
+    pub fn is_truthy(value: &Value) -> bool {
+        match *value {
+            Value::Nil => false,
+            Value::Bool(b) => b,
+            _ => true,
+        }
+    }
+
+    /// Applies a binary operator to already evaluated operands.
+    pub fn binary(operator: &Token, left: Value, right: Value) -> InterpRes {
+        Interpreter::binary_op(&operator.token_type, left, right).map_err(|e| Unwind::error(e, operator))
+    }
+
+    /// The semantics of binary operators shared by every backend.
+    pub fn binary_op(operator: &TokenType, left: Value, right: Value) -> Result<Value, String> {
+        // Any two values can be compared for equality.
+        match *operator {
+            TokenType::EqualEqual => return Result::Ok(Value::Bool(left.equals(&right))),
+            TokenType::BangEqual => return Result::Ok(Value::Bool(!left.equals(&right))),
+            _ => {},
+        }
+
+        let valid = match (&left, &right) {
+            (&Value::Number(_), &Value::Number(_)) => true,
+            (&Value::Str(_), &Value::Str(_)) => matches!(*operator,
+                TokenType::Plus | TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual),
+            _ => false,
+        };
+        if !valid {
+            let expected = match *operator {
+                TokenType::Plus | TokenType::Greater | TokenType::GreaterEqual |
+                TokenType::Less | TokenType::LessEqual => "two numbers or two strings",
+                _ => "numbers",
+            };
+            return Result::Err(format!("Operands of '{}' must be {}, got {} and {}.",
+                                       Interpreter::operator_lexeme(operator), expected, left.describe(), right.describe()));
+        }
+
         Result::Ok(match (left, right) {
-            (TokenLiteral::Number(ln), TokenLiteral::Number(rn)) => {
-                match expr.operator.token_type {
-                    TokenType::Minus => TokenLiteral::Number(ln - rn),
-                    TokenType::Plus => TokenLiteral::Number(ln + rn),
-                    TokenType::Slash => TokenLiteral::Number(ln / rn),
-                    TokenType::Star => TokenLiteral::Number(ln * rn),
-                    TokenType::Greater => TokenLiteral::Bool(ln > rn),
-                    TokenType::GreaterEqual => TokenLiteral::Bool(ln >= rn),
-                    TokenType::Less => TokenLiteral::Bool(ln < rn),
-                    TokenType::LessEqual => TokenLiteral::Bool(ln <= rn),
-                    TokenType::EqualEqual => TokenLiteral::Bool(ln == rn),
-                    TokenType::BangEqual => TokenLiteral::Bool(ln != rn),
-                    _ => return Result::Err(("Invalid operator for number type.", Rc::clone(&expr.operator))),
+            (Value::Number(ln), Value::Number(rn)) => {
+                match *operator {
+                    TokenType::Minus => Value::Number(ln - rn),
+                    TokenType::Plus => Value::Number(ln + rn),
+                    TokenType::Slash => Value::Number(ln / rn),
+                    TokenType::Star => Value::Number(ln * rn),
+                    TokenType::Percent => Value::Number(ln % rn),
+                    TokenType::StarStar => Value::Number(ln.powf(rn)),
+                    TokenType::Greater => Value::Bool(ln > rn),
+                    TokenType::GreaterEqual => Value::Bool(ln >= rn),
+                    TokenType::Less => Value::Bool(ln < rn),
+                    TokenType::LessEqual => Value::Bool(ln <= rn),
+                    _ => unreachable!("{:?} is not a binary operator", operator),
                 }
             },
-            (TokenLiteral::Str(ls), TokenLiteral::Str(rs)) => {
-                match expr.operator.token_type {
-                    TokenType::Plus => TokenLiteral::Str(ls + &rs),
-                    TokenType::Greater => TokenLiteral::Bool(ls > rs),
-                    TokenType::GreaterEqual => TokenLiteral::Bool(ls >= rs),
-                    TokenType::Less => TokenLiteral::Bool(ls < rs),
-                    TokenType::LessEqual => TokenLiteral::Bool(ls <= rs),
-                    TokenType::EqualEqual => TokenLiteral::Bool(ls == rs),
-                    TokenType::BangEqual => TokenLiteral::Bool(ls != rs),
-                    _ => return Result::Err(("Invalid operator for string type.", Rc::clone(&expr.operator))),
+            (Value::Str(ls), Value::Str(rs)) => {
+                match *operator {
+                    TokenType::Plus => {
+                        // Append in place when nothing else shares the left operand,
+                        // which makes chains of concatenations linear.
+                        let mut s = Rc::try_unwrap(ls).unwrap_or_else(|ls| String::clone(&ls));
+                        s.push_str(&rs);
+                        Value::Str(Rc::new(s))
+                    },
+                    TokenType::Greater => Value::Bool(ls > rs),
+                    TokenType::GreaterEqual => Value::Bool(ls >= rs),
+                    TokenType::Less => Value::Bool(ls < rs),
+                    _ => Value::Bool(ls <= rs),
                 }
             },
-            (TokenLiteral::Bool(ls), TokenLiteral::Bool(rs)) => {
-                match expr.operator.token_type {
-                    TokenType::EqualEqual => TokenLiteral::Bool(ls == rs),
-                    TokenType::BangEqual => TokenLiteral::Bool(ls != rs),
-                    _ => return Result::Err(("Invalid operator for boolean type.", Rc::clone(&expr.operator))),
+            _ => unreachable!(),
+        })
+    }
+
+    /// How `operator` is written, for error messages.
+    fn operator_lexeme(operator: &TokenType) -> &'static str {
+        match *operator {
+            TokenType::BangEqual => "!=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::Minus => "-",
+            TokenType::Percent => "%",
+            TokenType::Plus => "+",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::StarStar => "**",
+            _ => unreachable!("{:?} is not an operator", operator),
+        }
+    }
+
+    /// Runs the module at `path` once, returning it as a value. `line` is
+    /// the line of the import.
+    fn import(&self, path: &str, line: usize) -> Result<(Rc<RefCell<Module>>, Value), RuntimeError> {
+        let path = self.modules().resolve(path)?;
+        if let Some(module) = self.modules().cached(&path) {
+            return Result::Ok(module);
+        }
+
+        let program = modules::load(&path)?;
+        self.modules().enter(&path)?;
+        self.activations.borrow_mut().push(Activation {
+            function: None,
+            native: false,
+            file: self.modules().current_file(),
+            called_from: Some(line),
+            environment: Rc::clone(&self.environment.borrow()),
+        });
+        let environment = Environment::new(Some(Rc::clone(&self.builtins)));
+        let file = self.modules().current_file();
+        let result = match self.profiled(None, false, file, || self.execute_block(&program, Rc::clone(&environment))) {
+            Result::Err(Unwind::Error(e)) => Result::Err(self.traced(e)),
+            _ => Result::Ok(()),
+        };
+        self.activations.borrow_mut().pop();
+        let exported = self.modules().leave();
+        result?;
+
+        let exports = exported.into_iter()
+            .map(|name| {
+                let value = environment.borrow().get(&name).unwrap_or(Value::Nil);
+                (name, value)
+            })
+            .collect();
+        let module = Rc::new(RefCell::new(Module::new(path.clone(), exports)));
+        let value = Value::Object(Rc::clone(&module) as Rc<RefCell<dyn NativeObject>>);
+        self.modules().cache(path, (Rc::clone(&module), value.clone()));
+        Result::Ok((module, value))
+    }
+
+    /// The semantics of `object[index]` shared by every backend.
+    pub fn get_index(object: &Value, index: &Value) -> Result<Value, RuntimeError> {
+        match *object {
+            Value::List(ref list) => lists::get(&list.borrow(), index),
+            Value::Map(ref map) => maps::get(&map.borrow(), index),
+            _ => Result::Err(RuntimeError::new(format!("Can't index a {}.", object.type_name()))),
+        }
+    }
+
+    /// The semantics of `object[index] = value` shared by every backend.
+    pub fn set_index(object: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+        match *object {
+            Value::List(ref list) => lists::set(&mut list.borrow_mut(), index, value),
+            Value::Map(ref map) => maps::set(&mut map.borrow_mut(), index, value),
+            _ => Result::Err(RuntimeError::new(format!("Can't assign to an index of a {}.", object.type_name()))),
+        }
+    }
+
+    /// The semantics of `object.name` shared by every backend: a property of
+    /// a native object, or a method bound to `object`.
+    pub fn get_property(object: Value, name: &Symbol) -> Result<Value, RuntimeError> {
+        let arity = match object {
+            Value::Object(ref native) => {
+                let native = native.borrow();
+                if let Some(value) = native.get(name.as_str()) {
+                    return Result::Ok(value);
                 }
+                native.method_arity(name.as_str())
             },
-            _ => return Result::Err(("Invalid expression.", Rc::clone(&expr.operator))),
-        })
+            Value::Str(_) => strings::string_method_arity(name.as_str()),
+            Value::Number(_) => strings::number_method_arity(name.as_str()),
+            Value::List(_) => lists::list_method_arity(name.as_str()),
+            Value::Map(_) => maps::map_method_arity(name.as_str()),
+            _ => return Result::Err(RuntimeError::new("Only instances have properties.")),
+        };
+
+        match arity {
+            Some(arity) => Result::Ok(Value::Method(Rc::new(BoundMethod {
+                receiver: object,
+                name: name.clone(),
+                arity,
+            }))),
+            None => Result::Err(RuntimeError::new(format!("Undefined property '{}'.", name))),
+        }
     }
 
-    fn visit_grouping(&self, expr: &Grouping<'a, InterpRes<'a>>) -> InterpRes<'a> {
-        self.evaluate(Rc::clone(&expr.expression))
+    /// The semantics of `object.name = value` shared by every backend.
+    pub fn set_property(object: &Value, name: &Symbol, value: Value) -> Result<(), RuntimeError> {
+        match *object {
+            Value::Object(ref object) => object.borrow_mut().set(name.as_str(), value),
+            _ => Result::Err(RuntimeError::new("Only instances have fields.")),
+        }
     }
 
-    fn visit_literal(&self, expr: &Literal) -> InterpRes<'a> {
-        Result::Ok(expr.value.clone())
+    /// Calls a method with as many arguments as it takes. Methods taking a
+    /// function call it through `call`, the backend's way of calling values.
+    pub fn call_method<F>(method: &BoundMethod, arguments: &[Value], call: F) -> Result<Value, RuntimeError>
+    where F: FnMut(&Value, &[Value]) -> Result<Value, RuntimeError> {
+        match method.receiver {
+            Value::Object(ref object) => match object.try_borrow_mut() {
+                Ok(mut object) => object.call_method(&method.name, arguments),
+                Err(_) => Result::Err(RuntimeError::new(format!("Can't call '{}' while the object is in use.", method.name))),
+            },
+            Value::Str(ref s) => strings::call_string_method(s, &method.name, arguments),
+            Value::Number(n) => strings::call_number_method(n, &method.name, arguments),
+            Value::List(ref list) => lists::call_list_method(list, &method.name, arguments, call),
+            Value::Map(ref map) => maps::call_map_method(map, &method.name, arguments),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Applies a unary operator to an already evaluated operand.
+    pub fn unary(operator: &Token, right: Value) -> InterpRes {
+        Interpreter::unary_op(&operator.token_type, right).map_err(|e| Unwind::error(e, operator))
     }
-    
-    fn visit_unary(&self, expr: &Unary<'a, InterpRes<'a>>) -> InterpRes<'a> {
-        let right = self.evaluate(Rc::clone(&expr.right))?;
 
-        Result::Ok(match expr.operator.token_type {
-            TokenType::Bang => TokenLiteral::Bool(!self.is_truthy(right)),
+    /// The semantics of unary operators shared by every backend.
+    pub fn unary_op(operator: &TokenType, right: Value) -> Result<Value, String> {
+        Result::Ok(match *operator {
+            TokenType::Bang => Value::Bool(!Interpreter::is_truthy(&right)),
             TokenType::Minus => {
                 match right {
-                    TokenLiteral::Number(n) => TokenLiteral::Number(-n),
-                    _ => return Result::Err(("Operand must be a number.", Rc::clone(&expr.operator))),
+                    Value::Number(n) => Value::Number(-n),
+                    _ => return Result::Err(format!("Operand of '{}' must be a number, got {}.",
+                                                    Interpreter::operator_lexeme(operator), right.describe())),
                 }
             },
-            _ => TokenLiteral::None
+            _ => Value::Nil
+        })
+    }
+}
+
+impl Visitor<'static, InterpRes> for Interpreter {
+    fn visit_assign(&self, expr: &Assign<'static, InterpRes>) -> InterpRes {
+        let value = self.evaluate(Rc::clone(&expr.value))?;
+
+        if !self.environment.borrow().borrow_mut().assign(&expr.symbol, value.clone()) {
+            return Result::Err(Unwind::error(format!("Undefined variable '{}'.", expr.name.lexeme), &expr.name));
+        }
+        Result::Ok(value)
+    }
+
+    fn visit_binary(&self, expr: &Binary<'static, InterpRes>) -> InterpRes {
+        let left = self.evaluate(Rc::clone(&expr.left))?;
+        let right = self.evaluate(Rc::clone(&expr.right))?;
+        Interpreter::binary(&expr.operator, left, right)
+    }
+
+    fn visit_call(&self, expr: &Call<'static, InterpRes>) -> InterpRes {
+        let callee = self.evaluate(Rc::clone(&expr.callee))?;
+
+        let mut arguments = Vec::new();
+        for argument in &expr.arguments {
+            arguments.push(self.evaluate(Rc::clone(argument))?);
+        }
+
+        let caller = self.line.replace(Some(expr.paren.line));
+        let result = self.call(&callee, &arguments);
+        self.line.set(caller);
+
+        result.map_err(|mut e| {
+            e.line = e.line.or(Some(expr.paren.line));
+            Unwind::Error(e)
         })
     }
-}
\ No newline at end of file
+
+    fn visit_get(&self, expr: &Get<'static, InterpRes>) -> InterpRes {
+        let object = self.evaluate(Rc::clone(&expr.object))?;
+        Interpreter::get_property(object, &expr.symbol).map_err(|e| Unwind::error(e.message, &expr.name))
+    }
+
+    fn visit_grouping(&self, expr: &Grouping<'static, InterpRes>) -> InterpRes {
+        self.evaluate(Rc::clone(&expr.expression))
+    }
+
+    fn visit_index(&self, expr: &Index<'static, InterpRes>) -> InterpRes {
+        let object = self.evaluate(Rc::clone(&expr.object))?;
+        let index = self.evaluate(Rc::clone(&expr.index))?;
+        Interpreter::get_index(&object, &index).map_err(|e| Unwind::error(e.message, &expr.bracket))
+    }
+
+    fn visit_list(&self, expr: &List<'static, InterpRes>) -> InterpRes {
+        let mut elements = Vec::new();
+        for element in &expr.elements {
+            elements.push(self.evaluate(Rc::clone(element))?);
+        }
+        Result::Ok(Value::list(elements))
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> InterpRes {
+        Result::Ok(Value::from(expr.value.clone()))
+    }
+
+    fn visit_logical(&self, expr: &Logical<'static, InterpRes>) -> InterpRes {
+        let left = self.evaluate(Rc::clone(&expr.left))?;
+
+        let short_circuit = match expr.operator.token_type {
+            TokenType::Or => Interpreter::is_truthy(&left),
+            _ => !Interpreter::is_truthy(&left),
+        };
+        if short_circuit {
+            return Result::Ok(left);
+        }
+
+        self.evaluate(Rc::clone(&expr.right))
+    }
+
+    fn visit_map(&self, expr: &Map<'static, InterpRes>) -> InterpRes {
+        let mut entries = HashMap::new();
+        for (key, value) in expr.keys.iter().zip(&expr.values) {
+            let key = self.evaluate(Rc::clone(key))?;
+            let key = Key::from_value(&key).map_err(|e| Unwind::error(e.message, &expr.brace))?;
+            entries.insert(key, self.evaluate(Rc::clone(value))?);
+        }
+        Result::Ok(Value::map(entries))
+    }
+
+    fn visit_set(&self, expr: &Set<'static, InterpRes>) -> InterpRes {
+        let object = match self.evaluate(Rc::clone(&expr.object))? {
+            object @ Value::Object(_) => object,
+            _ => return Result::Err(Unwind::error("Only instances have fields.", &expr.name)),
+        };
+
+        let value = self.evaluate(Rc::clone(&expr.value))?;
+        Interpreter::set_property(&object, &expr.symbol, value.clone()).map_err(|e| Unwind::error(e.message, &expr.name))?;
+        Result::Ok(value)
+    }
+
+    fn visit_set_index(&self, expr: &SetIndex<'static, InterpRes>) -> InterpRes {
+        let object = self.evaluate(Rc::clone(&expr.object))?;
+        let index = self.evaluate(Rc::clone(&expr.index))?;
+        let value = self.evaluate(Rc::clone(&expr.value))?;
+        Interpreter::set_index(&object, &index, value.clone()).map_err(|e| Unwind::error(e.message, &expr.bracket))?;
+        Result::Ok(value)
+    }
+
+    fn visit_unary(&self, expr: &Unary<'static, InterpRes>) -> InterpRes {
+        let right = self.evaluate(Rc::clone(&expr.right))?;
+        Interpreter::unary(&expr.operator, right)
+    }
+
+    fn visit_variable(&self, expr: &Variable) -> InterpRes {
+        match self.environment.borrow().borrow().get(&expr.symbol) {
+            Some(value) => Result::Ok(value),
+            None => Result::Err(Unwind::error(format!("Undefined variable '{}'.", expr.name.lexeme), &expr.name)),
+        }
+    }
+}
+
+impl StmtVisitor<'static, InterpRes> for Interpreter {
+    fn visit_block(&self, stmt: &Block<'static, InterpRes>) -> InterpRes {
+        let environment = Environment::new(Some(Rc::clone(&self.environment.borrow())));
+        self.execute_block(&stmt.statements, environment)
+    }
+
+    fn visit_export(&self, stmt: &Export<'static, InterpRes>) -> InterpRes {
+        self.execute(&stmt.declaration)?;
+        self.modules().export(stmt.symbol.clone());
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_expression(&self, stmt: &Expression<'static, InterpRes>) -> InterpRes {
+        self.evaluate(Rc::clone(&stmt.expression))?;
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_for_in(&self, stmt: &ForIn<'static, InterpRes>) -> InterpRes {
+        // Loops run over a copy, so the body may change what it iterates.
+        let items = match self.evaluate(Rc::clone(&stmt.iterable))? {
+            Value::List(list) => list.borrow().clone(),
+            Value::Map(map) => maps::keys(&map.borrow()).iter().map(Key::to_value).collect(),
+            value => return Result::Err(Unwind::error(format!("Can't iterate over a {}.", value.type_name()), &stmt.keyword)),
+        };
+
+        for item in items {
+            let environment = Environment::new(Some(Rc::clone(&self.environment.borrow())));
+            environment.borrow_mut().define(stmt.symbol.clone(), item);
+            self.execute_block(std::slice::from_ref(&stmt.body), environment)?;
+        }
+
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_function(&self, stmt: &Function<'static, InterpRes>) -> InterpRes {
+        // Scopes only end up in cycles through the functions closing over them.
+        cycles::track_environment(&self.environment.borrow());
+        let function = LoxFunction {
+            name: stmt.symbol.clone(),
+            file: self.modules().current_file(),
+            params: stmt.params.iter().map(|param| intern(&param.lexeme)).collect(),
+            body: Rc::clone(&stmt.body),
+            closure: Rc::clone(&self.environment.borrow()),
+        };
+
+        self.environment.borrow().borrow_mut().define(stmt.symbol.clone(), Value::Function(Rc::new(function)));
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_if(&self, stmt: &If<'static, InterpRes>) -> InterpRes {
+        if Interpreter::is_truthy(&self.evaluate(Rc::clone(&stmt.condition))?) {
+            self.execute(&stmt.then_branch)
+        } else if let Some(ref else_branch) = stmt.else_branch {
+            self.execute(else_branch)
+        } else {
+            Result::Ok(Value::Nil)
+        }
+    }
+
+    fn visit_import(&self, stmt: &Import<'static, InterpRes>) -> InterpRes {
+        let path = match stmt.path.literal {
+            TokenLiteral::Str(ref path) => path,
+            _ => unreachable!("the parser only accepts string paths"),
+        };
+        // Errors inside the module keep their own line.
+        let (module, value) = self.import(path, stmt.keyword.line).map_err(|mut e| {
+            e.line = e.line.or(Some(stmt.keyword.line));
+            Unwind::Error(e)
+        })?;
+
+        let environment = self.environment.borrow();
+        if let Some(ref alias) = stmt.alias {
+            environment.borrow_mut().define(intern(&alias.lexeme), value);
+        }
+        for name in &stmt.names {
+            let symbol = intern(&name.lexeme);
+            match module.borrow().export(&symbol) {
+                Some(value) => environment.borrow_mut().define(symbol, value),
+                None => return Result::Err(Unwind::error(format!("Module '{}' doesn't export '{}'.", path, name.lexeme), name)),
+            }
+        }
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_print(&self, stmt: &Print<'static, InterpRes>) -> InterpRes {
+        let value = self.evaluate(Rc::clone(&stmt.expression))?;
+        println!("{}", value);
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_return(&self, stmt: &Return<'static, InterpRes>) -> InterpRes {
+        let value = match stmt.value {
+            Some(ref value) => self.evaluate(Rc::clone(value))?,
+            None => Value::Nil,
+        };
+
+        Result::Err(Unwind::Return(value))
+    }
+
+    fn visit_throw(&self, stmt: &Throw<'static, InterpRes>) -> InterpRes {
+        let value = self.evaluate(Rc::clone(&stmt.value))?;
+        Result::Err(Unwind::Error(errors::thrown(value, stmt.keyword.line)))
+    }
+
+    fn visit_try(&self, stmt: &Try<'static, InterpRes>) -> InterpRes {
+        let scope = || Environment::new(Some(Rc::clone(&self.environment.borrow())));
+
+        // Only errors are caught; a `return` passes through to the call. A
+        // script over one of its limits stops without running handlers.
+        let result = match (self.execute_block(&stmt.body, scope()), &stmt.handler) {
+            (Result::Err(Unwind::Error(ref e)), _) if e.limit.is_some() => return Result::Err(Unwind::Error(e.clone())),
+            (Result::Err(Unwind::Error(e)), &Some((ref name, ref handler))) => {
+                let environment = scope();
+                environment.borrow_mut().define(intern(&name.lexeme), errors::caught(e));
+                self.execute_block(handler, environment)
+            },
+            (result, _) => result,
+        };
+
+        // An error or `return` in the finally block replaces the outcome of
+        // the rest of the statement.
+        if let Some(ref finalizer) = stmt.finalizer {
+            self.execute_block(finalizer, scope())?;
+        }
+        result
+    }
+
+    fn visit_var(&self, stmt: &Var<'static, InterpRes>) -> InterpRes {
+        let value = match stmt.initializer {
+            Some(ref initializer) => self.evaluate(Rc::clone(initializer))?,
+            None => Value::Nil,
+        };
+
+        self.environment.borrow().borrow_mut().define(stmt.symbol.clone(), value);
+        Result::Ok(Value::Nil)
+    }
+
+    fn visit_while(&self, stmt: &While<'static, InterpRes>) -> InterpRes {
+        while Interpreter::is_truthy(&self.evaluate(Rc::clone(&stmt.condition))?) {
+            self.execute(&stmt.body)?;
+        }
+
+        Result::Ok(Value::Nil)
+    }
+}
@@ -0,0 +1,99 @@
+//! Natives reading and writing files and the standard streams.
+//!
+//! Failures, like a missing file, are runtime errors naming the path and
+//! the reason given by the operating system. Embedders that don't want
+//! scripts to touch files or streams switch all of these off with
+//! `Lox::set_io(false)`.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use lox::value::*;
+
+pub const NATIVES: [(&str, usize, NativeFn); 7] = [
+    ("readFile", 1, read_file),
+    ("writeFile", 2, write_file),
+    ("appendFile", 2, append_file),
+    ("exists", 1, exists),
+    ("listDir", 1, list_dir),
+    ("readLine", 0, read_line),
+    ("printError", 1, print_error),
+];
+
+/// Stands in for every native of `NATIVES` while I/O is switched off.
+pub fn disabled(_arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Result::Err(RuntimeError::new("I/O is disabled."))
+}
+
+fn failed(action: &str, path: &str, error: io::Error) -> RuntimeError {
+    RuntimeError::new(format!("Can't {} '{}': {}.", action, path, error))
+}
+
+fn read_file(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::try_from(&arguments[0])?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => Result::Ok(Value::from(contents)),
+        Err(e) => Result::Err(failed("read", &path, e)),
+    }
+}
+
+fn write_file(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::try_from(&arguments[0])?;
+    let contents = String::try_from(&arguments[1])?;
+    match fs::write(&path, contents) {
+        Ok(()) => Result::Ok(Value::Nil),
+        Err(e) => Result::Err(failed("write", &path, e)),
+    }
+}
+
+fn append_file(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::try_from(&arguments[0])?;
+    let contents = String::try_from(&arguments[1])?;
+    let appended = fs::OpenOptions::new().append(true).create(true).open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match appended {
+        Ok(()) => Result::Ok(Value::Nil),
+        Err(e) => Result::Err(failed("append to", &path, e)),
+    }
+}
+
+fn exists(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::try_from(&arguments[0])?;
+    Result::Ok(Value::Bool(fs::metadata(path).is_ok()))
+}
+
+/// The names of the entries of a directory, sorted.
+fn list_dir(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::try_from(&arguments[0])?;
+    let entries = fs::read_dir(&path)
+        .and_then(|entries| entries.map(|entry| entry.map(|e| e.file_name())).collect::<io::Result<Vec<_>>>());
+    match entries {
+        Ok(entries) => {
+            let mut names: Vec<String> = entries.iter().map(|name| name.to_string_lossy().into_owned()).collect();
+            names.sort();
+            Result::Ok(Value::from(names))
+        },
+        Err(e) => Result::Err(failed("list", &path, e)),
+    }
+}
+
+/// The next line of standard input without its line ending, or nil at the
+/// end of the input.
+fn read_line(_arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Result::Ok(Value::Nil),
+        Ok(_) => {
+            let end = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(end);
+            Result::Ok(Value::from(line))
+        },
+        Err(e) => Result::Err(RuntimeError::new(format!("Can't read from standard input: {}.", e))),
+    }
+}
+
+/// Prints a value to standard error, like `print` does to standard output.
+fn print_error(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    eprintln!("{}", arguments[0]);
+    Result::Ok(Value::Nil)
+}
@@ -0,0 +1,188 @@
+//! Limits on what a script may use, for running scripts that aren't trusted.
+//!
+//! A script going over a limit stops with a runtime error whose `limit`
+//! says which one. Such errors can't be caught by `try`, and `finally`
+//! blocks don't run for them, so a script can't keep itself going.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use lox::value::*;
+
+/// Which limit a script went over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    CallDepth,
+    Nesting,
+    Heap,
+    StringLength,
+    Timeout,
+    Cancelled,
+}
+
+/// What a script may use. `None` means no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Steps a run may take: expressions evaluated by the tree-walker,
+    /// instructions executed by the VM.
+    pub max_steps: Option<u64>,
+    /// Calls that may be running at once, stopping runaway recursion with
+    /// a "Stack overflow." error.
+    pub max_call_depth: usize,
+    /// Expressions and statements the tree-walker may be evaluating at once,
+    /// counting those of every running call. Each takes about 0.6KB of the
+    /// Rust stack in release builds and 3.5KB in debug builds; the
+    /// tree-walker grows the stack when it runs low, so this bounds the
+    /// memory deep scripts take whatever thread they run on.
+    pub max_nesting: usize,
+    /// Bytes the values a script can still reach may take. Both backends
+    /// measure them every so often; the VM collects its heap first and
+    /// counts the lists and maps on it with everything they hold.
+    pub max_heap_bytes: Option<usize>,
+    /// Bytes a string built by a script may take.
+    pub max_string_length: Option<usize>,
+    /// How long a run may take.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_call_depth: 1000,
+            max_nesting: 10_000,
+            max_heap_bytes: None,
+            max_string_length: None,
+            timeout: None,
+        }
+    }
+}
+
+/// Stops the scripts of a `Lox` from another thread. Once cancelled, every
+/// script it runs stops with a `Limit::Cancelled` error.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Steps between checks of the clock and of the cancel handle.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Keeps track of what the current run used, for one backend.
+#[derive(Default)]
+pub struct Budget {
+    limits: Limits,
+    cancel: CancelHandle,
+    steps: Cell<u64>,
+    started: Cell<Option<Instant>>,
+    /// The step at which the heap is measured next.
+    next_measure: Cell<u64>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits, cancel: CancelHandle) -> Budget {
+        Budget {
+            limits,
+            cancel,
+            steps: Cell::new(0),
+            started: Cell::new(None),
+            next_measure: Cell::new(0),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Starts a new run, with all of its steps and time left.
+    pub fn start(&self) {
+        self.steps.set(0);
+        self.next_measure.set(0);
+        self.started.set(Some(Instant::now()));
+    }
+
+    /// Counts a step, failing once a run takes too many steps or too long,
+    /// or has been cancelled.
+    pub fn step(&self) -> Result<(), RuntimeError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        if let Some(max) = self.limits.max_steps {
+            if steps > max {
+                return Result::Err(exceeded(Limit::Steps, format!("Exceeded the limit of {} steps.", max)));
+            }
+        }
+
+        if steps % CHECK_INTERVAL == 1 {
+            if self.cancel.is_cancelled() {
+                return Result::Err(exceeded(Limit::Cancelled, String::from("Cancelled.")));
+            }
+            if let (Some(timeout), Some(started)) = (self.limits.timeout, self.started.get()) {
+                if started.elapsed() > timeout {
+                    return Result::Err(exceeded(Limit::Timeout, format!("Timed out after {:?}.", timeout)));
+                }
+            }
+        }
+        Result::Ok(())
+    }
+
+    /// Fails if `depth` calls can't be running at once.
+    pub fn check_depth(&self, depth: usize) -> Result<(), RuntimeError> {
+        if depth > self.limits.max_call_depth {
+            return Result::Err(exceeded(Limit::CallDepth, String::from("Stack overflow.")));
+        }
+        Result::Ok(())
+    }
+
+    /// Fails if `nesting` expressions and statements can't be evaluated at once.
+    pub fn check_nesting(&self, nesting: usize) -> Result<(), RuntimeError> {
+        if nesting > self.limits.max_nesting {
+            return Result::Err(exceeded(Limit::Nesting, String::from("Stack overflow.")));
+        }
+        Result::Ok(())
+    }
+
+    /// Fails if `value` is a string longer than allowed.
+    pub fn check_string(&self, value: &Value) -> Result<(), RuntimeError> {
+        match (value, self.limits.max_string_length) {
+            (Value::Str(s), Some(max)) if s.len() > max => Result::Err(exceeded(
+                Limit::StringLength, format!("Strings can't be longer than {} bytes, got one of {}.", max, s.len()))),
+            _ => Result::Ok(()),
+        }
+    }
+
+    /// Whether the heap should be measured at this step.
+    pub fn should_measure(&self) -> bool {
+        self.limits.max_heap_bytes.is_some() && self.steps.get() >= self.next_measure.get()
+    }
+
+    /// Fails if a heap of `bytes` is too large. `objects` is how many objects
+    /// were measured: measuring again only after as many more steps keeps
+    /// the cost of measuring in proportion to the steps taken.
+    pub fn check_heap(&self, bytes: usize, objects: usize) -> Result<(), RuntimeError> {
+        self.next_measure.set(self.steps.get() + CHECK_INTERVAL.max(objects as u64));
+        match self.limits.max_heap_bytes {
+            Some(max) if bytes > max => Result::Err(exceeded(
+                Limit::Heap, format!("Exceeded the limit of {} bytes of heap.", max))),
+            _ => Result::Ok(()),
+        }
+    }
+}
+
+fn exceeded(limit: Limit, message: String) -> RuntimeError {
+    let mut error = RuntimeError::new(message);
+    error.limit = Some(limit);
+    error
+}
@@ -0,0 +1,161 @@
+//! Indexing and the methods of lists, shared by every backend.
+//!
+//! Indices may be negative and then count from the end, so `list[-1]` is
+//! the last element. Methods taking a function call it back through the
+//! `call` argument, which is how the backend running the script calls values.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use lox::value::*;
+use lox::interpreter::Interpreter;
+
+type ListRef = Rc<RefCell<Vec<Value>>>;
+
+/// Reads `list[index]`.
+pub fn get(list: &[Value], index: &Value) -> Result<Value, RuntimeError> {
+    let i = position(index, list.len(), false)?;
+    Result::Ok(list[i].clone())
+}
+
+/// Performs `list[index] = value`.
+pub fn set(list: &mut [Value], index: &Value, value: Value) -> Result<(), RuntimeError> {
+    let i = position(index, list.len(), false)?;
+    list[i] = value;
+    Result::Ok(())
+}
+
+/// The arity of list method `name`, or `None` if there is no such method.
+pub fn list_method_arity(name: &str) -> Option<usize> {
+    Some(match name {
+        "len" | "pop" => 0,
+        "push" | "remove" | "map" | "filter" | "sort" => 1,
+        "insert" | "slice" | "reduce" => 2,
+        _ => return None,
+    })
+}
+
+/// Calls a method `list_method_arity` knows with as many arguments as it takes.
+pub fn call_list_method<F>(list: &ListRef, name: &str, arguments: &[Value], mut call: F) -> Result<Value, RuntimeError>
+where F: FnMut(&Value, &[Value]) -> Result<Value, RuntimeError> {
+    // Callbacks may change the list, so they run over a copy of it and the
+    // list is never borrowed while one runs.
+    let elements = || list.borrow().clone();
+
+    Result::Ok(match name {
+        "len" => Value::Number(list.borrow().len() as f64),
+        "push" => {
+            list.borrow_mut().push(arguments[0].clone());
+            Value::Nil
+        },
+        "pop" => match list.borrow_mut().pop() {
+            Some(value) => value,
+            None => return Result::Err(RuntimeError::new("Can't pop from an empty list.")),
+        },
+        "insert" => {
+            let mut list = list.borrow_mut();
+            let i = position(&arguments[0], list.len(), true)?;
+            list.insert(i, arguments[1].clone());
+            Value::Nil
+        },
+        "remove" => {
+            let mut list = list.borrow_mut();
+            let i = position(&arguments[0], list.len(), false)?;
+            list.remove(i)
+        },
+        "slice" => {
+            let list = list.borrow();
+            let start = clamp(&arguments[0], list.len())?;
+            let end = clamp(&arguments[1], list.len())?.max(start);
+            Value::list(list[start..end].to_vec())
+        },
+        "map" => {
+            let mut mapped = Vec::new();
+            for element in elements() {
+                mapped.push(call(&arguments[0], &[element])?);
+            }
+            Value::list(mapped)
+        },
+        "filter" => {
+            let mut kept = Vec::new();
+            for element in elements() {
+                if Interpreter::is_truthy(&call(&arguments[0], std::slice::from_ref(&element))?) {
+                    kept.push(element);
+                }
+            }
+            Value::list(kept)
+        },
+        "reduce" => {
+            let mut accumulator = arguments[1].clone();
+            for element in elements() {
+                accumulator = call(&arguments[0], &[accumulator, element])?;
+            }
+            accumulator
+        },
+        "sort" => {
+            let sorted = merge_sort(elements(), &mut |a, b| {
+                match call(&arguments[0], &[a.clone(), b.clone()])? {
+                    Value::Number(n) => Result::Ok(n > 0.0),
+                    ref v => Result::Err(RuntimeError::new(format!("A comparator must return a number, not a {}.", v.type_name()))),
+                }
+            })?;
+            *list.borrow_mut() = sorted;
+            Value::Nil
+        },
+        _ => unreachable!("unknown list method '{}'", name),
+    })
+}
+
+/// Turns a possibly negative index into a position in a list of `len`
+/// elements; `len` itself is only valid when `inclusive`.
+fn position(index: &Value, len: usize, inclusive: bool) -> Result<usize, RuntimeError> {
+    let n = f64::try_from(index)?;
+    if n.fract() != 0.0 {
+        return Result::Err(RuntimeError::new(format!("List index {} is not an integer.", n)));
+    }
+
+    let i = if n < 0.0 { n + len as f64 } else { n };
+    let limit = if inclusive { len + 1 } else { len };
+    if i < 0.0 || i >= limit as f64 {
+        return Result::Err(RuntimeError::new(format!("List index {} is out of range for a list of length {}.", n, len)));
+    }
+    Result::Ok(i as usize)
+}
+
+/// Like `position`, but out of range indices stop at the ends of the list.
+fn clamp(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let n = f64::try_from(index)?;
+    if n.fract() != 0.0 {
+        return Result::Err(RuntimeError::new(format!("List index {} is not an integer.", n)));
+    }
+
+    let i = if n < 0.0 { n + len as f64 } else { n };
+    Result::Ok(i.max(0.0).min(len as f64) as usize)
+}
+
+/// A stable sort that stops at the first error of `greater`, which unlike
+/// `slice::sort_by` copes with comparators that are not consistent.
+fn merge_sort<F>(mut values: Vec<Value>, greater: &mut F) -> Result<Vec<Value>, RuntimeError>
+where F: FnMut(&Value, &Value) -> Result<bool, RuntimeError> {
+    if values.len() <= 1 {
+        return Result::Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, greater)?;
+    let right = merge_sort(right, greater)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if greater(l, r)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Result::Ok(merged)
+}
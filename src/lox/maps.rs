@@ -0,0 +1,55 @@
+//! Lookups and the methods of maps, shared by every backend.
+//!
+//! Only nil, booleans, numbers and strings can be keys. Keys are always
+//! listed in the order of `Key`, so iterating a map is deterministic.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use lox::value::*;
+
+type MapRef = Rc<RefCell<HashMap<Key, Value>>>;
+
+/// Reads `map[key]`; a missing key is an error.
+pub fn get(map: &HashMap<Key, Value>, key: &Value) -> Result<Value, RuntimeError> {
+    match map.get(&Key::from_value(key)?) {
+        Some(value) => Result::Ok(value.clone()),
+        None => Result::Err(RuntimeError::new(format!("Undefined key {:?}.", key))),
+    }
+}
+
+/// Performs `map[key] = value`.
+pub fn set(map: &mut HashMap<Key, Value>, key: &Value, value: Value) -> Result<(), RuntimeError> {
+    map.insert(Key::from_value(key)?, value);
+    Result::Ok(())
+}
+
+/// The keys of `map` in order.
+pub fn keys(map: &HashMap<Key, Value>) -> Vec<Key> {
+    let mut keys: Vec<Key> = map.keys().cloned().collect();
+    keys.sort();
+    keys
+}
+
+/// The arity of map method `name`, or `None` if there is no such method.
+pub fn map_method_arity(name: &str) -> Option<usize> {
+    Some(match name {
+        "len" | "keys" | "values" => 0,
+        "has" | "delete" => 1,
+        _ => return None,
+    })
+}
+
+/// Calls a method `map_method_arity` knows with as many arguments as it takes.
+pub fn call_map_method(map: &MapRef, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let mut map = map.borrow_mut();
+
+    Result::Ok(match name {
+        "len" => Value::Number(map.len() as f64),
+        "keys" => Value::list(keys(&map).iter().map(Key::to_value).collect()),
+        "values" => Value::list(keys(&map).iter().map(|key| map[key].clone()).collect()),
+        "has" => Value::Bool(map.contains_key(&Key::from_value(&arguments[0])?)),
+        "delete" => Value::Bool(map.remove(&Key::from_value(&arguments[0])?).is_some()),
+        _ => unreachable!("unknown map method '{}'", name),
+    })
+}
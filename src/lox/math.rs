@@ -0,0 +1,159 @@
+//! The math functions and constants every script starts with.
+//!
+//! `random()` draws from a SplitMix64 generator that is seeded from the
+//! clock; `seedRandom(n)` restarts it so runs can be reproduced. Every
+//! interpreter has a generator of its own.
+
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use lox::value::*;
+
+pub const CONSTANTS: [(&str, f64); 2] = [
+    ("PI", std::f64::consts::PI),
+    ("E", std::f64::consts::E),
+];
+
+pub const NATIVES: [(&str, usize, NativeFn); 15] = [
+    ("floor", 1, floor),
+    ("ceil", 1, ceil),
+    ("round", 1, round),
+    ("abs", 1, abs),
+    ("sqrt", 1, sqrt),
+    ("pow", 2, pow),
+    ("min", 2, min),
+    ("max", 2, max),
+    ("sin", 1, sin),
+    ("cos", 1, cos),
+    ("tan", 1, tan),
+    ("asin", 1, asin),
+    ("acos", 1, acos),
+    ("atan", 1, atan),
+    ("atan2", 2, atan2),
+];
+
+/// The functions drawing from the generator, methods of `Random` bound to
+/// an interpreter's own.
+pub const RANDOM_METHODS: [(&str, usize); 2] = [
+    ("random", 0),
+    ("seedRandom", 1),
+];
+
+/// The state of a SplitMix64 generator, seeded from the clock on first use.
+#[derive(Default)]
+pub struct Random {
+    state: Option<u64>,
+}
+
+impl Random {
+    /// A number in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        let seed = self.state.unwrap_or_else(|| {
+            let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            elapsed.as_nanos() as u64
+        });
+        let next = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state = Some(next);
+
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // The top 53 bits fill the mantissa exactly.
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl NativeObject for Random {
+    fn class_name(&self) -> &str {
+        "Random"
+    }
+
+    fn method_arity(&self, name: &str) -> Option<usize> {
+        RANDOM_METHODS.iter().find(|&&(method, _)| method == name).map(|&(_, arity)| arity)
+    }
+
+    fn call_method(&mut self, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        match name {
+            "random" => Result::Ok(Value::Number(self.next())),
+            "seedRandom" => {
+                self.state = Some(number(&arguments[0])?.to_bits());
+                Result::Ok(Value::Nil)
+            },
+            _ => Result::Err(RuntimeError::new(format!("Undefined property '{}'.", name))),
+        }
+    }
+}
+
+fn number(value: &Value) -> Result<f64, RuntimeError> {
+    f64::try_from(value)
+}
+
+fn unary(arguments: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    Result::Ok(Value::Number(f(number(&arguments[0])?)))
+}
+
+fn binary(arguments: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+    Result::Ok(Value::Number(f(number(&arguments[0])?, number(&arguments[1])?)))
+}
+
+fn floor(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::floor)
+}
+
+fn ceil(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::ceil)
+}
+
+/// Rounds halfway cases away from zero.
+fn round(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::round)
+}
+
+fn abs(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::abs)
+}
+
+fn sqrt(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::sqrt)
+}
+
+fn pow(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    binary(arguments, f64::powf)
+}
+
+fn min(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    binary(arguments, f64::min)
+}
+
+fn max(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    binary(arguments, f64::max)
+}
+
+fn sin(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::sin)
+}
+
+fn cos(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::cos)
+}
+
+fn tan(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::tan)
+}
+
+fn asin(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::asin)
+}
+
+fn acos(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::acos)
+}
+
+fn atan(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    unary(arguments, f64::atan)
+}
+
+fn atan2(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    binary(arguments, f64::atan2)
+}
@@ -0,0 +1,194 @@
+use std::mem;
+use std::rc::Rc;
+use lox::value::{NativeFunction, Value};
+
+/// Handle to an object living in a `Heap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjRef(usize);
+
+/// A value on the VM stack. Everything that does not fit in a machine word
+/// lives in the heap and is referred to by handle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VmValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+/// An object on the VM heap. Only strings and natives are the VM's own:
+/// lists, maps and the other values it shares with the tree-walker are
+/// reference counted, their cycles freed by `cycles`, and the heap only
+/// holds them as `Host` objects.
+pub enum Obj {
+    Str(Rc<String>),
+    Native(Rc<NativeFunction>),
+    /// A value the VM has no instructions for, such as a list. Host values
+    /// can't refer to heap objects, so there is nothing to trace, and what
+    /// they hold isn't counted in `Heap::bytes_allocated`.
+    Host(Value),
+}
+
+impl Obj {
+    fn size(&self) -> usize {
+        mem::size_of::<Obj>() + match *self {
+            Obj::Str(ref s) => s.len(),
+            Obj::Native(_) | Obj::Host(_) => 0,
+        }
+    }
+
+    /// Pushes every object referenced from this one onto `gray`.
+    fn trace(&self, _gray: &mut Vec<ObjRef>) {
+        match *self {
+            Obj::Str(_) | Obj::Native(_) | Obj::Host(_) => {},
+        }
+    }
+}
+
+/// Tuning of the garbage collector.
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /// Bytes that may be allocated before the first collection.
+    pub threshold: usize,
+    /// After a collection the next one happens once the heap has grown to
+    /// this multiple of the bytes still alive.
+    pub growth_factor: usize,
+    /// Collect before every allocation.
+    pub stress: bool,
+    /// Report every collection on stderr.
+    pub log: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig {
+            threshold: 1024 * 1024,
+            growth_factor: 2,
+            stress: false,
+            log: false,
+        }
+    }
+}
+
+/// Objects allocated by the VM, reclaimed by a tracing mark-and-sweep collector.
+pub struct Heap {
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap::new(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Heap {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: config.threshold,
+            config,
+        }
+    }
+
+    /// Whether the owner should run `collect` before its next allocation.
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn allocate(&mut self, obj: Obj) -> ObjRef {
+        self.bytes_allocated += obj.size();
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(obj);
+                ObjRef(index)
+            },
+            None => {
+                self.objects.push(Some(obj));
+                self.marks.push(false);
+                ObjRef(self.objects.len() - 1)
+            },
+        }
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        self.objects[obj.0].as_ref().expect("use of a collected object")
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /// The values held by `Host` objects.
+    pub fn host_values(&self) -> Vec<Value> {
+        self.objects.iter().filter_map(|obj| match *obj {
+            Some(Obj::Host(ref value)) => Some(value.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Frees every object not reachable from `roots` and returns the number
+    /// of bytes reclaimed.
+    pub fn collect<'r, I>(&mut self, roots: I) -> usize
+    where I: IntoIterator<Item = &'r VmValue> {
+        let before = self.bytes_allocated;
+
+        let mut gray = Vec::new();
+        for root in roots {
+            if let VmValue::Obj(obj) = *root {
+                gray.push(obj);
+            }
+        }
+        self.mark(gray);
+        self.sweep();
+
+        self.next_gc = self.bytes_allocated.max(1) * self.config.growth_factor;
+        let freed = before - self.bytes_allocated;
+
+        if self.config.log {
+            eprintln!(
+                "[gc] collected {} bytes (from {} to {}), {} objects alive, next at {}",
+                freed, before, self.bytes_allocated, self.object_count(), self.next_gc
+            );
+        }
+
+        freed
+    }
+
+    fn mark(&mut self, mut gray: Vec<ObjRef>) {
+        while let Some(obj) = gray.pop() {
+            if self.marks[obj.0] {
+                continue;
+            }
+
+            self.marks[obj.0] = true;
+            self.get(obj).trace(&mut gray);
+        }
+    }
+
+    fn sweep(&mut self) {
+        for index in 0..self.objects.len() {
+            if self.marks[index] {
+                self.marks[index] = false;
+                continue;
+            }
+
+            if let Some(obj) = self.objects[index].take() {
+                self.bytes_allocated -= obj.size();
+                self.free.push(index);
+            }
+        }
+    }
+}
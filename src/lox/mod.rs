@@ -1,51 +1,318 @@
+use std::fmt;
 use std::fs;
-use std::io;
+use std::path::Path;
 use std::io::*;
 use std::process;
 use std::rc::*;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod interner;
 pub mod token;
 pub mod scanner;
 pub mod expr;
+pub mod stmt;
 pub mod ast_printer;
+pub mod dot_printer;
 pub mod parser;
+pub mod environment;
+pub mod cycles;
 pub mod interpreter;
+pub mod constant_folder;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod debug;
+pub mod bytecode_file;
+pub mod memory;
+pub mod value;
+pub mod strings;
+pub mod math;
+pub mod lists;
+pub mod maps;
+pub mod io;
+pub mod modules;
+pub mod errors;
+pub mod testing;
+pub mod source_printer;
+pub mod fuzzing;
+pub mod limits;
+pub mod benchmarks;
+pub mod profiler;
 
 use self::scanner::*;
 use self::token::*;
 use self::parser::*;
 use self::interpreter::*;
-// use self::ast_printer::AstPrinter;
+use self::expr::ExprRef;
+use self::stmt::StmtRef;
+use self::constant_folder::*;
+use self::ast_printer::AstPrinter;
+use self::dot_printer::DotPrinter;
+use self::source_printer::SourcePrinter;
+use self::compiler::Compiler;
+use self::vm::VM;
+use self::chunk::Chunk;
+use self::bytecode_file::Prototype;
+use self::memory::GcConfig;
+use self::limits::{Budget, CancelHandle, Limits};
+use self::profiler::{Profile, Profiler, SAMPLE_INTERVAL};
+use self::interner::{intern, Symbol};
+use self::value::*;
+
+/// Output format of `Lox::ast_file`.
+pub enum AstFormat {
+    /// Lisp-like s-expressions produced by `AstPrinter`.
+    SExpr,
+    /// Graphviz DOT graph produced by `DotPrinter`, optionally labelled with source lines.
+    Dot { lines: bool },
+    /// Lox source produced by `SourcePrinter`.
+    Source,
+}
+
+/// Which engine executes scripts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// The tree-walking `Interpreter`.
+    Tree,
+    /// The bytecode `Compiler` and `VM`.
+    Vm,
+}
+
+/// Why `Lox::load` failed.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The script did not parse. The errors have been reported on stderr.
+    Syntax,
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Syntax => write!(f, "Syntax error."),
+            LoadError::Runtime(ref e) => write!(f, "{}", e),
+        }
+    }
+}
 
 pub struct Lox{
     had_error: bool,
     had_runtime_error: bool,
+    backend: Backend,
+    disassemble: bool,
+    trace: bool,
+    gc: GcConfig,
+    limits: Limits,
+    cancel: CancelHandle,
+    /// Where `run_file` writes the profile, without the extension.
+    profile_output: Option<String>,
+    interpreter: Interpreter,
+    globals: Vec<(Symbol, Value)>,
+}
+
+impl Default for Lox {
+    fn default() -> Lox {
+        Lox::new()
+    }
 }
 
 impl Lox {
     pub fn new() -> Lox {
-        Lox {
+        let mut lox = Lox {
             had_error: false,
             had_runtime_error: false,
+            backend: Backend::Tree,
+            disassemble: false,
+            trace: false,
+            gc: GcConfig::default(),
+            limits: Limits::default(),
+            cancel: CancelHandle::default(),
+            profile_output: None,
+            interpreter: Interpreter::new(),
+            globals: Vec::new(),
+        };
+
+        lox.define_native("clock", 0, clock);
+        for &(name, arity, function) in math::NATIVES.iter() {
+            lox.define_native(name, arity, function);
         }
+        let random = Value::object(math::Random::default());
+        for &(name, arity) in math::RANDOM_METHODS.iter() {
+            let method = BoundMethod { receiver: random.clone(), name: intern(name), arity };
+            lox.define_global(name, Value::Method(Rc::new(method)));
+        }
+        for &(name, value) in math::CONSTANTS.iter() {
+            lox.define_global(name, Value::Number(value));
+        }
+        for &(name, arity, function) in testing::NATIVES.iter() {
+            lox.define_native(name, arity, function);
+        }
+        lox.define_prelude(testing::PRELUDE);
+        lox.set_io(true);
+        lox.set_limits(Limits::default());
+        lox
+    }
+
+    /// Runs Lox source declaring builtins that are easier to write in Lox
+    /// than in Rust.
+    fn define_prelude(&mut self, source: &str) {
+        let tokens = self.scan(source);
+        let program = self.parse_program(&tokens, Some(&ConstantFolder::new())).filter(|_| !self.had_error).expect("the prelude parses");
+        if let Err(e) = self.interpreter.define_builtins(&program) {
+            panic!("the prelude failed: {}", e);
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// Prints every compiled chunk to stderr before the VM runs it.
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
+
+    /// Prints the VM stack and each instruction to stderr as it executes.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Configures the garbage collector of the VM heap.
+    pub fn set_gc(&mut self, gc: GcConfig) {
+        self.gc = gc;
+    }
+
+    /// Limits what every script and call run afterwards may use. A script
+    /// over a limit stops with a runtime error that `try` can't catch.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.interpreter.set_budget(Budget::new(limits, self.cancel.clone()));
+    }
+
+    /// A handle another thread can use to stop the scripts this runs.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Profiles the scripts and calls the tree-walker runs afterwards,
+    /// starting from an empty profile. The VM isn't profiled.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.interpreter.set_profiler(if enabled { Some(Profiler::new(SAMPLE_INTERVAL)) } else { None });
+    }
+
+    /// What was measured since profiling was turned on.
+    pub fn profile(&self) -> Option<Profile> {
+        self.interpreter.profile()
+    }
+
+    /// Profiles the script `run_file` runs, writing the report to
+    /// `output.txt` and the folded stacks to `output.folded` once it
+    /// finishes, even with an error.
+    pub fn set_profile_output(&mut self, output: &str) {
+        self.set_profiling(true);
+        self.profile_output = Some(String::from(output));
+    }
+
+    /// Gives scripts the natives of the `io` module, on by default. When
+    /// off, calling any of them or importing a module is a runtime error.
+    pub fn set_io(&mut self, enabled: bool) {
+        for &(name, arity, function) in io::NATIVES.iter() {
+            self.define_native(name, arity, if enabled { function } else { io::disabled });
+        }
+        self.interpreter.modules().set_io(enabled);
+    }
+
+    /// Makes a Rust function callable from scripts as the global `name`.
+    ///
+    /// Calls with the wrong number of arguments and errors returned by the
+    /// function are reported like any other runtime error, at the line of
+    /// the call.
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let native = Rc::new(NativeFunction {
+            name: intern(name),
+            arity,
+            function,
+        });
+
+        self.define_global(name, Value::Native(native));
+    }
+
+    /// Defines the global `name` for every script run afterwards.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let name = intern(name);
+        self.interpreter.define(name.clone(), value.clone());
+        self.globals.retain(|(defined, _)| *defined != name);
+        self.globals.push((name, value));
+    }
+
+    /// Adds a directory to search for imported modules that aren't next to
+    /// the file importing them.
+    pub fn add_module_path(&mut self, dir: &str) {
+        self.interpreter.modules().add_search_path(Path::new(dir));
+    }
+
+    /// Runs a script on the tree-walking interpreter and keeps its globals,
+    /// so its functions can be called later with `call`. Modules it imports
+    /// are looked up relative to the working directory.
+    pub fn load(&mut self, source: &str) -> std::result::Result<(), LoadError> {
+        self.interpreter.modules().set_main(None);
+        self.evaluate(source).map(|_| ())
+    }
+
+    /// Like `load`, but runs the script in the file at `path`, which is
+    /// where the modules it imports are looked up first.
+    pub fn load_file(&mut self, path: &str) -> std::result::Result<(), LoadError> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| LoadError::Runtime(RuntimeError::new(format!("Can't read '{}': {}.", path, e))))?;
+        self.interpreter.modules().set_main(Some(Path::new(path)));
+        self.evaluate(&source).map(|_| ())
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.interpreter.get_global(&intern(name))
+    }
+
+    /// The names of the globals scripts loaded so far defined, sorted.
+    pub fn global_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.interpreter.global_names().iter().map(|name| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    /// Calls a Lox or native function with arguments converted from Rust,
+    /// e.g. `lox.call(&f, &[1.0.into(), "a".into()])`.
+    pub fn call(&self, function: &Value, arguments: &[Value]) -> std::result::Result<Value, RuntimeError> {
+        self.interpreter.start();
+        self.interpreter.call(function, arguments)
     }
 
     pub fn run_prompt(&mut self) {
         loop {
-            io::stdout().write("> ".as_bytes()).unwrap();
-            io::stdout().flush().unwrap();
+            stdout().write_all("> ".as_bytes()).unwrap();
+            stdout().flush().unwrap();
 
             let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
+            stdin().read_line(&mut input).unwrap();
             self.run(&input);
 
             self.had_error = false;
         }
     }
 
+    /// Runs a script, or a compiled `.loxc` file directly on the VM.
     pub fn run_file(&mut self, filename: &str) {
-        let source = fs::read_to_string(filename).unwrap();
-        self.run(&source);
+        let bytes = read_file(filename);
+        if bytecode_file::is_bytecode(&bytes) {
+            self.run_bytecode(filename, &bytes);
+        } else {
+            self.interpreter.modules().set_main(Some(Path::new(filename)));
+            self.run(&source_text(filename, bytes));
+        }
+
+        if let (Some(output), Some(profile)) = (self.profile_output.as_ref(), self.interpreter.profile()) {
+            fs::write(format!("{}.txt", output), profile.report()).unwrap();
+            fs::write(format!("{}.folded", output), profile.folded()).unwrap();
+        }
 
         if self.had_error {
             process::exit(65);
@@ -56,26 +323,224 @@ impl Lox {
         }
     }
 
-    fn run<'a>(&mut self, source: &'a str) {
+    /// Compiles a script to bytecode and writes it to `output`.
+    pub fn compile_file(&mut self, filename: &str, output: &str) {
+        let source = source_text(filename, read_file(filename));
+        let mut scanner = Scanner::new(&source);
+
+        let tokens = scanner.scan_tokens(|line, err| self.error(line, err));
+        let expression = Parser::new(tokens).is_expression();
+        let chunk = Compiler::new(tokens).compile(|tok, err| self.error_token(tok, err));
+
+        match chunk {
+            Some(chunk) if !self.had_error => {
+                let prototypes = [Prototype { name: String::from("<script>"), expression, chunk }];
+                fs::write(output, bytecode_file::serialize(&prototypes)).unwrap();
+            },
+            _ => process::exit(65),
+        }
+    }
+
+    pub fn ast_file(&mut self, filename: &str, format: &AstFormat, optimize: bool) {
+        let source = source_text(filename, read_file(filename));
+
+        match self.print_ast(&source, format, optimize) {
+            Some(ast) => println!("{}", ast),
+            None => process::exit(65),
+        }
+    }
+
+    pub fn print_ast(&mut self, source: &str, format: &AstFormat, optimize: bool) -> Option<String> {
         let mut scanner = Scanner::new(source);
 
         let tokens = scanner.scan_tokens(|line, err| self.error(line, err));
-        let mut parser = Parser::new(tokens);
-        let expression = parser.parse(|tok, err| self.error_token(tok, err));
+        let folder = ConstantFolder::new();
+        let folder = if optimize { Some(&folder) } else { None };
+        if !Parser::new(tokens).is_expression() {
+            let program = self.parse_program(tokens, folder);
+            if self.had_error {
+                return None;
+            }
+            self.warn_folded(folder);
+
+            return program.map(|program| match *format {
+                AstFormat::SExpr => AstPrinter {}.print_program(&program),
+                AstFormat::Dot { lines } => DotPrinter::new(lines).print_program(&program),
+                AstFormat::Source => SourcePrinter {}.print_program(&program),
+            });
+        }
+
+        let expression = self.parse(tokens, folder);
 
-        // Stop if there was a syntax error.
         if self.had_error {
-            return;
+            return None;
         }
+        self.warn_folded(folder);
 
-        match expression {
-            Some(expr) => {
-                // let ast_printer = AstPrinter {};
-                // println!("{}", ast_printer.print(expr.as_ref()));
-                let interpreter = Interpreter {};
-                interpreter.interpret(expr, |tok, err| self.runtime_error(tok, err));
+        expression.map(|expr| match *format {
+            AstFormat::SExpr => AstPrinter {}.print(expr.as_ref()),
+            AstFormat::Dot { lines } => DotPrinter::new(lines).print(expr.as_ref()),
+            AstFormat::Source => SourcePrinter {}.print(expr.as_ref()),
+        })
+    }
+
+    fn run(&mut self, source: &str) {
+        let result = match self.backend {
+            Backend::Tree => self.evaluate(source),
+            Backend::Vm => self.execute(source),
+        };
+        self.report_result(result);
+    }
+
+    /// Runs a script on the configured backend, returning the value of a
+    /// script that is a single expression. Unlike `run_file`, this returns
+    /// runtime errors instead of reporting them; syntax errors are still
+    /// reported on stderr.
+    pub fn run_source(&mut self, source: &str) -> std::result::Result<Option<Value>, LoadError> {
+        match self.backend {
+            Backend::Tree => {
+                self.interpreter.modules().set_main(None);
+                self.evaluate(source)
             },
-            _ => {},
+            Backend::Vm => self.execute(source),
+        }
+    }
+
+    /// Prints the value of a script or reports its runtime error.
+    fn report_result(&mut self, result: std::result::Result<Option<Value>, LoadError>) {
+        match result {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) | Err(LoadError::Syntax) => {},
+            Err(LoadError::Runtime(e)) => self.runtime_error(&e),
+        }
+    }
+
+    /// Runs a script on the tree-walker. A script that is a single
+    /// expression evaluates to its value, anything else is run as a program.
+    fn evaluate(&mut self, source: &str) -> std::result::Result<Option<Value>, LoadError> {
+        self.had_error = false;
+        let tokens = self.scan(source);
+        self.interpreter.start();
+
+        if Parser::new(&tokens).is_expression() {
+            let expression = self.parse(&tokens, Some(&ConstantFolder::new()));
+
+            // Stop if there was a syntax error.
+            return match expression {
+                Some(expr) if !self.had_error => {
+                    // let ast_printer = AstPrinter {};
+                    // println!("{}", ast_printer.print(expr.as_ref()));
+                    self.interpreter.interpret_expression(expr).map(Some).map_err(LoadError::Runtime)
+                },
+                _ => Err(LoadError::Syntax),
+            };
+        }
+
+        match self.parse_program(&tokens, Some(&ConstantFolder::new())) {
+            Some(program) if !self.had_error => {
+                self.interpreter.interpret(&program).map(|_| None).map_err(LoadError::Runtime)
+            },
+            _ => Err(LoadError::Syntax),
+        }
+    }
+
+    /// Scans a script for the tree-walker. Tokens own their lexemes, so the
+    /// trees parsed from them don't borrow the source and live only as long
+    /// as the functions declared in them.
+    fn scan(&mut self, source: &str) -> Vec<Rc<Token>> {
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens(|line, err| self.error(line, err)).clone()
+    }
+
+    /// Compiles a script and runs it on the VM. Like `evaluate`, a script
+    /// that is a single expression evaluates to its value.
+    fn execute(&mut self, source: &str) -> std::result::Result<Option<Value>, LoadError> {
+        self.had_error = false;
+        let mut scanner = Scanner::new(source);
+
+        let tokens = scanner.scan_tokens(|line, err| self.error(line, err));
+        let expression = Parser::new(tokens).is_expression();
+        let chunk = Compiler::new(tokens).compile(|tok, err| self.error_token(tok, err));
+
+        // Stop if there was a syntax error.
+        match chunk {
+            Some(chunk) if !self.had_error => {
+                let value = self.run_chunk("<script>", &chunk).map_err(LoadError::Runtime)?;
+                Ok(Some(value).filter(|_| expression))
+            },
+            _ => Err(LoadError::Syntax),
+        }
+    }
+
+    fn run_bytecode(&mut self, filename: &str, bytes: &[u8]) {
+        match bytecode_file::deserialize(bytes) {
+            Ok(prototypes) => {
+                let script = &prototypes[0];
+                let result = self.run_chunk(&script.name, &script.chunk);
+                self.report_result(result.map(|value| Some(value).filter(|_| script.expression)).map_err(LoadError::Runtime));
+            },
+            Err(e) => {
+                eprintln!("Could not load '{}': {}", filename, e);
+                self.had_error = true;
+            },
+        }
+    }
+
+    fn run_chunk(&mut self, name: &str, chunk: &Chunk) -> std::result::Result<Value, RuntimeError> {
+        if self.disassemble {
+            eprint!("{}", debug::disassemble_chunk(chunk, name));
+        }
+
+        let mut vm = VM::new();
+        vm.set_trace(self.trace);
+        vm.set_gc(self.gc);
+        vm.set_budget(Budget::new(self.limits, self.cancel.clone()));
+        for (name, value) in &self.globals {
+            vm.define_global(name.clone(), value.clone());
+        }
+        let mut error = None;
+        let value = vm.interpret(chunk, |line, err| error = Some(RuntimeError::at(err, line)));
+        value.ok_or_else(|| {
+            // The VM only runs the top level of a script, so that is the whole trace.
+            let mut error = error.expect("the VM reports why it failed");
+            let file = self.interpreter.modules().main_file().map(|file| file.to_string());
+            error.trace.push(Frame { function: None, native: false, file, line: error.line.unwrap_or(0) });
+            error
+        })
+    }
+
+    /// Parses the tokens into an expression tree, running `folder` over it
+    /// if there is one.
+    fn parse<'a, U: 'a>(&mut self, tokens: &[Rc<Token>], folder: Option<&ConstantFolder<U>>) -> Option<ExprRef<'a, U>> {
+        let mut parser = Parser::new(tokens);
+
+        match folder {
+            Some(folder) => {
+                let expression = parser.parse::<FoldRes<'a, U>, _>(|tok, err| self.error_token(tok, err))?;
+                Some(folder.fold(expression.as_ref()))
+            },
+            None => parser.parse(|tok, err| self.error_token(tok, err)),
+        }
+    }
+
+    /// Parses the tokens into a program, folding constants with `folder` if there is one.
+    fn parse_program<'a, U: 'a>(&mut self, tokens: &[Rc<Token>], folder: Option<&ConstantFolder<U>>) -> Option<Vec<StmtRef<'a, U>>> {
+        let mut parser = Parser::new(tokens);
+
+        match folder {
+            Some(folder) => {
+                let program = parser.parse_program::<FoldRes<'a, U>, _>(|tok, err| self.error_token(tok, err))?;
+                Some(folder.fold_program(&program))
+            },
+            None => parser.parse_program(|tok, err| self.error_token(tok, err)),
+        }
+    }
+
+    /// Reports the operators the folder found to fail whenever they run.
+    /// They're only warnings: the program may never run them.
+    fn warn_folded<U>(&self, folder: Option<&ConstantFolder<U>>) {
+        for (token, message) in folder.map(ConstantFolder::diagnostics).unwrap_or_default() {
+            eprintln!("[line {}] Warning at '{}': {}", token.line, token.lexeme, message);
         }
     }
 
@@ -83,7 +548,7 @@ impl Lox {
         self.report(line, "", message);
     }
 
-    fn error_token<'a>(&mut self, token: Rc<Token<'a>>, message: &str) {
+    fn error_token(&mut self, token: Rc<Token>, message: &str) {
         if token.token_type == TokenType::Eof {
             self.report(token.line, " at end", message);
         } else {
@@ -91,15 +556,39 @@ impl Lox {
         }
     }
 
-    fn runtime_error<'a>(&mut self, token: Rc<Token<'a>>, message: &str) {
-        println!("{}\n[line {}]", message, token.line);
+    /// Reports an error that reached the top level, with the calls it left.
+    fn runtime_error(&mut self, error: &RuntimeError) {
+        eprintln!("{}", error);
         self.had_runtime_error = true;
     }
 
+
     fn report(&mut self, line: usize, whr: &str, message: &str) {
         let s = format!("[line {}] Error{}: {}\n", line, whr, message);
-        io::stderr().write(s.as_bytes()).unwrap();
+        stderr().write_all(s.as_bytes()).unwrap();
 
         self.had_error = true;
     }
-}
\ No newline at end of file
+}
+
+/// Reads the file at `filename`, exiting if it can't be read.
+fn read_file(filename: &str) -> Vec<u8> {
+    fs::read(filename).unwrap_or_else(|e| {
+        eprintln!("Can't read '{}': {}.", filename, e);
+        process::exit(66);
+    })
+}
+
+/// The text of a script, exiting if it isn't valid UTF-8.
+fn source_text(filename: &str, bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|_| {
+        eprintln!("Can't read '{}': invalid UTF-8.", filename);
+        process::exit(65);
+    })
+}
+
+/// `clock()`: seconds since the Unix epoch.
+fn clock(_arguments: &[Value]) -> std::result::Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
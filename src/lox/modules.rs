@@ -0,0 +1,203 @@
+//! Finding, parsing and caching the files scripts import.
+//!
+//! `import "util.lox" as util;` first looks for `util.lox` next to the
+//! importing file, then in every directory of the search path in order.
+//! Each file runs once; later imports share its module. Only the names a
+//! module declares with `export` are visible to the files importing it.
+//! Like the file natives, imports fail while I/O is switched off.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use lox::constant_folder::ConstantFolder;
+use lox::interner::{intern, Symbol};
+use lox::interpreter::Program;
+use lox::parser::Parser;
+use lox::scanner::Scanner;
+use lox::token::TokenType;
+use lox::value::*;
+
+/// The exports of a file that has run.
+pub struct Module {
+    pub path: PathBuf,
+    exports: HashMap<Symbol, Value>,
+}
+
+impl Module {
+    pub fn new(path: PathBuf, exports: HashMap<Symbol, Value>) -> Module {
+        Module {
+            path,
+            exports,
+        }
+    }
+
+    pub fn export(&self, name: &Symbol) -> Option<Value> {
+        self.exports.get(name).cloned()
+    }
+}
+
+impl NativeObject for Module {
+    fn class_name(&self) -> &str {
+        "Module"
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        self.export(&intern(name))
+    }
+}
+
+/// The file being run by the interpreter and the names it exported so far.
+struct Loading {
+    path: PathBuf,
+    /// The name stack traces show for the file.
+    name: Rc<String>,
+    exports: Vec<Symbol>,
+}
+
+impl Loading {
+    fn new(path: PathBuf) -> Loading {
+        let name = Rc::new(file_name(&path));
+        Loading { path, name, exports: Vec::new() }
+    }
+}
+
+#[derive(Default)]
+pub struct Modules {
+    search_path: Vec<PathBuf>,
+    cache: HashMap<PathBuf, (Rc<RefCell<Module>>, Value)>,
+    /// The files being run, innermost last.
+    loading: Vec<Loading>,
+    main: Option<Rc<String>>,
+    io_disabled: bool,
+}
+
+impl Modules {
+    /// Lets imports read files, or makes them fail with "I/O is disabled.".
+    pub fn set_io(&mut self, enabled: bool) {
+        self.io_disabled = !enabled;
+    }
+
+    pub fn add_search_path(&mut self, dir: &Path) {
+        self.search_path.push(dir.to_path_buf());
+    }
+
+    /// Forgets the files being run and starts over with the script at `path`,
+    /// or with a script that isn't a file, whose imports are relative to the
+    /// working directory.
+    pub fn set_main(&mut self, path: Option<&Path>) {
+        self.loading.clear();
+        self.main = None;
+        if let Some(path) = path {
+            let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            let loading = Loading::new(path);
+            self.main = Some(Rc::clone(&loading.name));
+            self.loading.push(loading);
+        }
+    }
+
+    /// The name of the main script, if it is a file.
+    pub fn main_file(&self) -> Option<Rc<String>> {
+        self.main.clone()
+    }
+
+    /// The name of the file being run, if it is a file.
+    pub fn current_file(&self) -> Option<Rc<String>> {
+        self.loading.last().map(|loading| Rc::clone(&loading.name))
+    }
+
+    /// Finds the file `name` refers to in the file being run.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, RuntimeError> {
+        if self.io_disabled {
+            return Result::Err(RuntimeError::new("I/O is disabled."));
+        }
+
+        let base = match self.loading.last() {
+            Some(loading) => loading.path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf(),
+            None => PathBuf::from("."),
+        };
+
+        let candidates = Some(base).into_iter().chain(self.search_path.iter().cloned()).map(|dir| dir.join(name));
+        for candidate in candidates {
+            if candidate.is_file() {
+                return fs::canonicalize(&candidate)
+                    .map_err(|e| RuntimeError::new(format!("Can't read module '{}': {}.", name, e)));
+            }
+        }
+
+        Result::Err(RuntimeError::new(format!("Can't find module '{}'.", name)))
+    }
+
+    pub fn cached(&self, path: &Path) -> Option<(Rc<RefCell<Module>>, Value)> {
+        self.cache.get(path).cloned()
+    }
+
+    /// Marks `path` as running, unless that would import it into itself.
+    pub fn enter(&mut self, path: &Path) -> Result<(), RuntimeError> {
+        if let Some(start) = self.loading.iter().position(|loading| loading.path == path) {
+            let cycle: Vec<String> = self.loading[start..].iter().map(|loading| &loading.path)
+                .chain(Some(&path.to_path_buf()))
+                .map(|path| file_name(path))
+                .collect();
+            return Result::Err(RuntimeError::new(format!("Import cycle: {}.", cycle.join(" -> "))));
+        }
+
+        self.loading.push(Loading::new(path.to_path_buf()));
+        Result::Ok(())
+    }
+
+    /// Marks the innermost running file as done, returning its exports.
+    pub fn leave(&mut self) -> Vec<Symbol> {
+        self.loading.pop().map(|loading| loading.exports).unwrap_or_default()
+    }
+
+    /// What the modules run so far export.
+    pub fn exported_values(&self) -> Vec<Value> {
+        self.cache.values().flat_map(|(module, _)| module.borrow().exports.values().cloned().collect::<Vec<_>>()).collect()
+    }
+
+    pub fn cache(&mut self, path: PathBuf, module: (Rc<RefCell<Module>>, Value)) {
+        self.cache.insert(path, module);
+    }
+
+    /// Records an exported name of the file being run.
+    pub fn export(&mut self, name: Symbol) {
+        if let Some(loading) = self.loading.last_mut() {
+            loading.exports.push(name);
+        }
+    }
+}
+
+/// Reads and parses a module. Its tree lives as long as the functions it
+/// declares.
+pub fn load(path: &Path) -> Result<Program, RuntimeError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| RuntimeError::new(format!("Can't read module '{}': {}.", file_name(path), e)))?;
+
+    let mut errors = Vec::new();
+    let tokens = Scanner::new(&source).scan_tokens(|line, message| {
+        errors.push(format!("[line {}] Error: {}", line, message));
+    }).clone();
+
+    let program = Parser::new(&tokens).parse_program(|token, message| {
+        let location = match token.token_type {
+            TokenType::Eof => String::from(" at end"),
+            _ => format!(" at '{}'", token.lexeme),
+        };
+        errors.push(format!("[line {}] Error{}: {}", token.line, location, message));
+    });
+    let program = program.map(|program| ConstantFolder::new().fold_program(&program));
+
+    match program {
+        Some(program) if errors.is_empty() => Result::Ok(program),
+        _ => Result::Err(RuntimeError::new(format!("Syntax error in module '{}':\n{}", file_name(path), errors.join("\n")))),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
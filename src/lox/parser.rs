@@ -1,22 +1,36 @@
 use std::rc::*;
 use lox::token::*;
 use lox::expr::*;
+use lox::stmt::*;
 
-pub struct Parser<'a> {
-    tokens: &'a Vec<Rc<Token<'a>>>,
+type ParseRes<T> = Result<T, (Rc<Token>, &'static str)>;
+
+/// How deeply expressions and statements may nest. Parsing, folding and
+/// evaluating a tree all recurse on the Rust stack once per level, so this
+/// keeps inputs like 100k opening parentheses from overflowing it.
+pub const MAX_NESTING: usize = 256;
+
+pub struct Parser<'t> {
+    tokens: &'t [Rc<Token>],
     current: usize,
+    /// How many function bodies enclose the current token.
+    function_depth: usize,
+    /// How many expressions and statements enclose the current token.
+    nesting: usize,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<Rc<Token<'a>>>) -> Parser<'a> {
+impl<'t> Parser<'t> {
+    pub fn new(tokens: &'t [Rc<Token>]) -> Parser<'t> {
         Parser {
             tokens,
             current: 0,
+            function_depth: 0,
+            nesting: 0,
         }
     }
 
-    pub fn parse<T: 'a, F>(&mut self, mut err: F) -> Option<ExprRef<'a, T>>
-    where F: FnMut(Rc<Token<'a>>, &'static str) {
+    pub fn parse<'a, T: 'a, F>(&mut self, mut err: F) -> Option<ExprRef<'a, T>>
+    where F: FnMut(Rc<Token>, &'static str) {
         match self.expression() {
             Ok(expr) => Option::Some(expr),
             Err(e) => {
@@ -26,11 +40,389 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expression<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
-        self.equalty()
+    /// Parses the tokens as a program. Every syntax error is reported, after
+    /// which parsing resumes at the next statement.
+    pub fn parse_program<'a, T: 'a, F>(&mut self, mut err: F) -> Option<Vec<StmtRef<'a, T>>>
+    where F: FnMut(Rc<Token>, &'static str) {
+        let mut statements = Vec::new();
+        let mut had_error = false;
+
+        while !self.is_at_end() {
+            match self.top_level_declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    err(e.0, e.1);
+                    had_error = true;
+                    self.synchronize();
+                },
+            }
+        }
+
+        if had_error {
+            Option::None
+        } else {
+            Option::Some(statements)
+        }
+    }
+
+    /// Whether the tokens form exactly one expression, as opposed to a program.
+    pub fn is_expression(&mut self) -> bool {
+        let start = self.current;
+        let mut expression = self.expression::<()>().is_ok() && self.is_at_end();
+        self.current = start;
+
+        // A leading brace is a block unless only a map makes sense of it.
+        if expression && self.check(TokenType::LeftBrace) {
+            expression = !self.is_program();
+            self.current = start;
+        }
+        expression
+    }
+
+    fn is_program(&mut self) -> bool {
+        while !self.is_at_end() {
+            if self.top_level_declaration::<()>().is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// A declaration at the top level of a file, where it may be exported.
+    fn top_level_declaration<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        if !self.mtch(&[TokenType::Export]) {
+            return self.declaration();
+        }
+
+        // Both declarations start with the name they declare.
+        let name = self.peek_next();
+        let declaration = if self.mtch(&[TokenType::Fun]) {
+            self.function("function")?
+        } else if self.mtch(&[TokenType::Var]) {
+            self.var_declaration()?
+        } else {
+            return Result::Err((self.peek(), "Expect 'fun' or 'var' after 'export'."));
+        };
+
+        Result::Ok(Export::new(name, declaration))
+    }
+
+    fn import_declaration<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let keyword = self.previous();
+        let path = self.consume(TokenType::Str, "Expect module path string.")?;
+
+        let (alias, names) = if keyword.token_type == TokenType::Import {
+            self.consume(TokenType::As, "Expect 'as' after module path.")?;
+            (Some(self.consume(TokenType::Identifier, "Expect module name after 'as'.")?), Vec::new())
+        } else {
+            self.consume(TokenType::Import, "Expect 'import' after module path.")?;
+            let mut names = vec![self.consume(TokenType::Identifier, "Expect name to import.")?];
+            while self.mtch(&[TokenType::Comma]) {
+                names.push(self.consume(TokenType::Identifier, "Expect name to import.")?);
+            }
+            (None, names)
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        Result::Ok(Import::new(keyword, path, alias, names))
+    }
+
+    fn declaration<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        if self.mtch(&[TokenType::Export]) {
+            return Result::Err((self.previous(), "Can only export top-level declarations."));
+        }
+        if self.mtch(&[TokenType::Import, TokenType::From]) {
+            return self.import_declaration();
+        }
+        if self.mtch(&[TokenType::Fun]) {
+            return self.function("function");
+        }
+        if self.mtch(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+
+        self.statement()
+    }
+
+    fn function<'a, T: 'a>(&mut self, kind: &'static str) -> ParseRes<StmtRef<'a, T>> {
+        let name = self.consume(TokenType::Identifier, match kind {
+            "method" => "Expect method name.",
+            _ => "Expect function name.",
+        })?;
+        self.consume(TokenType::LeftParen, match kind {
+            "method" => "Expect '(' after method name.",
+            _ => "Expect '(' after function name.",
+        })?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Result::Err((self.peek(), "Can't have more than 255 parameters."));
+                }
+                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+
+                if !self.mtch(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(TokenType::LeftBrace, match kind {
+            "method" => "Expect '{' before method body.",
+            _ => "Expect '{' before function body.",
+        })?;
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+
+        Result::Ok(Function::new(name, params, body?))
+    }
+
+    fn var_declaration<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+
+        let initializer = if self.mtch(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.")?;
+        Result::Ok(Var::new(name, initializer))
+    }
+
+    fn statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        self.nested(|parser| parser.simple_statement())
+    }
+
+    /// A statement, which may contain others but isn't counted as a level of nesting.
+    fn simple_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        if self.mtch(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.mtch(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.mtch(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.mtch(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.mtch(&[TokenType::Throw]) {
+            return self.throw_statement();
+        }
+        if self.mtch(&[TokenType::Try]) {
+            return self.try_statement();
+        }
+        if self.mtch(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.mtch(&[TokenType::LeftBrace]) {
+            return Result::Ok(Block::new(self.block()?));
+        }
+
+        self.expression_statement()
+    }
+
+    /// `for` has no node of its own: it is desugared into a `while` loop.
+    fn for_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check(TokenType::Var) && self.check_at(2, TokenType::In) {
+            self.advance();
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+            let keyword = self.advance();
+            let iterable = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+            let body = self.statement()?;
+            return Result::Ok(ForIn::new(name, keyword, iterable, body));
+        }
+
+        let initializer = if self.mtch(&[TokenType::Semicolon]) {
+            None
+        } else if self.mtch(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::Semicolon) {
+            Literal::new(TokenLiteral::Bool(true))
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+
+        if let Some(increment) = increment {
+            body = Block::new(vec![body, Expression::new(increment)]);
+        }
+        body = While::new(condition, body);
+        if let Some(initializer) = initializer {
+            body = Block::new(vec![initializer, body]);
+        }
+
+        Result::Ok(body)
+    }
+
+    fn if_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = self.statement()?;
+        let else_branch = if self.mtch(&[TokenType::Else]) {
+            Some(self.statement()?)
+        } else {
+            None
+        };
+
+        Result::Ok(If::new(condition, then_branch, else_branch))
+    }
+
+    fn print_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Result::Ok(Print::new(value))
+    }
+
+    fn return_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let keyword = self.previous();
+        if self.function_depth == 0 {
+            return Result::Err((keyword, "Can't return from top-level code."));
+        }
+
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Result::Ok(Return::new(keyword, value))
+    }
+
+    fn throw_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Result::Ok(Throw::new(keyword, value))
+    }
+
+    fn try_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let keyword = self.previous();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let handler = if self.mtch(&[TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(TokenType::Identifier, "Expect error variable name.")?;
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let finalizer = if self.mtch(&[TokenType::Finally]) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if handler.is_none() && finalizer.is_none() {
+            return Result::Err((self.peek(), "Expect 'catch' or 'finally' after try block."));
+        }
+        Result::Ok(Try::new(keyword, body, handler, finalizer))
+    }
+
+    fn while_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = self.statement()?;
+
+        Result::Ok(While::new(condition, body))
+    }
+
+    fn block<'a, T: 'a>(&mut self) -> ParseRes<Vec<StmtRef<'a, T>>> {
+        self.nested(|parser| {
+            let mut statements = Vec::new();
+
+            while !parser.check(TokenType::RightBrace) && !parser.is_at_end() {
+                statements.push(parser.declaration()?);
+            }
+
+            parser.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+            Result::Ok(statements)
+        })
+    }
+
+    fn expression_statement<'a, T: 'a>(&mut self) -> ParseRes<StmtRef<'a, T>> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Result::Ok(Expression::new(expr))
+    }
+
+    fn expression<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
+        self.nested(|parser| parser.assignment())
+    }
+
+    fn assignment<'a, T: 'a>(&mut self) -> ParseRes<ExprRef<'a, T>> {
+        let expr = self.or()?;
+
+        if self.mtch(&[TokenType::Equal]) {
+            let equals = self.previous();
+            let value = self.nested(|parser| parser.assignment())?;
+
+            return match expr.target() {
+                Some(Target::Variable(name)) => Result::Ok(Assign::new(name, value)),
+                Some(Target::Property(object, name)) => Result::Ok(Set::new(object, name, value)),
+                Some(Target::Index(object, bracket, index)) => Result::Ok(SetIndex::new(object, bracket, index, value)),
+                None => Result::Err((equals, "Invalid assignment target.")),
+            };
+        }
+
+        Result::Ok(expr)
+    }
+
+    fn or<'a, T: 'a>(&mut self) -> ParseRes<ExprRef<'a, T>> {
+        let mut expr = self.and()?;
+
+        while self.mtch(&[TokenType::Or]) {
+            let operator = self.previous();
+            let right = self.and()?;
+            expr = Logical::new(expr, operator, right);
+        }
+
+        Result::Ok(expr)
     }
 
-    fn equalty<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
+    fn and<'a, T: 'a>(&mut self) -> ParseRes<ExprRef<'a, T>> {
+        let mut expr = self.equalty()?;
+
+        while self.mtch(&[TokenType::And]) {
+            let operator = self.previous();
+            let right = self.equalty()?;
+            expr = Logical::new(expr, operator, right);
+        }
+
+        Result::Ok(expr)
+    }
+
+    fn equalty<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
         let mut expr = self.comparison()?;
 
         while self.mtch(&[TokenType::BangEqual, TokenType::EqualEqual]) {
@@ -42,19 +434,19 @@ impl<'a> Parser<'a> {
         Result::Ok(expr)
     }
 
-    fn comparison<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
-        let mut expr: Rc<Expr<'a, T>> = self.addition()?;
+    fn comparison<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
+        let mut expr: Rc<dyn Expr<'a, T>> = self.addition()?;
 
         while self.mtch(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
-            let operator: Rc<Token<'a>> = self.previous();
-            let right: Rc<Expr<'a, T>> = self.addition()?;
+            let operator: Rc<Token> = self.previous();
+            let right: Rc<dyn Expr<'a, T>> = self.addition()?;
             expr = Binary::new(expr, operator, right);
         }
 
         Result::Ok(expr)
     }
 
-    fn addition<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
+    fn addition<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
         let mut expr = self.multiplication()?;
 
         while self.mtch(&[TokenType::Minus, TokenType::Plus]) {
@@ -66,10 +458,10 @@ impl<'a> Parser<'a> {
         Result::Ok(expr)
     }
 
-    fn multiplication<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
+    fn multiplication<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
         let mut expr = self.unary()?;
 
-        while self.mtch(&[TokenType::Slash, TokenType::Star]) {
+        while self.mtch(&[TokenType::Percent, TokenType::Slash, TokenType::Star]) {
             let operator = self.previous();
             let right = self.unary()?;
             expr = Binary::new(expr, operator, right);
@@ -78,17 +470,72 @@ impl<'a> Parser<'a> {
         Result::Ok(expr)
     }
 
-    fn unary<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
+    fn unary<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
         if self.mtch(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous();
-            let right = self.unary()?;
+            let right = self.nested(|parser| parser.unary())?;
             return Result::Ok(Unary::new(operator, right));
         }
 
-        self.primary()
+        self.exponent()
     }
 
-    fn primary<T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token<'a>>, &'static str)> {
+    /// `**` binds tighter than unary minus on its left, so `-2 ** 2` is -4,
+    /// and is right associative.
+    fn exponent<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
+        let expr = self.call()?;
+
+        if self.mtch(&[TokenType::StarStar]) {
+            let operator = self.previous();
+            let right = self.nested(|parser| parser.unary())?;
+            return Result::Ok(Binary::new(expr, operator, right));
+        }
+
+        Result::Ok(expr)
+    }
+
+    fn call<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.mtch(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.mtch(&[TokenType::Dot]) {
+                let name = self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Get::new(expr, name);
+            } else if self.mtch(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Index::new(expr, bracket, index);
+            } else {
+                break;
+            }
+        }
+
+        Result::Ok(expr)
+    }
+
+    fn finish_call<'a, T: 'a>(&mut self, callee: ExprRef<'a, T>) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
+        let mut arguments = Vec::new();
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Result::Err((self.peek(), "Can't have more than 255 arguments."));
+                }
+                arguments.push(self.expression()?);
+
+                if !self.mtch(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        Result::Ok(Call::new(callee, paren, arguments))
+    }
+
+    fn primary<'a, T: 'a>(&mut self) -> Result<ExprRef<'a, T>, (Rc<Token>, &'static str)> {
         if self.mtch(&[TokenType::False]) {
             return Result::Ok(Literal::new(TokenLiteral::Bool(false)));
         }
@@ -103,16 +550,65 @@ impl<'a> Parser<'a> {
             return Result::Ok(Literal::new(self.previous().literal.clone()));
         }
 
+        if self.mtch(&[TokenType::Identifier]) {
+            return Result::Ok(Variable::new(self.previous()));
+        }
+
         if self.mtch(&[TokenType::LeftParen]) {
-            let expr: Rc<Expr<'a, T>> = self.expression()?;
+            let expr: Rc<dyn Expr<'a, T>> = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
             return Result::Ok(Grouping::new(expr));
         }
 
-        Result::Err((self.peek(), "Expected expression."))
+        if self.mtch(&[TokenType::LeftBrace]) {
+            let mut keys = Vec::new();
+            let mut values = Vec::new();
+            if !self.check(TokenType::RightBrace) {
+                loop {
+                    keys.push(self.expression()?);
+                    self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                    values.push(self.expression()?);
+                    if !self.mtch(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            let brace = self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+            return Result::Ok(Map::new(brace, keys, values));
+        }
+
+        if self.mtch(&[TokenType::LeftBracket]) {
+            let mut elements = Vec::new();
+            if !self.check(TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.mtch(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            let bracket = self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+            return Result::Ok(List::new(bracket, elements));
+        }
+
+        Result::Err((self.peek(), "Expect expression."))
+    }
+
+    /// Parses something nested in what is being parsed, failing once that is
+    /// more than `MAX_NESTING` levels deep.
+    fn nested<R, P>(&mut self, parse: P) -> ParseRes<R>
+    where P: FnOnce(&mut Parser<'t>) -> ParseRes<R> {
+        if self.nesting >= MAX_NESTING {
+            return Result::Err((self.peek(), "Too much nesting."));
+        }
+
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &'static str) -> Result<Rc<Token<'a>>, (Rc<Token<'a>>, &'static str)> {
+    fn consume(&mut self, token_type: TokenType, message: &'static str) -> Result<Rc<Token>, (Rc<Token>, &'static str)> {
         if self.check(token_type) {
             return Result::Ok(self.advance());
         }
@@ -120,29 +616,35 @@ impl<'a> Parser<'a> {
         Result::Err((self.peek(), message))
     }
 
-    // fn synchronize(&mut self) {
-    //     self.advance();
+    /// Skips tokens until the start of the next statement.
+    fn synchronize(&mut self) {
+        self.advance();
 
-    //     while !self.is_at_end() {
-    //         if self.previous().token_type == TokenType::Semicolon {
-    //             return;
-    //         }
-    //     }
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
 
-    //     match self.peek().token_type {
-    //         TokenType::Class |
-    //         TokenType::Fun |
-    //         TokenType::Var |
-    //         TokenType::For |
-    //         TokenType::If |
-    //         TokenType::While |
-    //         TokenType::Print |
-    //         TokenType::Return => return,
-    //         _ => {},
-    //     }
+            match self.peek().token_type {
+                TokenType::Class |
+                TokenType::Export |
+                TokenType::Fun |
+                TokenType::From |
+                TokenType::Import |
+                TokenType::Var |
+                TokenType::For |
+                TokenType::If |
+                TokenType::While |
+                TokenType::Print |
+                TokenType::Return |
+                TokenType::Throw |
+                TokenType::Try => return,
+                _ => {},
+            }
 
-    //     self.advance();
-    // }
+            self.advance();
+        }
+    }
 
     fn mtch(&mut self, types: &[TokenType]) -> bool {
         for t in types {
@@ -163,7 +665,19 @@ impl<'a> Parser<'a> {
         self.peek().token_type == token_type
     }
 
-    fn advance(&mut self) -> Rc<Token<'a>> {
+    fn peek_next(&self) -> Rc<Token> {
+        Rc::clone(self.tokens.get(self.current + 1).unwrap_or_else(|| self.tokens.last().unwrap()))
+    }
+
+    /// Like `check`, but looks `ahead` tokens past the current one.
+    fn check_at(&self, ahead: usize, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + ahead) {
+            Some(token) => token.token_type == token_type,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> Rc<Token> {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -175,11 +689,11 @@ impl<'a> Parser<'a> {
         self.peek().token_type == TokenType::Eof
     }
 
-    fn peek(&self) -> Rc<Token<'a>> {
+    fn peek(&self) -> Rc<Token> {
         Rc::clone(&self.tokens[self.current])
     }
 
-    fn previous(&self) -> Rc<Token<'a>> {
+    fn previous(&self) -> Rc<Token> {
         Rc::clone(&self.tokens[self.current - 1])
     }
 }
\ No newline at end of file
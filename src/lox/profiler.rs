@@ -0,0 +1,205 @@
+//! Where scripts spend their time, for the tree-walker.
+//!
+//! Every call is timed, giving the calls and the time of each function,
+//! both inclusive, with the calls it makes, and exclusive, without them.
+//! Every `interval` the line being run and the calls running are sampled,
+//! giving the hot lines and a folded-stack file for flame graph tools like
+//! `flamegraph.pl` and `inferno-flamegraph`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How often `Lox::set_profiling` samples the running line.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_micros(100);
+
+/// A call being run.
+struct Call {
+    frame: String,
+    file: Option<Rc<String>>,
+    started: Instant,
+    /// The time the calls it made took.
+    children: Duration,
+    /// The line it is at, once it evaluated an expression.
+    line: Option<usize>,
+}
+
+#[derive(Default)]
+struct Totals {
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+}
+
+pub struct Profiler {
+    interval: Duration,
+    next_sample: Instant,
+    /// The calls being run, outermost first.
+    calls: Vec<Call>,
+    functions: HashMap<String, Totals>,
+    lines: HashMap<(Option<Rc<String>>, usize), u64>,
+    stacks: HashMap<String, u64>,
+}
+
+impl Profiler {
+    pub fn new(interval: Duration) -> Profiler {
+        Profiler {
+            interval,
+            next_sample: Instant::now() + interval,
+            calls: Vec::new(),
+            functions: HashMap::new(),
+            lines: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Names a function like traces do: `name` or `script` for the top
+    /// level of a file, followed by the file or `(native)`.
+    pub fn frame(function: Option<&str>, native: bool, file: Option<&str>) -> String {
+        let mut frame = String::from(function.unwrap_or("script"));
+        match file {
+            _ if native => frame += " (native)",
+            Some(file) => write!(frame, " ({})", file).unwrap(),
+            None => {},
+        }
+        frame
+    }
+
+    pub fn enter(&mut self, frame: String, file: Option<Rc<String>>) {
+        self.calls.push(Call { frame, file, started: Instant::now(), children: Duration::default(), line: None });
+    }
+
+    pub fn exit(&mut self) {
+        self.sample();
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+
+        let elapsed = call.started.elapsed();
+        let recursive = self.calls.iter().any(|outer| outer.frame == call.frame);
+        let totals = self.functions.entry(call.frame).or_default();
+        totals.calls += 1;
+        totals.exclusive += elapsed.checked_sub(call.children).unwrap_or_default();
+        // The outermost call of a recursive function already counts the inner ones.
+        if !recursive {
+            totals.inclusive += elapsed;
+        }
+        if let Some(caller) = self.calls.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    /// Moves the innermost call to `line`.
+    pub fn set_line(&mut self, line: usize) {
+        if let Some(call) = self.calls.last_mut() {
+            call.line = Some(line);
+        }
+    }
+
+    /// Samples the running line and calls if the interval is up, once for
+    /// every interval that passed since the last sample.
+    pub fn sample(&mut self) {
+        let now = Instant::now();
+        if now < self.next_sample || self.calls.is_empty() {
+            return;
+        }
+
+        let behind = (now - self.next_sample).as_nanos() / self.interval.as_nanos().max(1);
+        let samples = behind as u64 + 1;
+        self.next_sample = now + self.interval;
+
+        // Natives have no lines; their time goes to the line calling them.
+        if let Some(line) = self.calls.iter().rev().find_map(|call| call.line.map(|line| (call.file.clone(), line))) {
+            *self.lines.entry(line).or_default() += samples;
+        }
+        let stack: Vec<&str> = self.calls.iter().map(|call| call.frame.as_str()).collect();
+        *self.stacks.entry(stack.join(";")).or_default() += samples;
+    }
+
+    /// What was measured so far. Calls still running aren't counted yet.
+    pub fn profile(&self) -> Profile {
+        let mut functions: Vec<FunctionProfile> = self.functions.iter().map(|(frame, totals)| FunctionProfile {
+            frame: frame.clone(),
+            calls: totals.calls,
+            inclusive: totals.inclusive,
+            exclusive: totals.exclusive,
+        }).collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.frame.cmp(&b.frame)));
+
+        let mut lines: Vec<LineProfile> = self.lines.iter().map(|(&(ref file, line), &samples)| LineProfile {
+            file: file.as_ref().map(|file| file.to_string()),
+            line,
+            samples,
+        }).collect();
+        lines.sort_by(|a, b| b.samples.cmp(&a.samples).then_with(|| (&a.file, a.line).cmp(&(&b.file, b.line))));
+
+        let mut stacks: Vec<(String, u64)> = self.stacks.iter().map(|(stack, &samples)| (stack.clone(), samples)).collect();
+        stacks.sort();
+
+        Profile { interval: self.interval, functions, lines, stacks }
+    }
+}
+
+/// The calls and time of one function, named like `Profiler::frame`.
+#[derive(Debug, Clone)]
+pub struct FunctionProfile {
+    pub frame: String,
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+/// How often a line was running when sampled.
+#[derive(Debug, Clone)]
+pub struct LineProfile {
+    pub file: Option<String>,
+    pub line: usize,
+    pub samples: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub interval: Duration,
+    /// Slowest first, by exclusive time.
+    pub functions: Vec<FunctionProfile>,
+    /// Most sampled first.
+    pub lines: Vec<LineProfile>,
+    /// The calls running at each sample, outermost first and separated by
+    /// `;`, and how often they were.
+    pub stacks: Vec<(String, u64)>,
+}
+
+impl Profile {
+    /// A table of the functions, then one of the lines.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "{:>10} {:>12} {:>12}  function", "calls", "inclusive", "exclusive").unwrap();
+        for function in &self.functions {
+            writeln!(report, "{:>10} {:>12} {:>12}  {}", function.calls,
+                     format!("{:.2?}", function.inclusive), format!("{:.2?}", function.exclusive), function.frame).unwrap();
+        }
+
+        let total: u64 = self.lines.iter().map(|line| line.samples).sum();
+        writeln!(report).unwrap();
+        writeln!(report, "{:>10} {:>7}  line (a sample every {:?})", "samples", "%", self.interval).unwrap();
+        for line in &self.lines {
+            let location = match line.file {
+                Some(ref file) => format!("{}:{}", file, line.line),
+                None => format!("line {}", line.line),
+            };
+            writeln!(report, "{:>10} {:>6.1}%  {}", line.samples, line.samples as f64 * 100.0 / total as f64, location).unwrap();
+        }
+        report
+    }
+
+    /// A line per stack, as in `script (main.lox);fib (main.lox) 42`.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for &(ref stack, samples) in &self.stacks {
+            writeln!(folded, "{} {}", stack, samples).unwrap();
+        }
+        folded
+    }
+}
@@ -2,26 +2,36 @@ use std::rc::*;
 use lox::token::Token;
 use lox::token::TokenType;
 use lox::token::TokenLiteral;
+use lox::interner::intern;
 
 use std::collections::HashMap;
 
 lazy_static! {
-    static ref Keywords: HashMap<&'static str, TokenType> = {
+    static ref KEYWORDS: HashMap<&'static str, TokenType> = {
         let mut m = HashMap::new();
         m.insert("and", TokenType::And);
+        m.insert("as", TokenType::As);
+        m.insert("catch", TokenType::Catch);
         m.insert("class", TokenType::Class);
         m.insert("else", TokenType::Else);
+        m.insert("export", TokenType::Export);
         m.insert("false", TokenType::False);
+        m.insert("finally", TokenType::Finally);
         m.insert("for", TokenType::For);
+        m.insert("from", TokenType::From);
         m.insert("fun", TokenType::Fun);
         m.insert("if", TokenType::If);
+        m.insert("import", TokenType::Import);
+        m.insert("in", TokenType::In);
         m.insert("nil", TokenType::Nil);
         m.insert("or", TokenType::Or);
         m.insert("print", TokenType::Print);
         m.insert("return", TokenType::Return);
         m.insert("super", TokenType::Super);
         m.insert("this", TokenType::This);
+        m.insert("throw", TokenType::Throw);
         m.insert("true", TokenType::True);
+        m.insert("try", TokenType::Try);
         m.insert("var", TokenType::Var);
         m.insert("while", TokenType::While);
         m
@@ -30,14 +40,14 @@ lazy_static! {
 
 pub struct Scanner<'a> {
     source: &'a str,
-    tokens: Vec<Rc<Token<'a>>>,
+    tokens: Vec<Rc<Token>>,
     start: usize,
     current: usize,
     line: usize,
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: &'a str) -> Scanner {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
             source,
             tokens: Vec::new(),
@@ -47,7 +57,7 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    pub fn scan_tokens<F>(&mut self, mut err: F) -> &Vec<Rc<Token<'a>>>
+    pub fn scan_tokens<F>(&mut self, mut err: F) -> &Vec<Rc<Token>>
     where F: FnMut(usize, &str) {
         while !self.is_at_end() {
             // We are at the beginning of the next lexeme.
@@ -67,12 +77,19 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(TokenType::RightParen, TokenLiteral::None),
             '{' => self.add_token(TokenType::LeftBrace, TokenLiteral::None),
             '}' => self.add_token(TokenType::RightBrace, TokenLiteral::None),
+            '[' => self.add_token(TokenType::LeftBracket, TokenLiteral::None),
+            ']' => self.add_token(TokenType::RightBracket, TokenLiteral::None),
+            ':' => self.add_token(TokenType::Colon, TokenLiteral::None),
             ',' => self.add_token(TokenType::Comma, TokenLiteral::None),
             '.' => self.add_token(TokenType::Dot, TokenLiteral::None),
             '-' => self.add_token(TokenType::Minus, TokenLiteral::None),
             '+' => self.add_token(TokenType::Plus, TokenLiteral::None),
             ';' => self.add_token(TokenType::Semicolon, TokenLiteral::None),
-            '*' => self.add_token(TokenType::Star, TokenLiteral::None),
+            '%' => self.add_token(TokenType::Percent, TokenLiteral::None),
+            '*' => {
+                let m = if self.mtch("*") {TokenType::StarStar} else {TokenType::Star};
+                self.add_token(m, TokenLiteral::None);
+            },
             '!' => {
                 let m = if self.mtch("=") {TokenType::BangEqual} else {TokenType::Bang};
                 self.add_token(m, TokenLiteral::None);
@@ -127,8 +144,9 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        (&self.source[self.current - 1..self.current]).parse::<char>().unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
     }
 
     fn mtch(&mut self, expected: &'a str) -> bool {
@@ -136,29 +154,22 @@ impl<'a> Scanner<'a> {
             return false;
         }
 
-        let lexeme = &self.source[self.current..self.current + 1];
-        if lexeme != expected {
+        if !self.source[self.current..].starts_with(expected) {
             return false;
         }
 
-        self.current += 1;
+        self.current += expected.len();
         true
     }
 
+    // Positions are byte offsets, so characters are read whole to keep
+    // them on UTF-8 boundaries.
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        (&self.source[self.current..self.current + 1]).parse().unwrap()
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-
-        (&self.source[self.current + 1..self.current + 2]).parse().unwrap()
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn string(&mut self) -> Result<(), (usize, &'static str)> {
@@ -176,13 +187,13 @@ impl<'a> Scanner<'a> {
         self.advance();
 
         let s = &self.source[self.start + 1..self.current - 1];
-        self.add_token(TokenType::Str, TokenLiteral::Str(String::from(s)));
+        self.add_token(TokenType::Str, TokenLiteral::Str(intern(s).into()));
 
         Ok(())
     }
 
     fn is_digit(c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn number(&mut self) {
@@ -200,7 +211,7 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        let parsed_literal = (&self.source[self.start..self.current]).parse::<f64>().unwrap();
+        let parsed_literal = self.source[self.start..self.current].parse::<f64>().unwrap();
         self.add_token(
             TokenType::Number,
             TokenLiteral::Number(parsed_literal)
@@ -208,9 +219,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_alpha(c: char) -> bool {
-        c >= 'a' && c <= 'z' ||
-        c >= 'A' && c <= 'Z' ||
-        c == '_'
+        c.is_ascii_alphabetic() || c == '_'
     }
 
     fn is_alpha_numeric(c: char) -> bool {
@@ -223,9 +232,9 @@ impl<'a> Scanner<'a> {
         }
 
         let txt = &self.source[self.start..self.current];
-        match Keywords.get(txt) {
+        match KEYWORDS.get(txt) {
             Some(token) => {
-                self.add_token(token.clone(), TokenLiteral::Str(String::from(txt)));
+                self.add_token(token.clone(), TokenLiteral::Str(intern(txt).into()));
             },
             None => {
                 self.add_token(TokenType::Identifier, TokenLiteral::None);
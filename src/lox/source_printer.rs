@@ -0,0 +1,188 @@
+use lox::expr::*;
+use lox::stmt::*;
+use lox::token::TokenLiteral;
+
+/// Prints a syntax tree back as Lox source.
+///
+/// Parsing the output gives the same tree, up to line numbers, for any tree
+/// the parser built: precedence is kept by the tree's `Grouping` nodes, so
+/// no parentheses are added. A `for` loop comes out as the `while` loop it
+/// was desugared into.
+pub struct SourcePrinter;
+
+impl<'a> SourcePrinter {
+    pub fn print(&self, expr: &dyn Expr<'a, String>) -> String {
+        expr.accept(self)
+    }
+
+    /// Prints every statement of a program on its own line.
+    pub fn print_program(&self, program: &[StmtRef<'a, String>]) -> String {
+        program.iter().map(|stmt| stmt.accept(self)).collect::<Vec<_>>().join("\n")
+    }
+
+    fn block(&self, statements: &[StmtRef<'a, String>]) -> String {
+        if statements.is_empty() {
+            return String::from("{}");
+        }
+
+        let mut s = String::from("{\n");
+        for statement in statements {
+            for line in statement.accept(self).lines() {
+                s += "    ";
+                s += line;
+                s += "\n";
+            }
+        }
+        s + "}"
+    }
+
+    fn list(&self, exprs: &[ExprRef<'a, String>]) -> String {
+        exprs.iter().map(|e| e.accept(self)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl<'a> Visitor<'a, String> for SourcePrinter {
+    fn visit_assign(&self, expr: &Assign<'a, String>) -> String {
+        format!("{} = {}", expr.name.lexeme, expr.value.accept(self))
+    }
+
+    fn visit_binary(&self, expr: &Binary<'a, String>) -> String {
+        format!("{} {} {}", expr.left.accept(self), expr.operator.lexeme, expr.right.accept(self))
+    }
+
+    fn visit_call(&self, expr: &Call<'a, String>) -> String {
+        format!("{}({})", expr.callee.accept(self), self.list(&expr.arguments))
+    }
+
+    fn visit_get(&self, expr: &Get<'a, String>) -> String {
+        format!("{}.{}", expr.object.accept(self), expr.name.lexeme)
+    }
+
+    fn visit_grouping(&self, expr: &Grouping<'a, String>) -> String {
+        format!("({})", expr.expression.accept(self))
+    }
+
+    fn visit_index(&self, expr: &Index<'a, String>) -> String {
+        format!("{}[{}]", expr.object.accept(self), expr.index.accept(self))
+    }
+
+    fn visit_list(&self, expr: &List<'a, String>) -> String {
+        format!("[{}]", self.list(&expr.elements))
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> String {
+        match expr.value {
+            TokenLiteral::Str(ref s) => format!("\"{}\"", s),
+            // Too many digits to fit in a double is the only way to write infinity.
+            TokenLiteral::Number(n) if n.is_infinite() => format!("1{}", "0".repeat(309)),
+            TokenLiteral::Number(n) => n.to_string(),
+            TokenLiteral::Bool(b) => b.to_string(),
+            TokenLiteral::None => String::from("nil"),
+        }
+    }
+
+    fn visit_logical(&self, expr: &Logical<'a, String>) -> String {
+        format!("{} {} {}", expr.left.accept(self), expr.operator.lexeme, expr.right.accept(self))
+    }
+
+    fn visit_map(&self, expr: &Map<'a, String>) -> String {
+        let entries: Vec<String> = expr.keys.iter().zip(&expr.values)
+            .map(|(key, value)| format!("{}: {}", key.accept(self), value.accept(self)))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+
+    fn visit_set(&self, expr: &Set<'a, String>) -> String {
+        format!("{}.{} = {}", expr.object.accept(self), expr.name.lexeme, expr.value.accept(self))
+    }
+
+    fn visit_set_index(&self, expr: &SetIndex<'a, String>) -> String {
+        format!("{}[{}] = {}", expr.object.accept(self), expr.index.accept(self), expr.value.accept(self))
+    }
+
+    fn visit_unary(&self, expr: &Unary<'a, String>) -> String {
+        format!("{}{}", expr.operator.lexeme, expr.right.accept(self))
+    }
+
+    fn visit_variable(&self, expr: &Variable) -> String {
+        expr.name.lexeme.to_string()
+    }
+}
+
+impl<'a> StmtVisitor<'a, String> for SourcePrinter {
+    fn visit_block(&self, stmt: &Block<'a, String>) -> String {
+        self.block(&stmt.statements)
+    }
+
+    fn visit_export(&self, stmt: &Export<'a, String>) -> String {
+        format!("export {}", stmt.declaration.accept(self))
+    }
+
+    fn visit_expression(&self, stmt: &Expression<'a, String>) -> String {
+        format!("{};", stmt.expression.accept(self))
+    }
+
+    fn visit_for_in(&self, stmt: &ForIn<'a, String>) -> String {
+        format!("for (var {} in {}) {}", stmt.name.lexeme, stmt.iterable.accept(self), stmt.body.accept(self))
+    }
+
+    fn visit_function(&self, stmt: &Function<'a, String>) -> String {
+        let params: Vec<&str> = stmt.params.iter().map(|p| &*p.lexeme).collect();
+        format!("fun {}({}) {}", stmt.name.lexeme, params.join(", "), self.block(&stmt.body))
+    }
+
+    fn visit_if(&self, stmt: &If<'a, String>) -> String {
+        let mut s = format!("if ({}) {}", stmt.condition.accept(self), stmt.then_branch.accept(self));
+        if let Some(ref else_branch) = stmt.else_branch {
+            s += &format!(" else {}", else_branch.accept(self));
+        }
+        s
+    }
+
+    fn visit_import(&self, stmt: &Import<'a, String>) -> String {
+        match stmt.alias {
+            Some(ref alias) => format!("import {} as {};", stmt.path.lexeme, alias.lexeme),
+            None => {
+                let names: Vec<&str> = stmt.names.iter().map(|n| &*n.lexeme).collect();
+                format!("from {} import {};", stmt.path.lexeme, names.join(", "))
+            },
+        }
+    }
+
+    fn visit_print(&self, stmt: &Print<'a, String>) -> String {
+        format!("print {};", stmt.expression.accept(self))
+    }
+
+    fn visit_return(&self, stmt: &Return<'a, String>) -> String {
+        match stmt.value {
+            Some(ref value) => format!("return {};", value.accept(self)),
+            None => String::from("return;"),
+        }
+    }
+
+    fn visit_throw(&self, stmt: &Throw<'a, String>) -> String {
+        format!("throw {};", stmt.value.accept(self))
+    }
+
+    fn visit_try(&self, stmt: &Try<'a, String>) -> String {
+        let mut s = format!("try {}", self.block(&stmt.body));
+        if let Some((ref name, ref handler)) = stmt.handler {
+            s += &format!(" catch ({}) {}", name.lexeme, self.block(handler));
+        }
+        if let Some(ref finalizer) = stmt.finalizer {
+            s += &format!(" finally {}", self.block(finalizer));
+        }
+        s
+    }
+
+    fn visit_var(&self, stmt: &Var<'a, String>) -> String {
+        match stmt.initializer {
+            Some(ref initializer) => format!("var {} = {};", stmt.name.lexeme, initializer.accept(self)),
+            None => format!("var {};", stmt.name.lexeme),
+        }
+    }
+
+    fn visit_while(&self, stmt: &While<'a, String>) -> String {
+        format!("while ({}) {}", stmt.condition.accept(self), stmt.body.accept(self))
+    }
+}
@@ -0,0 +1,330 @@
+#![allow(clippy::new_ret_no_self)]
+
+use std::marker::PhantomData;
+use std::rc::*;
+use lox::token::Token;
+use lox::expr::ExprRef;
+use lox::interner::*;
+
+pub type StmtRef<'a, T> = Rc<dyn Stmt<'a, T> + 'a>;
+
+pub trait StmtVisitor<'a, T: 'a> {
+    fn visit_block(&self, stmt: &Block<'a, T>) -> T;
+    fn visit_export(&self, stmt: &Export<'a, T>) -> T;
+    fn visit_expression(&self, stmt: &Expression<'a, T>) -> T;
+    fn visit_for_in(&self, stmt: &ForIn<'a, T>) -> T;
+    fn visit_function(&self, stmt: &Function<'a, T>) -> T;
+    fn visit_if(&self, stmt: &If<'a, T>) -> T;
+    fn visit_import(&self, stmt: &Import<'a, T>) -> T;
+    fn visit_print(&self, stmt: &Print<'a, T>) -> T;
+    fn visit_return(&self, stmt: &Return<'a, T>) -> T;
+    fn visit_throw(&self, stmt: &Throw<'a, T>) -> T;
+    fn visit_try(&self, stmt: &Try<'a, T>) -> T;
+    fn visit_var(&self, stmt: &Var<'a, T>) -> T;
+    fn visit_while(&self, stmt: &While<'a, T>) -> T;
+}
+
+pub trait Stmt<'a, T: 'a> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T;
+}
+
+
+pub struct Block<'a, T: 'a> {
+    pub statements: Vec<StmtRef<'a, T>>,
+}
+
+impl<'a, T: 'a> Block<'a, T> {
+    pub fn new(statements: Vec<StmtRef<'a, T>>) -> StmtRef<'a, T> {
+        Rc::new(Block { statements })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Block<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_block(self)
+    }
+}
+
+
+/// `export` in front of a top-level `fun` or `var` declaring `name`.
+pub struct Export<'a, T: 'a> {
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    pub declaration: StmtRef<'a, T>,
+}
+
+impl<'a, T: 'a> Export<'a, T> {
+    pub fn new(name: Rc<Token>, declaration: StmtRef<'a, T>) -> StmtRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Export {
+            name,
+            symbol,
+            declaration,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Export<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_export(self)
+    }
+}
+
+
+pub struct Expression<'a, T: 'a> {
+    pub expression: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Expression<'a, T> {
+    pub fn new(expression: ExprRef<'a, T>) -> StmtRef<'a, T> {
+        Rc::new(Expression { expression })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Expression<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_expression(self)
+    }
+}
+
+
+/// `for (var name in iterable) body`, looping over the elements of a list
+/// or the keys of a map.
+pub struct ForIn<'a, T: 'a> {
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    pub keyword: Rc<Token>,
+    pub iterable: ExprRef<'a, T>,
+    pub body: StmtRef<'a, T>,
+}
+
+impl<'a, T: 'a> ForIn<'a, T> {
+    pub fn new(name: Rc<Token>, keyword: Rc<Token>, iterable: ExprRef<'a, T>, body: StmtRef<'a, T>) -> StmtRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(ForIn {
+            name,
+            symbol,
+            keyword,
+            iterable,
+            body,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for ForIn<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_for_in(self)
+    }
+}
+
+
+pub struct Function<'a, T: 'a> {
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    /// Shared with every function value created from this declaration.
+    pub params: Rc<Vec<Rc<Token>>>,
+    pub body: Rc<Vec<StmtRef<'a, T>>>,
+}
+
+impl<'a, T: 'a> Function<'a, T> {
+    pub fn new(name: Rc<Token>, params: Vec<Rc<Token>>, body: Vec<StmtRef<'a, T>>) -> StmtRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Function {
+            name,
+            symbol,
+            params: Rc::new(params),
+            body: Rc::new(body),
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Function<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_function(self)
+    }
+}
+
+
+pub struct If<'a, T: 'a> {
+    pub condition: ExprRef<'a, T>,
+    pub then_branch: StmtRef<'a, T>,
+    pub else_branch: Option<StmtRef<'a, T>>,
+}
+
+impl<'a, T: 'a> If<'a, T> {
+    pub fn new(condition: ExprRef<'a, T>, then_branch: StmtRef<'a, T>, else_branch: Option<StmtRef<'a, T>>) -> StmtRef<'a, T> {
+        Rc::new(If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for If<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_if(self)
+    }
+}
+
+
+/// `import "path" as alias;` when `alias` is set, otherwise
+/// `from "path" import names;`.
+pub struct Import<'a, T: 'a> {
+    pub keyword: Rc<Token>,
+    pub path: Rc<Token>,
+    pub alias: Option<Rc<Token>>,
+    pub names: Vec<Rc<Token>>,
+    marker: PhantomData<&'a T>,
+}
+
+impl<'a, T: 'a> Import<'a, T> {
+    pub fn new(keyword: Rc<Token>, path: Rc<Token>, alias: Option<Rc<Token>>, names: Vec<Rc<Token>>) -> StmtRef<'a, T> {
+        Rc::new(Import {
+            keyword,
+            path,
+            alias,
+            names,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Import<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_import(self)
+    }
+}
+
+
+pub struct Print<'a, T: 'a> {
+    pub expression: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Print<'a, T> {
+    pub fn new(expression: ExprRef<'a, T>) -> StmtRef<'a, T> {
+        Rc::new(Print { expression })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Print<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_print(self)
+    }
+}
+
+
+pub struct Return<'a, T: 'a> {
+    pub keyword: Rc<Token>,
+    pub value: Option<ExprRef<'a, T>>,
+}
+
+impl<'a, T: 'a> Return<'a, T> {
+    pub fn new(keyword: Rc<Token>, value: Option<ExprRef<'a, T>>) -> StmtRef<'a, T> {
+        Rc::new(Return {
+            keyword,
+            value,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Return<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_return(self)
+    }
+}
+
+
+pub struct Throw<'a, T: 'a> {
+    pub keyword: Rc<Token>,
+    pub value: ExprRef<'a, T>,
+}
+
+impl<'a, T: 'a> Throw<'a, T> {
+    pub fn new(keyword: Rc<Token>, value: ExprRef<'a, T>) -> StmtRef<'a, T> {
+        Rc::new(Throw {
+            keyword,
+            value,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Throw<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_throw(self)
+    }
+}
+
+
+/// `try { body } catch (name) { handler } finally { finalizer }`, where
+/// either the `catch` or the `finally` clause may be left out.
+pub struct Try<'a, T: 'a> {
+    pub keyword: Rc<Token>,
+    pub body: Vec<StmtRef<'a, T>>,
+    /// The variable the caught value is bound to, and the statements handling it.
+    pub handler: Option<(Rc<Token>, Vec<StmtRef<'a, T>>)>,
+    pub finalizer: Option<Vec<StmtRef<'a, T>>>,
+}
+
+impl<'a, T: 'a> Try<'a, T> {
+    pub fn new(keyword: Rc<Token>, body: Vec<StmtRef<'a, T>>, handler: Option<(Rc<Token>, Vec<StmtRef<'a, T>>)>,
+               finalizer: Option<Vec<StmtRef<'a, T>>>) -> StmtRef<'a, T> {
+        Rc::new(Try {
+            keyword,
+            body,
+            handler,
+            finalizer,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Try<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_try(self)
+    }
+}
+
+
+pub struct Var<'a, T: 'a> {
+    pub name: Rc<Token>,
+    pub symbol: Symbol,
+    pub initializer: Option<ExprRef<'a, T>>,
+}
+
+impl<'a, T: 'a> Var<'a, T> {
+    pub fn new(name: Rc<Token>, initializer: Option<ExprRef<'a, T>>) -> StmtRef<'a, T> {
+        let symbol = intern(&name.lexeme);
+        Rc::new(Var {
+            name,
+            symbol,
+            initializer,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for Var<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_var(self)
+    }
+}
+
+
+pub struct While<'a, T: 'a> {
+    pub condition: ExprRef<'a, T>,
+    pub body: StmtRef<'a, T>,
+}
+
+impl<'a, T: 'a> While<'a, T> {
+    pub fn new(condition: ExprRef<'a, T>, body: StmtRef<'a, T>) -> StmtRef<'a, T> {
+        Rc::new(While {
+            condition,
+            body,
+        })
+    }
+}
+
+impl<'a, T> Stmt<'a, T> for While<'a, T> {
+    fn accept(&self, visitor: &dyn StmtVisitor<'a, T>) -> T {
+        (*visitor).visit_while(self)
+    }
+}
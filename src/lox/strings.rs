@@ -0,0 +1,118 @@
+//! Methods of strings and numbers, as in `"lox".upper()` or `n.toFixed(2)`.
+//!
+//! Strings are indexed by Unicode scalar value, never by byte, so
+//! `"héllo".len()` is 5 and `"héllo".substring(1, 2)` is `"é"`.
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+use lox::value::*;
+
+/// The arity of string method `name`, or `None` if there is no such method.
+pub fn string_method_arity(name: &str) -> Option<usize> {
+    Some(match name {
+        "len" | "trim" | "upper" | "lower" | "chars" | "toNumber" => 0,
+        "indexOf" | "split" | "join" | "startsWith" | "endsWith" => 1,
+        "substring" | "replace" => 2,
+        _ => return None,
+    })
+}
+
+/// Calls a method `string_method_arity` knows with as many arguments as it takes.
+pub fn call_string_method(s: &str, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Result::Ok(match name {
+        "len" => Value::Number(s.chars().count() as f64),
+        "trim" => Value::from(s.trim()),
+        "upper" => Value::from(s.to_uppercase()),
+        "lower" => Value::from(s.to_lowercase()),
+        "chars" => Value::list(s.chars().map(|c| Value::from(c.to_string())).collect()),
+        "toNumber" => match s.trim().parse::<f64>() {
+            Ok(n) if !s.trim().is_empty() && is_lox_number(s.trim()) => Value::Number(n),
+            _ => Value::Nil,
+        },
+        "indexOf" => {
+            let needle = String::try_from(&arguments[0])?;
+            match s.find(&needle) {
+                Some(offset) => Value::Number(s[..offset].chars().count() as f64),
+                None => Value::Number(-1.0),
+            }
+        },
+        "split" => {
+            let separator = String::try_from(&arguments[0])?;
+            if separator.is_empty() {
+                return call_string_method(s, "chars", &[]);
+            }
+            Value::list(s.split(separator.as_str()).map(Value::from).collect())
+        },
+        "join" => {
+            let parts = Vec::<String>::try_from(&arguments[0])?;
+            Value::from(parts.join(s))
+        },
+        "startsWith" => Value::Bool(s.starts_with(String::try_from(&arguments[0])?.as_str())),
+        "endsWith" => Value::Bool(s.ends_with(String::try_from(&arguments[0])?.as_str())),
+        "substring" => {
+            let length = s.chars().count();
+            let start = index(&arguments[0], length)?;
+            let end = index(&arguments[1], length)?;
+            if start > end {
+                return Result::Err(RuntimeError::new(format!("Substring start {} is after its end {}.", start, end)));
+            }
+            Value::from(s.chars().skip(start).take(end - start).collect::<String>())
+        },
+        "replace" => {
+            let from = String::try_from(&arguments[0])?;
+            let to = String::try_from(&arguments[1])?;
+            if from.is_empty() {
+                return Result::Err(RuntimeError::new("Can't replace an empty string."));
+            }
+            Value::Str(Rc::new(s.replace(from.as_str(), &to)))
+        },
+        _ => unreachable!("unknown string method '{}'", name),
+    })
+}
+
+/// The arity of number method `name`, or `None` if there is no such method.
+pub fn number_method_arity(name: &str) -> Option<usize> {
+    match name {
+        "toString" => Some(0),
+        "toFixed" => Some(1),
+        _ => None,
+    }
+}
+
+pub fn call_number_method(n: f64, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Result::Ok(match name {
+        "toString" => Value::from(Value::Number(n).to_string()),
+        "toFixed" => {
+            let digits = f64::try_from(&arguments[0])?;
+            if digits.fract() != 0.0 || !(0.0..=100.0).contains(&digits) {
+                return Result::Err(RuntimeError::new(format!("Can't show {} digits, expected a whole number from 0 to 100.", digits)));
+            }
+            Value::from(format!("{:.*}", digits as usize, n))
+        },
+        _ => unreachable!("unknown number method '{}'", name),
+    })
+}
+
+/// Only accepts what the scanner would read as a number, possibly negated,
+/// so that `"inf"` or `"1e5"` do not parse.
+fn is_lox_number(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next();
+
+    !whole.is_empty() && whole.bytes().all(|b| b.is_ascii_digit())
+        && fraction.is_none_or(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Checks that `value` is a whole number in `0..=max`.
+fn index(value: &Value, max: usize) -> Result<usize, RuntimeError> {
+    let n = f64::try_from(value)?;
+    if n.fract() != 0.0 {
+        return Result::Err(RuntimeError::new(format!("Index {} is not an integer.", n)));
+    }
+    if n < 0.0 || n > max as f64 {
+        return Result::Err(RuntimeError::new(format!("Index {} is out of range 0..{}.", n, max)));
+    }
+    Result::Ok(n as usize)
+}
@@ -0,0 +1,244 @@
+//! Assertions for tests written in Lox, and the runner of `lox test`.
+//!
+//! A test file is a script named `*_test.lox`; every function it declares
+//! at the top level with a name starting with `test` is a test. Each test
+//! runs on its own interpreter that has just run the file, so tests can't
+//! see what other tests changed. A test fails when it raises an error,
+//! usually through one of the assertions.
+//!
+//! The tests are found by running the file once and looking at the
+//! functions it defined, so the top level of a file with N tests runs
+//! N + 1 times. Whatever it does beyond declaring, such as printing or
+//! writing files, happens that often too.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use lox::interpreter::Interpreter;
+use lox::value::*;
+use lox::Lox;
+
+pub const NATIVES: [(&str, usize, NativeFn); 2] = [
+    ("assert", 2, assert),
+    ("assertEqual", 2, assert_equal),
+];
+
+/// The assertions that call back into Lox, which natives can't.
+pub const PRELUDE: &str = "
+fun assertThrows(f) {
+    try {
+        f();
+    } catch (e) {
+        return e;
+    }
+    throw \"Expected an error but none was thrown.\";
+}
+";
+
+/// `assert(condition, message)`
+fn assert(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    if Interpreter::is_truthy(&arguments[0]) {
+        return Result::Ok(Value::Nil);
+    }
+    Result::Err(RuntimeError::new(format!("Assertion failed: {}", arguments[1])))
+}
+
+/// `assertEqual(actual, expected)`, comparing lists and maps by their contents.
+fn assert_equal(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    if equal(&arguments[0], &arguments[1], &mut Vec::new()) {
+        return Result::Ok(Value::Nil);
+    }
+    Result::Err(RuntimeError::new(format!("Expected {:?} but got {:?}.", arguments[1], arguments[0])))
+}
+
+/// Whether `a` and `b` have the same contents. `comparing` holds the
+/// addresses of the lists and maps being compared further up, which are
+/// taken to be equal when they come up again inside themselves.
+fn equal(a: &Value, b: &Value, comparing: &mut Vec<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) => {
+            let addresses = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+            if Rc::ptr_eq(a, b) || comparing.contains(&addresses) {
+                return true;
+            }
+            comparing.push(addresses);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal = a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b, comparing));
+            comparing.pop();
+            equal
+        },
+        (Value::Map(a), Value::Map(b)) => {
+            let addresses = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+            if Rc::ptr_eq(a, b) || comparing.contains(&addresses) {
+                return true;
+            }
+            comparing.push(addresses);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal = a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| equal(a, b, comparing)));
+            comparing.pop();
+            equal
+        },
+        (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+        (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+        (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+/// Why a test failed.
+pub struct Failure {
+    pub message: String,
+    /// The line of the test file the test failed on.
+    pub line: Option<usize>,
+}
+
+pub struct TestResult {
+    /// The test file, relative to the directory the tests were found in.
+    pub file: String,
+    /// The test function, or `script` when the file itself failed to run.
+    pub name: String,
+    pub failure: Option<Failure>,
+    pub duration: Duration,
+}
+
+/// The test files under `dir`, sorted, or why a directory couldn't be read.
+pub fn discover(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect(dir, &mut files)?;
+    files.sort();
+    Result::Ok(files)
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path.file_name().is_some_and(|name| name.to_string_lossy().ends_with("_test.lox")) {
+            files.push(path);
+        }
+    }
+    Result::Ok(())
+}
+
+/// Runs every test of the file at `path`, on interpreters made by `new_lox`:
+/// one that runs the file to find the tests, then one for every test.
+/// `file` is how the results name the file.
+pub fn run_file<F: Fn() -> Lox>(path: &Path, file: &str, new_lox: &F) -> Vec<TestResult> {
+    let script = path.to_string_lossy();
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+    let result = |name: &str, failure, started: Instant| TestResult {
+        file: file.to_string(),
+        name: name.to_string(),
+        failure,
+        duration: started.elapsed(),
+    };
+
+    let started = Instant::now();
+    let mut lox = new_lox();
+    if let Err(e) = lox.load_file(&script) {
+        return vec![result("script", Some(Failure { message: e.to_string(), line: None }), started)];
+    }
+    let names: Vec<String> = lox.global_names().into_iter()
+        .filter(|name| name.starts_with("test") && matches!(lox.get_global(name), Some(Value::Function(_))))
+        .collect();
+
+    names.iter().map(|name| {
+        let started = Instant::now();
+        let mut lox = new_lox();
+        let outcome = lox.load_file(&script).map_err(|e| RuntimeError::new(e.to_string()))
+            .and_then(|_| lox.call(&lox.get_global(name).unwrap(), &[]));
+        let failure = outcome.err().map(|e| {
+            // The innermost line of the test file, which may be in a helper.
+            let line = e.trace.iter()
+                .find(|frame| frame.file.is_some() && frame.file == file_name)
+                .map(|frame| frame.line)
+                .or(e.line);
+            Failure { message: e.message, line }
+        });
+        result(name, failure, started)
+    }).collect()
+}
+
+/// The results as a JUnit XML report, with a test suite for every file.
+pub fn junit(results: &[TestResult]) -> String {
+    let failed = results.iter().filter(|result| result.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<testsuites tests=\"{}\" failures=\"{}\">\n", results.len(), failed));
+
+    let mut start = 0;
+    while start < results.len() {
+        let file = &results[start].file;
+        let end = start + results[start..].iter().take_while(|result| result.file == *file).count();
+        let suite = &results[start..end];
+        let failures = suite.iter().filter(|result| result.failure.is_some()).count();
+        let time: Duration = suite.iter().map(|result| result.duration).sum();
+        xml.push_str(&format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+                              xml_escape(file), suite.len(), failures, time.as_secs_f64()));
+
+        for result in suite {
+            let case = format!("    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                               xml_escape(&result.name), xml_escape(file), result.duration.as_secs_f64());
+            match result.failure {
+                None => xml.push_str(&format!("{}/>\n", case)),
+                Some(ref failure) => {
+                    let location = match failure.line {
+                        Some(line) => format!("{}:{}", file, line),
+                        None => file.to_string(),
+                    };
+                    xml.push_str(&format!("{}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                                          case, xml_escape(&failure.message), xml_escape(&location)));
+                },
+            }
+        }
+
+        xml.push_str("  </testsuite>\n");
+        start = end;
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// The results as a JSON report.
+pub fn json(results: &[TestResult]) -> String {
+    let failed = results.iter().filter(|result| result.failure.is_some()).count();
+    let tests: Vec<String> = results.iter().map(|result| {
+        let failure = match result.failure {
+            None => String::new(),
+            Some(ref failure) => format!(", \"message\": {}, \"line\": {}", json_string(&failure.message),
+                                         failure.line.map_or(String::from("null"), |line| line.to_string())),
+        };
+        format!("    {{\"file\": {}, \"name\": {}, \"passed\": {}, \"seconds\": {:.6}{}}}",
+                json_string(&result.file), json_string(&result.name), result.failure.is_none(),
+                result.duration.as_secs_f64(), failure)
+    }).collect();
+
+    format!("{{\n  \"passed\": {},\n  \"failed\": {},\n  \"tests\": [\n{}\n  ]\n}}\n",
+            results.len() - failed, failed, tests.join(",\n"))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
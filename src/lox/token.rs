@@ -7,21 +7,22 @@ use std::fmt::{
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {                                   
     // Single-character tokens.                      
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Colon, Comma, Dot, Minus, Percent, Plus, Semicolon, Slash, Star,
 
     // One or two character tokens.
     Bang, BangEqual,
     Equal, EqualEqual,
     Greater, GreaterEqual,
     Less, LessEqual,
+    StarStar,
 
     // Literals.
     Identifier, Str, Number,
 
     // Keywords.
-    And, Class, Else, False, Fun, For, If, Nil, Or,
-    Print, Return, Super, This, True, Var, While,
+    And, As, Catch, Class, Else, Export, False, Finally, From, Fun, For, If, Import,
+    In, Nil, Or, Print, Return, Super, This, Throw, True, Try, Var, While,
 
     Eof
 }
@@ -29,30 +30,33 @@ pub enum TokenType {
 #[derive(Debug, Clone)]
 pub enum TokenLiteral {
     Number(f64),
-    Str(String),
+    /// Strings are shared; only constants are interned, so equal strings
+    /// built at run time may live in different allocations.
+    Str(Rc<String>),
     Bool(bool),
     None,
 }
 
-pub struct Token<'a> {
+/// A token owns its lexeme, so trees built from it don't borrow the source.
+pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: &'a str,
+    pub lexeme: Rc<str>,
     pub literal: TokenLiteral,
     pub line: usize,
 }
 
-impl<'a> Token<'a> {
-    pub fn new(token_type: TokenType, lexeme: &'a str, literal: TokenLiteral, line: usize) -> Rc<Token<'a>> {
+impl Token {
+    pub fn new(token_type: TokenType, lexeme: &str, literal: TokenLiteral, line: usize) -> Rc<Token> {
         Rc::new(Token {
             token_type,
-            lexeme,
+            lexeme: Rc::from(lexeme),
             literal,
             line,
         })
     }
 }
 
-impl<'a> Display for Token<'a> {
+impl Display for Token {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{:?} {} {:?}, line: {}", self.token_type, self.lexeme, self.literal, self.line)
     }
@@ -0,0 +1,539 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use lox::cycles;
+use lox::interner::Symbol;
+use lox::interpreter::LoxFunction;
+use lox::limits::Limit;
+use lox::token::TokenLiteral;
+
+/// A runtime error, or a value thrown by a `throw` statement. Errors raised
+/// by native code have no line; the interpreter attaches the line of the
+/// call that failed.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: Option<usize>,
+    /// What a `throw` statement threw; `None` for errors of the interpreter.
+    pub value: Option<Value>,
+    /// The calls that were running, innermost first. Empty until the error
+    /// leaves the function it was raised in or reaches the top level.
+    pub trace: Vec<Frame>,
+    /// The limit the script went over, for errors that stopped it.
+    pub limit: Option<Limit>,
+}
+
+impl RuntimeError {
+    pub fn new<S: Into<String>>(message: S) -> RuntimeError {
+        RuntimeError { message: message.into(), line: None, value: None, trace: Vec::new(), limit: None }
+    }
+
+    pub fn at<S: Into<String>>(message: S, line: usize) -> RuntimeError {
+        RuntimeError { message: message.into(), line: Some(line), value: None, trace: Vec::new(), limit: None }
+    }
+}
+
+/// Errors are equal when they report the same message on the same line.
+impl PartialEq for RuntimeError {
+    fn eq(&self, other: &RuntimeError) -> bool {
+        self.message == other.message && self.line == other.line
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.trace.is_empty() {
+            write!(f, "{}", self.message)?;
+            // Runaway recursion repeats one frame up to the call depth limit.
+            let mut i = 0;
+            while i < self.trace.len() {
+                let frame = &self.trace[i];
+                let repeats = self.trace[i..].iter().take_while(|other| *other == frame).count();
+                write!(f, "\n{}", frame)?;
+                if repeats > 2 {
+                    write!(f, "\n[... {} more times]", repeats - 1)?;
+                    i += repeats;
+                } else {
+                    i += 1;
+                }
+            }
+            return Result::Ok(());
+        }
+
+        match self.line {
+            Some(line) => write!(f, "{}\n[line {}]", self.message, line),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// A call that was running when an error was raised, and the line it was at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The name of the function, or `None` for the top level of a file.
+    pub function: Option<String>,
+    /// Whether the function is implemented in Rust.
+    pub native: bool,
+    /// The file the code is in, if it came from a file.
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.function {
+            Some(ref function) => write!(f, "[line {}] in {}()", self.line, function)?,
+            None => write!(f, "[line {}] in script", self.line)?,
+        }
+
+        match self.file {
+            _ if self.native => write!(f, " (native)"),
+            Some(ref file) => write!(f, " ({})", file),
+            None => Result::Ok(()),
+        }
+    }
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust and callable from Lox.
+pub struct NativeFunction {
+    pub name: Symbol,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+/// A Rust value exposed to scripts as an object with properties and methods.
+///
+/// Scripts read properties with `object.name`, assign them with
+/// `object.name = value` and call methods with `object.name(arguments)`.
+pub trait NativeObject {
+    /// Used when the object is printed, as in `<Point instance>`.
+    fn class_name(&self) -> &str;
+
+    /// The value of property `name`, or `None` if there is no such property.
+    fn get(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    fn set(&mut self, name: &str, _value: Value) -> Result<(), RuntimeError> {
+        Result::Err(RuntimeError::new(format!("Can't set property '{}' on {}.", name, self.class_name())))
+    }
+
+    /// The number of arguments method `name` takes, or `None` if there is no such method.
+    fn method_arity(&self, _name: &str) -> Option<usize> {
+        None
+    }
+
+    /// Only called for methods `method_arity` knows, with that many arguments.
+    fn call_method(&mut self, name: &str, _arguments: &[Value]) -> Result<Value, RuntimeError> {
+        Result::Err(RuntimeError::new(format!("Undefined property '{}'.", name)))
+    }
+}
+
+/// A built-in method together with the value it was read from: a native
+/// object, a string or a number.
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub name: Symbol,
+    pub arity: usize,
+}
+
+/// A Lox value as seen by the interpreter and by native functions.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<String>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<Key, Value>>>),
+    Native(Rc<NativeFunction>),
+    Function(Rc<LoxFunction>),
+    Object(Rc<RefCell<dyn NativeObject>>),
+    Method(Rc<BoundMethod>),
+}
+
+impl Value {
+    /// Wraps a Rust value so scripts can use it as an object.
+    pub fn object<O: NativeObject + 'static>(object: O) -> Value {
+        Value::Object(Rc::new(RefCell::new(object)))
+    }
+
+    pub fn list(values: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(values));
+        cycles::track_list(&list);
+        Value::List(list)
+    }
+
+    pub fn map(entries: HashMap<Key, Value>) -> Value {
+        let map = Rc::new(RefCell::new(entries));
+        cycles::track_map(&map);
+        Value::Map(map)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Native(_) | Value::Function(_) | Value::Method(_) => "function",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// The type of the value and, unless it is long, the value itself, as
+    /// error messages show them: `number 1`, `string "a"`, `nil`.
+    pub fn describe(&self) -> String {
+        if let Value::Nil = *self {
+            return String::from("nil");
+        }
+
+        // Formatting stops as soon as the value is too long, so describing a
+        // huge list doesn't format all of it.
+        let mut value = Bounded { text: String::new(), chars: 0, max_chars: 20 };
+        match fmt::write(&mut value, format_args!("{:?}", self)) {
+            Result::Ok(()) => format!("{} {}", self.type_name(), value.text),
+            Result::Err(_) => String::from(self.type_name()),
+        }
+    }
+
+    /// What `==` means: numbers, strings, booleans and nil are equal to the
+    /// same values, everything else only to itself.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+            (Value::Method(a), Value::Method(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// The constant this value can be stored as, if any.
+    pub fn to_literal(&self) -> Option<TokenLiteral> {
+        match *self {
+            Value::Nil => Some(TokenLiteral::None),
+            Value::Bool(b) => Some(TokenLiteral::Bool(b)),
+            Value::Number(n) => Some(TokenLiteral::Number(n)),
+            Value::Str(ref s) => Some(TokenLiteral::Str(Rc::clone(s))),
+            _ => None,
+        }
+    }
+}
+
+impl From<TokenLiteral> for Value {
+    fn from(literal: TokenLiteral) -> Value {
+        match literal {
+            TokenLiteral::None => Value::Nil,
+            TokenLiteral::Bool(b) => Value::Bool(b),
+            TokenLiteral::Number(n) => Value::Number(n),
+            TokenLiteral::Str(s) => Value::Str(s),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(ref s) => write!(f, "{}", s),
+            Value::List(ref list) => unless_displaying(Rc::as_ptr(list) as usize, f, "[...]", |f| {
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}", value)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(ref map) => unless_displaying(Rc::as_ptr(map) as usize, f, "{...}", |f| {
+                // Sorted, so printing a map gives the same text every time.
+                let map = map.borrow();
+                let mut keys: Vec<&Key> = map.keys().collect();
+                keys.sort();
+
+                write!(f, "{{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {:?}", key.to_value(), map[key])?;
+                }
+                write!(f, "}}")
+            }),
+            Value::Native(_) => write!(f, "<native fn>"),
+            Value::Function(ref function) => write!(f, "<fn {}>", function.name),
+            Value::Object(ref object) => write!(f, "<{} instance>", object.borrow().class_name()),
+            Value::Method(ref method) => write!(f, "<native fn {}>", method.name),
+        }
+    }
+}
+
+/// Text written up to a number of characters; writing more fails.
+struct Bounded {
+    text: String,
+    chars: usize,
+    max_chars: usize,
+}
+
+impl fmt::Write for Bounded {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.chars += s.chars().count();
+        if self.chars > self.max_chars {
+            return Result::Err(fmt::Error);
+        }
+        self.text.push_str(s);
+        Result::Ok(())
+    }
+}
+
+thread_local! {
+    /// The lists and maps being displayed, innermost last.
+    static DISPLAYING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Displays the list or map at `address` with `display`, or as
+/// `placeholder` if it contains itself and is already being displayed.
+fn unless_displaying<F>(address: usize, f: &mut fmt::Formatter, placeholder: &str, display: F) -> fmt::Result
+where F: FnOnce(&mut fmt::Formatter) -> fmt::Result {
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&address)) {
+        return write!(f, "{}", placeholder);
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(address));
+    let result = display(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Str(ref s) => write!(f, "{:?}", s),
+            Value::Native(ref native) => write!(f, "<native fn {}>", native.name),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+/// A value used as a map key. Only values compared by contents can be
+/// keys, and `-0.0` is the same key as `0.0`.
+#[derive(Clone)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<String>),
+}
+
+impl Key {
+    pub fn from_value(value: &Value) -> Result<Key, RuntimeError> {
+        match *value {
+            Value::Nil => Result::Ok(Key::Nil),
+            Value::Bool(b) => Result::Ok(Key::Bool(b)),
+            Value::Number(n) if n.is_nan() => Result::Err(RuntimeError::new("NaN can't be used as a map key.")),
+            Value::Number(n) => Result::Ok(Key::Number(if n == 0.0 { 0.0 } else { n })),
+            Value::Str(ref s) => Result::Ok(Key::Str(Rc::clone(s))),
+            ref value => Result::Err(RuntimeError::new(format!("A {} can't be used as a map key.", value.type_name()))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Key::Nil => Value::Nil,
+            Key::Bool(b) => Value::Bool(b),
+            Key::Number(n) => Value::Number(n),
+            Key::Str(ref s) => Value::Str(Rc::clone(s)),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match *self {
+            Key::Nil => 0,
+            Key::Bool(_) => 1,
+            Key::Number(_) => 2,
+            Key::Str(_) => 3,
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match *self {
+            Key::Nil => {},
+            Key::Bool(b) => b.hash(state),
+            Key::Number(n) => n.to_bits().hash(state),
+            Key::Str(ref s) => s.hash(state),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Keys of different types are ordered nil, booleans, numbers, strings.
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (&Key::Bool(a), &Key::Bool(b)) => a.cmp(&b),
+            // Keys are never NaN, so numbers are totally ordered.
+            (&Key::Number(a), &Key::Number(b)) => a.partial_cmp(&b).unwrap(),
+            (Key::Str(a), Key::Str(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(Rc::new(s))
+    }
+}
+
+impl<'s> From<&'s str> for Value {
+    fn from(s: &'s str) -> Value {
+        Value::Str(Rc::new(String::from(s)))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Value {
+        match option {
+            Some(value) => value.into(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::list(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(entries: HashMap<String, T>) -> Value {
+        Value::map(entries.into_iter().map(|(k, v)| (Key::Str(Rc::new(k)), v.into())).collect())
+    }
+}
+
+fn expected(type_name: &str, value: &Value) -> RuntimeError {
+    RuntimeError::new(format!("Expected a {} but got {}.", type_name, value.type_name()))
+}
+
+impl TryFrom<&Value> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<f64, RuntimeError> {
+        match *value {
+            Value::Number(n) => Result::Ok(n),
+            ref value => Result::Err(expected("number", value)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<bool, RuntimeError> {
+        match *value {
+            Value::Bool(b) => Result::Ok(b),
+            ref value => Result::Err(expected("bool", value)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<String, RuntimeError> {
+        match *value {
+            Value::Str(ref s) => Result::Ok(String::clone(s)),
+            ref value => Result::Err(expected("string", value)),
+        }
+    }
+}
+
+/// `nil` converts to `None`.
+impl<'v, T> TryFrom<&'v Value> for Option<T>
+where T: TryFrom<&'v Value, Error = RuntimeError> {
+    type Error = RuntimeError;
+
+    fn try_from(value: &'v Value) -> Result<Option<T>, RuntimeError> {
+        match *value {
+            Value::Nil => Result::Ok(None),
+            ref value => T::try_from(value).map(Some),
+        }
+    }
+}
+
+impl<'v, T> TryFrom<&'v Value> for Vec<T>
+where T: for<'e> TryFrom<&'e Value, Error = RuntimeError> {
+    type Error = RuntimeError;
+
+    fn try_from(value: &'v Value) -> Result<Vec<T>, RuntimeError> {
+        match *value {
+            Value::List(ref list) => list.borrow().iter().map(T::try_from).collect(),
+            ref value => Result::Err(expected("list", value)),
+        }
+    }
+}
+
+/// Only maps whose keys are all strings convert.
+impl<'v, T> TryFrom<&'v Value> for HashMap<String, T>
+where T: for<'e> TryFrom<&'e Value, Error = RuntimeError> {
+    type Error = RuntimeError;
+
+    fn try_from(value: &'v Value) -> Result<HashMap<String, T>, RuntimeError> {
+        match *value {
+            Value::Map(ref map) => map.borrow().iter().map(|(k, v)| {
+                match *k {
+                    Key::Str(ref s) => Result::Ok((String::clone(s), T::try_from(v)?)),
+                    ref key => Result::Err(expected("string key", &key.to_value())),
+                }
+            }).collect(),
+            ref value => Result::Err(expected("map", value)),
+        }
+    }
+}
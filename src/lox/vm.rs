@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use lox::token::*;
+use lox::chunk::*;
+use lox::interpreter::Interpreter;
+use lox::interner::*;
+use lox::value::*;
+use lox::debug;
+use lox::memory::*;
+use lox::limits::Budget;
+
+type VMRes = Result<Value, (usize, String)>;
+
+/// Stack-based virtual machine executing the bytecode produced by `Compiler`.
+#[derive(Default)]
+pub struct VM {
+    stack: Vec<VmValue>,
+    /// The chunk's constants, with strings moved into the heap.
+    constants: Vec<VmValue>,
+    /// The symbol of every string constant, used to look up globals by name.
+    names: Vec<Option<Symbol>>,
+    globals: HashMap<Symbol, VmValue>,
+    heap: Heap,
+    trace: bool,
+    budget: Budget,
+}
+
+impl VM {
+    pub fn new() -> VM {
+        VM {
+            stack: Vec::new(),
+            constants: Vec::new(),
+            names: Vec::new(),
+            globals: HashMap::new(),
+            heap: Heap::default(),
+            trace: false,
+            budget: Budget::default(),
+        }
+    }
+
+    /// Replaces the heap, so this has to happen before any global is defined.
+    pub fn set_gc(&mut self, config: GcConfig) {
+        self.heap = Heap::new(config);
+    }
+
+    /// Prints the stack and every instruction to stderr before executing it.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Sets the limits of the chunk run next. A step is an instruction.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    pub fn define_global(&mut self, name: Symbol, value: Value) {
+        let value = self.load_value(value);
+        self.globals.insert(name, value);
+    }
+
+    /// Runs a chunk, returning the value of its expression, or `None` once
+    /// `err` has been told why it failed.
+    pub fn interpret<F>(&mut self, chunk: &Chunk, mut err: F) -> Option<Value>
+    where F: FnMut(usize, &str) {
+        self.budget.start();
+        self.stack.clear();
+        self.constants.clear();
+        self.names.clear();
+        for constant in &chunk.constants {
+            let name = match *constant {
+                TokenLiteral::Str(ref s) => Some(intern(s)),
+                _ => None,
+            };
+            let value = self.load_value(Value::from(constant.clone()));
+            self.constants.push(value);
+            self.names.push(name);
+        }
+
+        match self.run(chunk) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                err(e.0, &e.1);
+                None
+            },
+        }
+    }
+
+    fn run(&mut self, chunk: &Chunk) -> VMRes {
+        let mut ip = 0;
+
+        loop {
+            let offset = ip;
+            if self.trace {
+                let stack: Vec<Value> = self.stack.iter().map(|&v| self.to_value(v)).collect();
+                eprintln!("{}", debug::stack(&stack));
+                eprintln!("{}", debug::disassemble_instruction(chunk, offset).0);
+            }
+
+            if let Err(e) = self.budget.step() {
+                return Result::Err((chunk.get_line(offset), e.message));
+            }
+            if self.budget.should_measure() {
+                if let Err(message) = self.measure_heap() {
+                    return Result::Err((chunk.get_line(offset), message));
+                }
+            }
+
+            let instruction = chunk.code[ip];
+            ip += 1;
+
+            let op = match OpCode::from_byte(instruction) {
+                Some(op) => op,
+                None => return Result::Err((chunk.get_line(offset), String::from("Unknown opcode."))),
+            };
+
+            let result = match op {
+                OpCode::Constant => {
+                    let constant = chunk.code[ip];
+                    ip += 1;
+                    let value = self.constants[constant as usize];
+                    self.push(value)
+                },
+                OpCode::Nil => self.push(VmValue::Nil),
+                OpCode::True => self.push(VmValue::Bool(true)),
+                OpCode::False => self.push(VmValue::Bool(false)),
+                OpCode::Equal => self.binary(&TokenType::EqualEqual),
+                OpCode::NotEqual => self.binary(&TokenType::BangEqual),
+                OpCode::Greater => self.binary(&TokenType::Greater),
+                OpCode::GreaterEqual => self.binary(&TokenType::GreaterEqual),
+                OpCode::Less => self.binary(&TokenType::Less),
+                OpCode::LessEqual => self.binary(&TokenType::LessEqual),
+                OpCode::Add => self.binary(&TokenType::Plus),
+                OpCode::Subtract => self.binary(&TokenType::Minus),
+                OpCode::Multiply => self.binary(&TokenType::Star),
+                OpCode::Divide => self.binary(&TokenType::Slash),
+                OpCode::Modulo => self.binary(&TokenType::Percent),
+                OpCode::Power => self.binary(&TokenType::StarStar),
+                OpCode::Not => self.unary(&TokenType::Bang),
+                OpCode::Negate => self.unary(&TokenType::Minus),
+                OpCode::Return => {
+                    let value = self.pop();
+                    return Result::Ok(self.to_value(value));
+                },
+                OpCode::GetGlobal => {
+                    let constant = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.get_global(constant)
+                },
+                OpCode::Call => {
+                    let arg_count = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.call(arg_count)
+                },
+                OpCode::BuildList => {
+                    let count = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.build_list(count)
+                },
+                OpCode::Index => self.index(),
+                OpCode::SetIndex => self.set_index(),
+                OpCode::BuildMap => {
+                    let count = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.build_map(count)
+                },
+                OpCode::GetProperty => {
+                    let constant = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.get_property(constant)
+                },
+                OpCode::SetProperty => {
+                    let constant = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.set_property(constant)
+                },
+                OpCode::Pop => {
+                    self.pop();
+                    Result::Ok(())
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    println!("{}", self.to_value(value));
+                    Result::Ok(())
+                },
+                OpCode::DefineGlobal => {
+                    let constant = chunk.code[ip] as usize;
+                    ip += 1;
+                    let name = self.names[constant].clone().expect("global name is a string constant");
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                    Result::Ok(())
+                },
+                OpCode::SetGlobal => {
+                    let constant = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.set_global(constant)
+                },
+                OpCode::GetLocal => {
+                    let slot = chunk.code[ip] as usize;
+                    ip += 1;
+                    let value = self.stack[slot];
+                    self.push(value)
+                },
+                OpCode::SetLocal => {
+                    let slot = chunk.code[ip] as usize;
+                    ip += 1;
+                    self.stack[slot] = self.peek();
+                    Result::Ok(())
+                },
+                OpCode::Jump => {
+                    ip += 2 + chunk.jump_distance(offset).unwrap();
+                    Result::Ok(())
+                },
+                OpCode::JumpIfFalse => {
+                    ip += 2;
+                    if VM::is_falsey(self.peek()) {
+                        ip += chunk.jump_distance(offset).unwrap();
+                    }
+                    Result::Ok(())
+                },
+                OpCode::Loop => {
+                    ip = ip + 2 - chunk.jump_distance(offset).unwrap();
+                    Result::Ok(())
+                },
+            };
+
+            let result = result.and_then(|_| match op {
+                OpCode::Add | OpCode::Call => self.check_limits(),
+                _ => Result::Ok(()),
+            });
+            if let Err(message) = result {
+                return Result::Err((chunk.get_line(offset), message));
+            }
+        }
+    }
+
+    /// Checks the string an instruction pushed.
+    fn check_limits(&mut self) -> Result<(), String> {
+        if let Some(&value) = self.stack.last() {
+            self.budget.check_string(&self.to_value(value)).map_err(|e| e.message)?;
+        }
+        Result::Ok(())
+    }
+
+    /// Checks the size of what is alive: the heap after a collection, and
+    /// the lists, maps and other host values on it with what they hold.
+    fn measure_heap(&mut self) -> Result<(), String> {
+        self.heap.collect(self.stack.iter().chain(self.constants.iter()).chain(self.globals.values()));
+        let (host_bytes, host_objects) = Interpreter::measure_values(Vec::new(), self.heap.host_values());
+        let bytes = self.heap.bytes_allocated() + host_bytes;
+        self.budget.check_heap(bytes, self.heap.object_count() + host_objects).map_err(|e| e.message)
+    }
+
+    fn push(&mut self, value: VmValue) -> Result<(), String> {
+        self.stack.push(value);
+        Result::Ok(())
+    }
+
+    fn pop(&mut self) -> VmValue {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self) -> VmValue {
+        *self.stack.last().unwrap()
+    }
+
+    fn is_falsey(value: VmValue) -> bool {
+        matches!(value, VmValue::Nil | VmValue::Bool(false))
+    }
+
+    fn binary(&mut self, operator: &TokenType) -> Result<(), String> {
+        let right = self.pop();
+        let left = self.pop();
+        let value = Interpreter::binary_op(operator, self.to_value(left), self.to_value(right))?;
+        let value = self.load_value(value);
+        self.push(value)
+    }
+
+    fn unary(&mut self, operator: &TokenType) -> Result<(), String> {
+        let right = self.pop();
+        let value = Interpreter::unary_op(operator, self.to_value(right))?;
+        let value = self.load_value(value);
+        self.push(value)
+    }
+
+    fn get_global(&mut self, constant: usize) -> Result<(), String> {
+        let name = self.names[constant].as_ref().expect("global name is a string constant");
+        match self.globals.get(name) {
+            Some(&value) => self.push(value),
+            None => Result::Err(format!("Undefined variable '{}'.", name)),
+        }
+    }
+
+    /// Assigns the value on top of the stack to an existing global.
+    fn set_global(&mut self, constant: usize) -> Result<(), String> {
+        let name = self.names[constant].as_ref().expect("global name is a string constant");
+        let value = self.peek();
+        match self.globals.get_mut(name) {
+            Some(global) => {
+                *global = value;
+                Result::Ok(())
+            },
+            None => Result::Err(format!("Undefined variable '{}'.", name)),
+        }
+    }
+
+    fn call(&mut self, arg_count: usize) -> Result<(), String> {
+        let callee = self.to_value(self.stack[self.stack.len() - 1 - arg_count]);
+        let first = self.stack.len() - arg_count;
+        let arguments: Vec<Value> = self.stack[first..].iter().map(|&v| self.to_value(v)).collect();
+        let result = VM::call_value(&callee, &arguments).map_err(|e| e.message)?;
+
+        self.stack.truncate(first - 1);
+        let result = self.load_value(result);
+        self.push(result)
+    }
+
+    /// Calls a native, or a method, which calls the natives it is given the
+    /// same way.
+    fn call_value(callee: &Value, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let arity = match *callee {
+            Value::Native(ref native) => native.arity,
+            Value::Method(ref method) => method.arity,
+            _ => return Result::Err(RuntimeError::new("Can only call functions and classes.")),
+        };
+
+        if arguments.len() != arity {
+            return Result::Err(RuntimeError::new(format!("Expected {} arguments but got {}.", arity, arguments.len())));
+        }
+
+        match *callee {
+            Value::Native(ref native) => (native.function)(arguments),
+            Value::Method(ref method) => Interpreter::call_method(method, arguments, VM::call_value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn build_list(&mut self, count: usize) -> Result<(), String> {
+        let first = self.stack.len() - count;
+        let elements: Vec<Value> = self.stack[first..].iter().map(|&v| self.to_value(v)).collect();
+        self.stack.truncate(first);
+
+        // Lists live on the host side, so their elements are plain values.
+        let list = self.load_value(Value::list(elements));
+        self.push(list)
+    }
+
+    fn build_map(&mut self, count: usize) -> Result<(), String> {
+        let first = self.stack.len() - 2 * count;
+        let mut entries = HashMap::new();
+        for pair in self.stack[first..].chunks(2) {
+            let key = Key::from_value(&self.to_value(pair[0])).map_err(|e| e.message)?;
+            entries.insert(key, self.to_value(pair[1]));
+        }
+        self.stack.truncate(first);
+
+        let map = self.load_value(Value::map(entries));
+        self.push(map)
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        let index = self.pop();
+        let object = self.pop();
+        let value = Interpreter::get_index(&self.to_value(object), &self.to_value(index)).map_err(|e| e.message)?;
+        let value = self.load_value(value);
+        self.push(value)
+    }
+
+    fn set_index(&mut self) -> Result<(), String> {
+        let value = self.pop();
+        let index = self.pop();
+        let object = self.pop();
+        Interpreter::set_index(&self.to_value(object), &self.to_value(index), self.to_value(value)).map_err(|e| e.message)?;
+        self.push(value)
+    }
+
+    fn get_property(&mut self, constant: usize) -> Result<(), String> {
+        let name = self.names[constant].clone().expect("property name is a string constant");
+        let object = self.pop();
+        let value = Interpreter::get_property(self.to_value(object), &name).map_err(|e| e.message)?;
+        let value = self.load_value(value);
+        self.push(value)
+    }
+
+    fn set_property(&mut self, constant: usize) -> Result<(), String> {
+        let name = self.names[constant].clone().expect("property name is a string constant");
+        let value = self.pop();
+        let object = self.pop();
+        Interpreter::set_property(&self.to_value(object), &name, self.to_value(value)).map_err(|e| e.message)?;
+        self.push(value)
+    }
+
+    fn to_value(&self, value: VmValue) -> Value {
+        match value {
+            VmValue::Nil => Value::Nil,
+            VmValue::Bool(b) => Value::Bool(b),
+            VmValue::Number(n) => Value::Number(n),
+            VmValue::Obj(obj) => match *self.heap.get(obj) {
+                Obj::Str(ref s) => Value::Str(Rc::clone(s)),
+                Obj::Native(ref native) => Value::Native(Rc::clone(native)),
+                Obj::Host(ref value) => value.clone(),
+            },
+        }
+    }
+
+    fn load_value(&mut self, value: Value) -> VmValue {
+        match value {
+            Value::Nil => VmValue::Nil,
+            Value::Bool(b) => VmValue::Bool(b),
+            Value::Number(n) => VmValue::Number(n),
+            Value::Str(s) => VmValue::Obj(self.allocate(Obj::Str(s))),
+            Value::Native(native) => VmValue::Obj(self.allocate(Obj::Native(native))),
+            value => VmValue::Obj(self.allocate(Obj::Host(value))),
+        }
+    }
+
+    fn allocate(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            // Roots: the value stack, with the locals, the constants and the globals.
+            self.heap.collect(self.stack.iter().chain(self.constants.iter()).chain(self.globals.values()));
+        }
+
+        self.heap.allocate(obj)
+    }
+}
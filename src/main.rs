@@ -1,18 +1,331 @@
 extern crate lox;
 
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
 use lox::Lox;
+use lox::lox::AstFormat;
+use lox::lox::Backend;
+use lox::lox::memory::GcConfig;
+use lox::lox::limits::Limits;
+use lox::lox::testing;
+use lox::lox::benchmarks;
+use lox::lox::LoadError;
 
 fn main() {
+    let interpreter = thread::spawn(run);
+    if interpreter.join().is_err() {
+        process::exit(70);
+    }
+}
+
+fn run() {
     let args: Vec<String> = env::args().collect();
     let mut lox = Lox::new();
 
-    match args.len() {
-        1 => lox.run_prompt(),
-        2 => lox.run_file(&args[1]),
+    if args.len() > 1 && args[1] == "ast" {
+        return ast(&mut lox, &args[2..]);
+    }
+
+    if args.len() > 1 && args[1] == "compile" {
+        return compile(&mut lox, &args[2..]);
+    }
+
+    if args.len() > 1 && args[1] == "test" {
+        return test(&args[2..]);
+    }
+
+    if args.len() > 1 && args[1] == "bench" {
+        return bench(&args[2..]);
+    }
+
+    let mut script = None;
+    let mut gc = GcConfig::default();
+    let mut limits = Limits::default();
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--backend=tree" => lox.set_backend(Backend::Tree),
+            "--backend=vm" => lox.set_backend(Backend::Vm),
+            "--disassemble" => lox.set_disassemble(true),
+            "--trace" => lox.set_trace(true),
+            "--no-io" => lox.set_io(false),
+            "--profile" => lox.set_profile_output("profile"),
+            arg if arg.starts_with("--profile=") => lox.set_profile_output(&arg["--profile=".len()..]),
+            arg if arg.starts_with("--module-path=") => lox.add_module_path(&arg["--module-path=".len()..]),
+            "--gc-stress" => gc.stress = true,
+            "--gc-log" => gc.log = true,
+            arg if arg.starts_with("--gc-threshold=") => gc.threshold = number_option(arg),
+            arg if arg.starts_with("--gc-growth=") => gc.growth_factor = number_option(arg),
+            arg if arg.starts_with("--max-steps=") => limits.max_steps = Some(number_option(arg) as u64),
+            arg if arg.starts_with("--max-depth=") => limits.max_call_depth = number_option(arg),
+            arg if arg.starts_with("--max-nesting=") => limits.max_nesting = number_option(arg),
+            arg if arg.starts_with("--max-heap=") => limits.max_heap_bytes = Some(number_option(arg)),
+            arg if arg.starts_with("--max-string=") => limits.max_string_length = Some(number_option(arg)),
+            arg if arg.starts_with("--timeout=") => limits.timeout = Some(Duration::from_millis(number_option(arg) as u64)),
+            arg if !arg.starts_with("--") && script.is_none() => script = Some(arg),
+            _ => usage(),
+        }
+    }
+    lox.set_gc(gc);
+    lox.set_limits(limits);
+
+    match script {
+        None => lox.run_prompt(),
+        Some(script) => lox.run_file(script),
+    }
+}
+
+fn usage() -> ! {
+    println!("Usage: lox [options] [script]");
+    println!("       lox compile <script> [-o <output>]");
+    println!("       lox ast [--format sexpr|dot|lox] [--lines] [--optimize] <script>");
+    println!("       lox test [--junit=FILE] [--json=FILE] [--module-path=DIR] <dir>");
+    println!("       lox bench [--backend=tree|vm] [--runs=N] [--save=FILE] [--baseline=FILE] [script...]");
+    println!();
+    println!("Options:");
+    println!("  --backend=tree|vm    execute with the tree-walking interpreter (default) or the bytecode VM");
+    println!("  --disassemble        print compiled bytecode before running it");
+    println!("  --trace              print the VM stack before every instruction");
+    println!("  --module-path=DIR    also look for imported modules in DIR");
+    println!("  --no-io              disable the file and standard stream natives and imports");
+    println!("  --profile[=NAME]     time the functions and sample the lines of the script, writing");
+    println!("                       NAME.txt and NAME.folded (default profile.txt and profile.folded)");
+    println!("  --gc-stress          collect garbage before every VM allocation");
+    println!("  --gc-log             report every garbage collection");
+    println!("  --gc-threshold=N     bytes allocated before the first collection");
+    println!("  --gc-growth=N        grow the collection threshold to N times the live heap");
+    println!("  --max-steps=N        stop scripts after N expressions or instructions");
+    println!("  --max-depth=N        allow N calls at once (default 1000)");
+    println!("  --max-nesting=N      allow N expressions and statements to be evaluated at once (default 10000)");
+    println!("  --max-heap=N         stop scripts holding more than N bytes of values");
+    println!("  --max-string=N       stop scripts building strings longer than N bytes");
+    println!("  --timeout=MS         stop scripts running longer than MS milliseconds");
+    std::process::exit(64);
+}
+
+fn number_option(arg: &str) -> usize {
+    match arg[arg.find('=').unwrap() + 1..].parse() {
+        Ok(n) if n > 0 => n,
+        _ => usage(),
+    }
+}
+
+fn compile(lox: &mut Lox, args: &[String]) {
+    let (script, output) = match args.len() {
+        1 => (&args[0], Path::new(&args[0]).with_extension("loxc")),
+        3 if args[1] == "-o" => (&args[0], PathBuf::from(&args[2])),
         _ => {
-            println!("Usage: lox [script]");
+            println!("Usage: lox compile <script> [-o <output>]");
             std::process::exit(64);
         },
+    };
+
+    lox.compile_file(script, &output.to_string_lossy());
+}
+
+fn ast(lox: &mut Lox, args: &[String]) {
+    let mut format = "sexpr";
+    let mut lines = false;
+    let mut optimize = false;
+    let mut script = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" if i + 1 < args.len() && ["sexpr", "dot", "lox"].contains(&args[i + 1].as_str()) => {
+                format = &args[i + 1];
+                i += 1;
+            },
+            "--lines" => lines = true,
+            "--optimize" => optimize = true,
+            arg if !arg.starts_with("--") && script.is_none() => script = Some(arg),
+            _ => ast_usage(),
+        }
+        i += 1;
+    }
+
+    let format = match format {
+        "dot" => AstFormat::Dot { lines },
+        "lox" => AstFormat::Source,
+        _ => AstFormat::SExpr,
+    };
+    match script {
+        Some(script) => lox.ast_file(script, &format, optimize),
+        None => ast_usage(),
+    }
+}
+
+fn ast_usage() -> ! {
+    println!("Usage: lox ast [--format sexpr|dot|lox] [--lines] [--optimize] <script>");
+    std::process::exit(64);
+}
+
+/// Runs the tests under a directory, printing a line for every test.
+fn test(args: &[String]) {
+    let mut dir = None;
+    let mut junit = None;
+    let mut json = None;
+    let mut module_paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            arg if arg.starts_with("--junit=") => junit = Some(&arg["--junit=".len()..]),
+            arg if arg.starts_with("--json=") => json = Some(&arg["--json=".len()..]),
+            arg if arg.starts_with("--module-path=") => module_paths.push(&arg["--module-path=".len()..]),
+            arg if !arg.starts_with("--") && dir.is_none() => dir = Some(arg),
+            _ => test_usage(),
+        }
+    }
+    let dir = Path::new(dir.unwrap_or_else(|| test_usage()));
+
+    let new_lox = || {
+        let mut lox = Lox::new();
+        for path in &module_paths {
+            lox.add_module_path(path);
+        }
+        lox
+    };
+
+    let files = testing::discover(dir).unwrap_or_else(|e| {
+        eprintln!("Can't read '{}': {}.", dir.display(), e);
+        std::process::exit(64);
+    });
+    if files.is_empty() {
+        eprintln!("No _test.lox files in '{}'.", dir.display());
+        std::process::exit(64);
+    }
+
+    let mut results = Vec::new();
+    for path in files {
+        let file = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().into_owned();
+        println!("{}", file);
+        for result in testing::run_file(&path, &file, &new_lox) {
+            match result.failure {
+                None => println!("  PASS {}", result.name),
+                Some(ref failure) => match failure.line {
+                    Some(line) => println!("  FAIL {} (line {}): {}", result.name, line, failure.message),
+                    None => println!("  FAIL {}: {}", result.name, failure.message),
+                },
+            }
+            results.push(result);
+        }
+    }
+
+    let failed = results.iter().filter(|result| result.failure.is_some()).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+
+    if let Some(path) = junit {
+        fs::write(path, testing::junit(&results)).unwrap();
+    }
+    if let Some(path) = json {
+        fs::write(path, testing::json(&results)).unwrap();
+    }
+
+    if failed > 0 {
+        std::process::exit(1);
     }
-}
\ No newline at end of file
+}
+
+fn test_usage() -> ! {
+    println!("Usage: lox test [--junit=FILE] [--json=FILE] [--module-path=DIR] <dir>");
+    std::process::exit(64);
+}
+
+fn bench(args: &[String]) {
+    let mut backends = vec![Backend::Tree, Backend::Vm];
+    let mut runs = 5;
+    let mut save = None;
+    let mut baseline = None;
+    let mut scripts = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--backend=tree" => backends = vec![Backend::Tree],
+            "--backend=vm" => backends = vec![Backend::Vm],
+            arg if arg.starts_with("--runs=") => runs = number_option(arg),
+            arg if arg.starts_with("--save=") => save = Some(&arg["--save=".len()..]),
+            arg if arg.starts_with("--baseline=") => baseline = Some(&arg["--baseline=".len()..]),
+            arg if !arg.starts_with("--") => scripts.push(arg),
+            _ => bench_usage(),
+        }
+    }
+
+    let programs: Vec<(String, String)> = if scripts.is_empty() {
+        benchmarks::PROGRAMS.iter().map(|&(name, source)| (String::from(name), String::from(source))).collect()
+    } else {
+        scripts.iter().map(|script| {
+            let name = Path::new(script).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| script.to_string());
+            let source = fs::read_to_string(script).unwrap_or_else(|e| {
+                eprintln!("Can't read '{}': {}.", script, e);
+                std::process::exit(66);
+            });
+            (name, source)
+        }).collect()
+    };
+
+    // The medians of an earlier `--save`, in nanoseconds.
+    let baseline: HashMap<(String, String), f64> = match baseline {
+        None => HashMap::new(),
+        Some(path) => fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Can't read '{}': {}.", path, e);
+            std::process::exit(66);
+        }).lines().filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [name, backend, median] => Some(((name.to_string(), backend.to_string()), median.parse().ok()?)),
+                _ => None,
+            }
+        }).collect(),
+    };
+
+    println!("{:<20} {:<8} {:>12} {:>12} {:>8}", "benchmark", "backend", "median", "min", "change");
+    let mut saved = String::new();
+    let mut failed = false;
+    for (name, source) in &programs {
+        for &backend in &backends {
+            let backend_name = match backend {
+                Backend::Tree => "tree",
+                Backend::Vm => "vm",
+            };
+            if !benchmarks::supports(backend, source) {
+                println!("{:<20} {:<8} {:>12}", name, backend_name, "unsupported");
+                continue;
+            }
+
+            match benchmarks::measure(name, source, backend, runs, &Lox::new) {
+                Ok(timing) => {
+                    let median = timing.median().as_nanos() as f64;
+                    let change = match baseline.get(&(name.clone(), backend_name.to_string())) {
+                        Some(&before) => format!("{:+.1}%", (median - before) / before * 100.0),
+                        None => String::new(),
+                    };
+                    println!("{:<20} {:<8} {:>12} {:>12} {:>8}", name, backend_name,
+                             format!("{:.2?}", timing.median()), format!("{:.2?}", timing.min()), change);
+                    saved += &format!("{} {} {}\n", name, backend_name, median);
+                },
+                Err(LoadError::Runtime(e)) => {
+                    println!("{:<20} {:<8} failed: {}", name, backend_name, e.message);
+                    failed = true;
+                },
+                Err(LoadError::Syntax) => {
+                    println!("{:<20} {:<8} failed to compile", name, backend_name);
+                    failed = true;
+                },
+            }
+        }
+    }
+
+    if let Some(path) = save {
+        fs::write(path, saved).unwrap();
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn bench_usage() -> ! {
+    println!("Usage: lox bench [--backend=tree|vm] [--runs=N] [--save=FILE] [--baseline=FILE] [script...]");
+    std::process::exit(64);
+}
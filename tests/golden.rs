@@ -0,0 +1,232 @@
+//! Runs the scripts under `tests/lox` and checks what they print and how
+//! they exit against the expectations in their comments. The comments
+//! follow the test suite of the craftinginterpreters repository, so its
+//! files can be copied in as they are:
+//!
+//! - `// expect: 3`: the script prints the line `3`.
+//! - `// expect runtime error: message`: the script stops with `message`
+//!   on stderr, followed by a stack trace starting on this line, and exits
+//!   with 70.
+//! - `// Error at 'x': message` or `// [line 3] Error at 'x': message`:
+//!   the script doesn't compile, reporting this error for this line or for
+//!   line 3, and exits with 65.
+//!
+//! Every directory directly under `tests/lox` is a chapter, and the test
+//! prints how many scripts of each chapter pass. `LOX_TEST=functions`
+//! only runs the scripts whose path starts with `functions`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Chapters of the official suite covering features Lox doesn't have yet.
+const UNSUPPORTED: [&str; 8] = ["benchmark", "class", "constructor", "field", "inheritance", "method", "super", "this"];
+
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    /// The message and the line of the expected runtime error.
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Expectations {
+        let mut expected = Expectations::default();
+
+        for (i, line) in source.lines().enumerate() {
+            let comment = match line.find("// ") {
+                Some(start) => &line[start + 3..],
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect:") {
+                expected.output.push(output.strip_prefix(' ').unwrap_or(output).to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expected.runtime_error = Some((message.to_string(), i + 1));
+            } else if comment.starts_with("Error") {
+                expected.compile_errors.push(format!("[line {}] {}", i + 1, comment));
+            } else if let Some(error) = comment.strip_prefix("[line ").or_else(|| comment.strip_prefix("[java line ")) {
+                // Errors only the C implementation reports are left out.
+                expected.compile_errors.push(format!("[line {}", error));
+            }
+        }
+
+        expected
+    }
+
+    fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+/// The line a stack trace line like `[line 3] in foo()` is about.
+fn trace_line(line: &str) -> Option<usize> {
+    let rest = line.strip_prefix("[line ")?;
+    rest[..rest.find(']')?].parse().ok()
+}
+
+/// Runs the script at `path`, returning what didn't go as expected.
+fn run(path: &Path) -> Result<(), Vec<String>> {
+    let source = fs::read_to_string(path).map_err(|e| vec![format!("Can't read the script: {}.", e)])?;
+    let expected = Expectations::parse(&source);
+    let result = Command::new(env!("CARGO_BIN_EXE_lox")).arg(path).output()
+        .map_err(|e| vec![format!("Can't run lox: {}.", e)])?;
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let errors: Vec<&str> = stderr.lines().filter(|line| !line.is_empty()).collect();
+
+    let mut problems = Vec::new();
+    if !expected.compile_errors.is_empty() {
+        let mut actual: Vec<String> = errors.iter().map(|line| line.to_string()).collect();
+        let mut wanted = expected.compile_errors.clone();
+        actual.sort();
+        wanted.sort();
+        if actual != wanted {
+            problems.push(format!("Expected the compile errors {:?} but got {:?}.", wanted, actual));
+        }
+    } else if let Some((ref message, line)) = expected.runtime_error {
+        if errors.first() != Some(&message.as_str()) {
+            problems.push(format!("Expected the runtime error {:?} but got {:?}.", message, errors.first()));
+        }
+        match errors.get(1).and_then(|trace| trace_line(trace)) {
+            Some(actual) if actual == line => {},
+            actual => problems.push(format!("Expected a stack trace starting on line {} but got {:?}.", line, actual)),
+        }
+    } else if !errors.is_empty() {
+        problems.push(format!("Unexpected output on stderr:\n{}", stderr.trim_end()));
+    }
+
+    let output: Vec<&str> = stdout.lines().collect();
+    for (i, wanted) in expected.output.iter().enumerate() {
+        match output.get(i) {
+            Some(actual) if actual == wanted => {},
+            actual => problems.push(format!("Expected output {:?} but got {:?}.", wanted, actual)),
+        }
+    }
+    for extra in output.iter().skip(expected.output.len()) {
+        problems.push(format!("Got output {:?} when none was expected.", extra));
+    }
+
+    match result.status.code() {
+        Some(code) if code == expected.exit_code() => {},
+        code => problems.push(format!("Expected exit code {} but got {:?}.", expected.exit_code(), code)),
+    }
+
+    if problems.is_empty() {
+        Result::Ok(())
+    } else {
+        Result::Err(problems)
+    }
+}
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+/// The scripts under `tests/lox` to run, with their paths relative to it,
+/// leaving out unsupported chapters and those `LOX_TEST` filters out.
+fn selected_scripts() -> Vec<(String, PathBuf)> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("lox");
+    let filter = env::var("LOX_TEST").unwrap_or_default();
+    let mut paths = Vec::new();
+    scripts(&root, &mut paths);
+    paths.sort();
+
+    paths.into_iter()
+        .map(|path| (path.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/"), path))
+        .filter(|(relative, _)| {
+            let chapter = relative.split('/').next().unwrap();
+            !UNSUPPORTED.contains(&chapter) && relative.starts_with(&filter)
+        })
+        .collect()
+}
+
+#[test]
+fn golden_files() {
+    // Scripts passed and run, by chapter.
+    let mut chapters: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut failures = Vec::new();
+    for (relative, path) in selected_scripts() {
+        let chapter = relative.split('/').next().unwrap().to_string();
+        let counts = chapters.entry(chapter).or_insert((0, 0));
+        counts.1 += 1;
+        match run(&path) {
+            Result::Ok(()) => counts.0 += 1,
+            Result::Err(problems) => failures.push(format!("{}:\n    {}", relative, problems.join("\n    "))),
+        }
+    }
+
+    for (chapter, &(passed, run)) in &chapters {
+        println!("{:<16} {:>3}/{}", chapter, passed, run);
+    }
+    assert!(failures.is_empty(), "{} scripts failed:\n{}", failures.len(), failures.join("\n"));
+}
+
+/// Runs every script on both backends, which have to print the same, fail
+/// the same way and exit with the same code. Scripts using what only the
+/// tree-walker runs, like functions, are left out.
+#[test]
+fn backends_agree() {
+    let run = |backend: &str, path: &Path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg(backend).arg(path).output().expect("can't run lox");
+        (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned(), output.status.code())
+    };
+
+    let mut compared = 0;
+    let mut tree_only = 0;
+    let mut differences = Vec::new();
+    for (relative, path) in selected_scripts() {
+        let vm = run("--backend=vm", &path);
+        if vm.1.contains("The VM doesn't support") {
+            tree_only += 1;
+            continue;
+        }
+
+        compared += 1;
+        let tree = run("--backend=tree", &path);
+        if tree != vm {
+            differences.push(format!("{}:\n    tree-walker: {:?}\n    VM:          {:?}", relative, tree, vm));
+        }
+    }
+
+    println!("{} scripts run on both backends, {} only on the tree-walker", compared, tree_only);
+    assert!(compared > 0, "no script ran on the VM");
+    assert!(differences.is_empty(), "{} scripts ran differently:\n{}", differences.len(), differences.join("\n"));
+}
+
+/// Files lox can't read as scripts stop it with a message, not a panic.
+#[test]
+fn unreadable_scripts() {
+    let dir = env::temp_dir().join(format!("lox-unreadable-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let invalid = dir.join("invalid.lox");
+    fs::write(&invalid, b"print \"\xff\";\n").unwrap();
+
+    let run = |path: &Path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg(path).output().expect("can't run lox");
+        (String::from_utf8_lossy(&output.stderr).into_owned(), output.status.code())
+    };
+
+    assert_eq!((format!("Can't read '{}': invalid UTF-8.\n", invalid.display()), Some(65)), run(&invalid));
+    let (message, code) = run(&dir.join("missing.lox"));
+    assert!(message.starts_with("Can't read '"), "{}", message);
+    assert_eq!(Some(66), code);
+
+    fs::remove_dir_all(&dir).unwrap();
+}